@@ -5,10 +5,6 @@ extern crate std;
 use soroban_sdk::{
     contract, contractimpl, symbol_short, Address, BytesN, Env, Map, String, Symbol, Vec,
 };
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-use soroban_sdk::{contract, contractimpl, Address, BytesN, Env, Map, String};
-
-use soroban_sdk::{contract, contractimpl, symbol_short, Address, BytesN, Env, Map, String, Symbol, Vec};
 
 mod portfolio;
 mod reflector;
@@ -22,12 +18,6 @@ pub use types::*;
 #[contract]
 pub struct PortfolioRebalancer;
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-fn validate_slippage_policy_version(version: u32) -> bool {
-    version == CURRENT_SLIPPAGE_POLICY_VERSION
-}
-
-
 fn guard_ledger_timestamp(env: &Env) -> u64 {
     let current = env.ledger().timestamp();
     let last: Option<u64> = env.storage().instance().get(&DataKey::LastTimestamp);
@@ -50,13 +40,18 @@ fn guard_ledger_timestamp(env: &Env) -> u64 {
 #[contractimpl]
 impl PortfolioRebalancer {
     pub fn initialize(env: Env, admin: Address, reflector_address: Address) -> Result<(), Error> {
-        if env.storage().instance().has(&DataKey::Initialized) {
+        // An admin alone is enough to refuse, so storage written before the
+        // `Initialized` flag existed cannot be taken over either.
+        if env.storage().instance().has(&DataKey::Initialized)
+            || env.storage().instance().has(&DataKey::Admin)
+        {
             return Err(Error::AlreadyInitialized);
         }
         env.storage().instance().set(&DataKey::Admin, &admin);
         env.storage()
             .instance()
             .set(&DataKey::ReflectorAddress, &reflector_address);
+        portfolio::cache_reflector_decimals(&env, &reflector_address);
         env.storage()
             .instance()
             .set(&DataKey::EmergencyStop, &false);
@@ -68,6 +63,39 @@ impl PortfolioRebalancer {
         env.storage().instance().get(&DataKey::Admin).unwrap()
     }
 
+    pub fn propose_admin(env: Env, new_admin: Address) {
+        require_admin(&env);
+        let current_admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        env.storage()
+            .instance()
+            .set(&DataKey::PendingAdmin, &new_admin);
+        portfolio::emit_admin_proposed(&env, current_admin, new_admin);
+    }
+
+    pub fn accept_admin(env: Env) -> Result<(), Error> {
+        let pending_admin: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::PendingAdmin)
+            .ok_or(Error::NoPendingAdmin)?;
+        pending_admin.require_auth();
+        let previous_admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        env.storage()
+            .instance()
+            .set(&DataKey::Admin, &pending_admin);
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+        portfolio::emit_admin_accepted(&env, previous_admin, pending_admin);
+        Ok(())
+    }
+
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::PendingAdmin)
+    }
+
+    /// `max_price_age` sets the portfolio's price staleness limit from the
+    /// start, validated as `set_max_price_age`; `None` uses
+    /// `PRICE_MAX_AGE_SECONDS`.
+    #[allow(clippy::too_many_arguments)]
     pub fn create_portfolio(
         env: Env,
         user: Address,
@@ -76,87 +104,500 @@ impl PortfolioRebalancer {
         rebalance_threshold: u32,
         slippage_tolerance: u32,
         slippage_policy_version: u32,
+        max_price_age: Option<u64>,
     ) -> Result<u64, Error> {
+        ensure_not_paused(&env)?;
         user.require_auth();
 
-        if !portfolio::validate_allocations(&target_allocations) {
-            return Err(Error::InvalidAllocation);
-        }
-        if !validate_asset_decimals(&target_allocations, &asset_decimals) {
-            return Err(Error::InvalidAssetDecimals);
+        validate_portfolio_config(
+            &env,
+            &target_allocations,
+            &asset_decimals,
+            rebalance_threshold,
+            slippage_tolerance,
+            slippage_policy_version,
+        )?;
+        let mut portfolio = new_portfolio(
+            &env,
+            user,
+            target_allocations,
+            asset_decimals,
+            rebalance_threshold,
+            slippage_tolerance,
+            slippage_policy_version,
+        );
+        if let Some(max_price_age) = max_price_age {
+            validate_max_price_age(max_price_age)?;
+            portfolio.max_price_age = max_price_age;
         }
-        if target_allocations.len() > MAX_PORTFOLIO_ASSETS {
-            return Err(Error::TooManyAssets);
+        insert_portfolio(&env, &portfolio)
+    }
+
+    /// Creates `count` identical portfolios from one allocation template,
+    /// validated once. Assets use `DEFAULT_ASSET_DECIMALS`, and slippage is
+    /// `DEFAULT_SLIPPAGE_TOLERANCE_BPS` under the current policy version.
+    pub fn create_portfolios(
+        env: Env,
+        user: Address,
+        template: Map<Address, u32>,
+        count: u32,
+        rebalance_threshold: u32,
+    ) -> Result<Vec<u64>, Error> {
+        ensure_not_paused(&env)?;
+        user.require_auth();
+        if count == 0 || count > MAX_BATCH_PORTFOLIOS {
+            return Err(Error::InvalidBatchSize);
         }
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
 
-        if !(MIN_REBALANCE_THRESHOLD..=MAX_REBALANCE_THRESHOLD).contains(&rebalance_threshold) {
-            return Err(Error::InvalidThreshold);
+        let mut asset_decimals = Map::new(&env);
+        for (asset, _) in template.iter() {
+            asset_decimals.set(asset, DEFAULT_ASSET_DECIMALS);
         }
+        validate_portfolio_config(
+            &env,
+            &template,
+            &asset_decimals,
+            rebalance_threshold,
+            DEFAULT_SLIPPAGE_TOLERANCE_BPS,
+            CURRENT_SLIPPAGE_POLICY_VERSION,
+        )?;
+        let portfolio = new_portfolio(
+            &env,
+            user,
+            template,
+            asset_decimals,
+            rebalance_threshold,
+            DEFAULT_SLIPPAGE_TOLERANCE_BPS,
+            CURRENT_SLIPPAGE_POLICY_VERSION,
+        );
 
-        if !(MIN_SLIPPAGE_TOLERANCE_BPS..=MAX_SLIPPAGE_TOLERANCE_BPS).contains(&slippage_tolerance) {
-            return Err(Error::InvalidSlippageTolerance);
+        let mut ids = Vec::new(&env);
+        for _ in 0..count {
+            ids.push_back(insert_portfolio(&env, &portfolio)?);
         }
+        Ok(ids)
+    }
 
-        if !validate_slippage_policy_version(slippage_policy_version) {
+    /// Creates a portfolio from `(asset, weight_bps)` pairs for callers that
+    /// cannot build a `Map`. A repeated asset is rejected rather than letting
+    /// the later weight silently win. Defaults match `create_portfolios`.
+    pub fn create_portfolio_from_pairs(
+        env: Env,
+        user: Address,
+        pairs: Vec<(Address, u32)>,
+        rebalance_threshold: u32,
+    ) -> Result<u64, Error> {
+        ensure_not_paused(&env)?;
+        user.require_auth();
 
-        if !(MIN_REBALANCE_THRESHOLD..=MAX_REBALANCE_THRESHOLD).contains(&rebalance_threshold) {
-            return Err(Error::InvalidThreshold);
+        let mut target_allocations = Map::new(&env);
+        let mut asset_decimals = Map::new(&env);
+        for (asset, weight) in pairs.iter() {
+            if target_allocations.contains_key(asset.clone()) {
+                return Err(Error::DuplicateAsset);
+            }
+            target_allocations.set(asset.clone(), weight);
+            asset_decimals.set(asset, DEFAULT_ASSET_DECIMALS);
         }
+        validate_portfolio_config(
+            &env,
+            &target_allocations,
+            &asset_decimals,
+            rebalance_threshold,
+            DEFAULT_SLIPPAGE_TOLERANCE_BPS,
+            CURRENT_SLIPPAGE_POLICY_VERSION,
+        )?;
+        let portfolio = new_portfolio(
+            &env,
+            user,
+            target_allocations,
+            asset_decimals,
+            rebalance_threshold,
+            DEFAULT_SLIPPAGE_TOLERANCE_BPS,
+            CURRENT_SLIPPAGE_POLICY_VERSION,
+        );
+        insert_portfolio(&env, &portfolio)
+    }
 
-        if !(MIN_SLIPPAGE_TOLERANCE_BPS..=MAX_SLIPPAGE_TOLERANCE_BPS).contains(&slippage_tolerance)
-        {
-            return Err(Error::InvalidSlippageTolerance);
-        }
+    /// Rescales rounded weights to sum to exactly `ALLOCATION_DENOMINATOR`,
+    /// for frontends to run before `create_portfolio` or
+    /// `update_target_allocations`, which stay strict. See
+    /// `portfolio::normalize_allocations`.
+    pub fn normalize_allocations(
+        env: Env,
+        raw: Map<Address, u32>,
+    ) -> Result<Map<Address, u32>, Error> {
+        portfolio::normalize_allocations(&env, &raw)
+    }
 
-        if !portfolio::validate_slippage_policy_version(slippage_policy_version) {
+    pub fn get_portfolio(env: Env, portfolio_id: u64) -> Result<Portfolio, Error> {
+        Self::load_portfolio(&env, portfolio_id)
+    }
 
-            return Err(Error::UnsupportedSlippagePolicyVersion);
-        }
+    pub fn get_target_allocations(env: Env, portfolio_id: u64) -> Result<Map<Address, u32>, Error> {
+        Ok(Self::load_portfolio(&env, portfolio_id)?.target_allocations)
+    }
+
+    pub fn get_balances(env: Env, portfolio_id: u64) -> Result<Map<Address, i128>, Error> {
+        Ok(Self::load_portfolio(&env, portfolio_id)?.current_balances)
+    }
+
+    pub fn get_user_portfolios(env: Env, user: Address) -> Vec<u64> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UserPortfolios(user))
+            .unwrap_or(Vec::new(&env))
+    }
 
-        let portfolio_id: u64 = env
+    /// Every portfolio id ever created, oldest first, skipping `start` ids and
+    /// returning at most `limit` (capped at `MAX_PORTFOLIO_PAGE_SIZE`). Ids are
+    /// sequential, so the page is derived from the id counter without reading
+    /// any portfolio.
+    pub fn get_all_portfolio_ids(env: Env, start: u32, limit: u32) -> Vec<u64> {
+        let next_id: u64 = env
             .storage()
             .persistent()
             .get(&DataKey::NextPortfolioId)
             .unwrap_or(1);
-        let portfolio = Portfolio {
-            user: user.clone(),
-            target_allocations,
-            current_balances: Map::new(&env),
-            asset_decimals,
-            rebalance_threshold,
-            slippage_tolerance,
-            slippage_policy_version,
-            last_rebalance: guard_ledger_timestamp(&env),
-            total_value: 0,
-            is_active: true,
-            pause_reason: PauseReason::None,
-        };
+        let first = 1 + start as u64;
+        let end = next_id.min(first + limit.min(MAX_PORTFOLIO_PAGE_SIZE) as u64);
+        let mut ids = Vec::new(&env);
+        for id in first..end {
+            ids.push_back(id);
+        }
+        ids
+    }
 
-        let _estimated_footprint =
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-            // portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio)?;
+    pub fn update_target_allocations(
+        env: Env,
+        portfolio_id: u64,
+        new_allocations: Map<Address, u32>,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
 
-            portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio)?;
+        apply_target_allocations(&env, portfolio_id, &mut portfolio, &new_allocations)?;
 
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::emit_allocations_updated(&env, portfolio_id, new_allocations);
+        Ok(())
+    }
 
-        env.storage()
-            .persistent()
-            .set(&DataKey::NextPortfolioId, &(portfolio_id + 1));
-        portfolio::check_portfolio_invariants(&portfolio)?;
+    pub fn set_twap_records(env: Env, portfolio_id: u64, twap_records: u32) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if twap_records > MAX_TWAP_RECORDS {
+            return Err(Error::InvalidTwapRecords);
+        }
+        portfolio.twap_records = twap_records;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
 
-        env.storage()
-            .persistent()
-            .set(&DataKey::Portfolio(portfolio_id), &portfolio);
-        portfolio::emit_portfolio_created(&env, portfolio_id, user);
-        Ok(portfolio_id)
+    /// Sets how much of each drift a rebalance closes, from `1` bps up to
+    /// `FULL_REBALANCE_RATIO_BPS` (all the way to target).
+    pub fn set_rebalance_ratio_bps(
+        env: Env,
+        portfolio_id: u64,
+        rebalance_ratio_bps: u32,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if rebalance_ratio_bps == 0 || rebalance_ratio_bps > FULL_REBALANCE_RATIO_BPS {
+            return Err(Error::InvalidRebalanceRatio);
+        }
+        portfolio.rebalance_ratio_bps = rebalance_ratio_bps;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
     }
 
-    pub fn get_portfolio(env: Env, portfolio_id: u64) -> Portfolio {
-        env.storage()
+    /// Scales each trade with its drift instead of closing it in full: none at
+    /// `rebalance_threshold`, all of it at `drift_scaling_cap_bps`. The cap
+    /// must sit above the threshold; `0` returns to full-size trades.
+    pub fn set_drift_scaling_cap(
+        env: Env,
+        portfolio_id: u64,
+        drift_scaling_cap_bps: u32,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if drift_scaling_cap_bps != 0
+            && (drift_scaling_cap_bps <= portfolio.rebalance_threshold
+                || drift_scaling_cap_bps > ALLOCATION_DENOMINATOR)
+        {
+            return Err(Error::InvalidThreshold);
+        }
+        portfolio.drift_scaling_cap_bps = drift_scaling_cap_bps;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Tightens how old a Reflector price may be before this portfolio's
+    /// valuations treat it as stale. Capped at the contract-wide
+    /// `PRICE_MAX_AGE_SECONDS`.
+    pub fn set_max_price_age(env: Env, portfolio_id: u64, max_price_age: u64) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        validate_max_price_age(max_price_age)?;
+        portfolio.max_price_age = max_price_age;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Values the portfolio in `quote_asset` instead of the oracle's base.
+    /// The oracle must currently price the quote unless it is the base.
+    /// While quoted in anything else, the portfolio is left out of the value
+    /// total in `get_global_stats`.
+    pub fn set_quote_asset(env: Env, portfolio_id: u64, quote_asset: Asset) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let quote = portfolio::quote_price(
+            &env,
+            &ReflectorClient::new(&env, &reflector_address),
+            &quote_asset,
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        )?;
+        // Take the old valuation out under the old quote, then count the
+        // refreshed one only if the new quote is the oracle's base.
+        let previous_value = portfolio.total_value;
+        portfolio::record_value_under_management(&env, portfolio_id, -previous_value);
+        portfolio::set_quoted_in_base(&env, portfolio_id, quote.is_none());
+        portfolio.quote_asset = quote_asset;
+        refresh_total_value(&env, portfolio_id, &mut portfolio);
+        portfolio::record_value_under_management(&env, portfolio_id, previous_value);
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Leaves assets within `no_trade_band_bps` of their target untouched when
+    /// a rebalance runs. The band may not exceed `rebalance_threshold`.
+    pub fn set_no_trade_band(
+        env: Env,
+        portfolio_id: u64,
+        no_trade_band_bps: u32,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if no_trade_band_bps > portfolio.rebalance_threshold {
+            return Err(Error::InvalidTradeLimit);
+        }
+        portfolio.no_trade_band_bps = no_trade_band_bps;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Scales down trades in assets whose spot price sits more than
+    /// `max_twap_divergence_bps` away from their TWAP. `0` disables the check.
+    pub fn set_max_twap_divergence(
+        env: Env,
+        portfolio_id: u64,
+        max_twap_divergence_bps: u32,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if max_twap_divergence_bps > ALLOCATION_DENOMINATOR {
+            return Err(Error::InvalidTradeLimit);
+        }
+        portfolio.max_twap_divergence_bps = max_twap_divergence_bps;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Sets the least time between two rebalances of this portfolio, such as
+    /// a day for an at-most-daily policy. It applies on top of the
+    /// contract-wide cooldown, whichever ends later.
+    pub fn set_min_rebalance_interval(
+        env: Env,
+        portfolio_id: u64,
+        min_interval_seconds: u64,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if min_interval_seconds > MAX_REBALANCE_COOLDOWN_SECONDS {
+            return Err(Error::InvalidCooldown);
+        }
+        portfolio.min_interval_seconds = min_interval_seconds;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Holds off the first rebalance until `initial_grace_seconds` after
+    /// creation, while deposits are still arriving. The window is one-shot:
+    /// the next successful rebalance clears it.
+    pub fn set_initial_grace_period(
+        env: Env,
+        portfolio_id: u64,
+        initial_grace_seconds: u64,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if initial_grace_seconds > MAX_REBALANCE_COOLDOWN_SECONDS {
+            return Err(Error::InvalidCooldown);
+        }
+        portfolio.initial_grace_seconds = initial_grace_seconds;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Caps the value traded in any single asset per rebalance; larger moves
+    /// converge over several rebalances. `0` removes the cap.
+    pub fn set_max_trade_notional(
+        env: Env,
+        portfolio_id: u64,
+        max_trade_notional: i128,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if max_trade_notional < 0 {
+            return Err(Error::InvalidTradeLimit);
+        }
+        portfolio.max_trade_notional = max_trade_notional;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Chooses how target balances are rounded when trades are sized.
+    pub fn set_rounding_mode(
+        env: Env,
+        portfolio_id: u64,
+        rounding_mode: RoundingMode,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        portfolio.rounding_mode = rounding_mode;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Chooses who holds the portfolio's tokens. Only an empty portfolio can
+    /// switch, since existing balances would otherwise be in the wrong place.
+    pub fn set_custody_mode(
+        env: Env,
+        portfolio_id: u64,
+        custody_mode: CustodyMode,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if portfolio::portfolio_has_positive_balance(&portfolio) {
+            return Err(Error::CustodyModeLocked);
+        }
+        portfolio.custody_mode = custody_mode;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    pub fn set_rebalance_threshold(
+        env: Env,
+        portfolio_id: u64,
+        rebalance_threshold: u32,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if !portfolio::validate_rebalance_threshold(rebalance_threshold) {
+            return Err(Error::InvalidThreshold);
+        }
+        portfolio.rebalance_threshold = rebalance_threshold;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    pub fn set_min_trade_amount(
+        env: Env,
+        portfolio_id: u64,
+        asset: Address,
+        min_trade_amount: i128,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if min_trade_amount < 0 {
+            return Err(Error::InvalidAmount);
+        }
+        if !portfolio.target_allocations.contains_key(asset.clone()) {
+            return Err(Error::AssetNotSupported);
+        }
+        portfolio.min_trade_amounts.set(asset, min_trade_amount);
+        portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio)?;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Keeps at least `min_balance` of a target asset through rebalances, for
+    /// example XLM reserved for fees; sells that would dip below it are
+    /// shrunk. `0` removes the floor. Withdrawals are not limited.
+    pub fn set_min_balance(
+        env: Env,
+        portfolio_id: u64,
+        asset: Address,
+        min_balance: i128,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if min_balance < 0 {
+            return Err(Error::InvalidAmount);
+        }
+        if !portfolio.target_allocations.contains_key(asset.clone()) {
+            return Err(Error::AssetNotSupported);
+        }
+        if min_balance == 0 {
+            portfolio.min_balances.remove(asset);
+        } else {
+            portfolio.min_balances.set(asset, min_balance);
+        }
+        portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio)?;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    /// Charges the owner's own fee, `fee_bps` of portfolio value, on each
+    /// rebalance and pays it to `fee_collector` in `fee_asset` (the largest
+    /// holding when `None`). Separate from the contract's treasury and trade
+    /// fees; `0` turns it off.
+    pub fn set_portfolio_fee(
+        env: Env,
+        portfolio_id: u64,
+        fee_bps: u32,
+        fee_collector: Option<Address>,
+        fee_asset: Option<Address>,
+    ) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if fee_bps > MAX_PORTFOLIO_FEE_BPS || (fee_bps > 0 && fee_collector.is_none()) {
+            return Err(Error::InvalidFeeBps);
+        }
+        if let Some(asset) = &fee_asset {
+            if !portfolio.target_allocations.contains_key(asset.clone()) {
+                return Err(Error::AssetNotSupported);
+            }
+        }
+        portfolio.fee_bps = fee_bps;
+        portfolio.fee_collector = fee_collector;
+        portfolio.fee_asset = fee_asset;
+        portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio)?;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        Ok(())
+    }
+
+    pub fn bump_portfolio_ttl(env: Env, portfolio_id: u64, ledgers: u32) -> Result<(), Error> {
+        if !env
+            .storage()
             .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-            .unwrap()
+            .has(&DataKey::Portfolio(portfolio_id))
+        {
+            return Err(Error::PortfolioNotFound);
+        }
+        let extend_to = ledgers.min(env.storage().max_ttl());
+        env.storage().persistent().extend_ttl(
+            &DataKey::Portfolio(portfolio_id),
+            extend_to,
+            extend_to,
+        );
+        Ok(())
     }
 
     pub fn check_invariants(env: Env, portfolio_id: u64) -> Result<(), Error> {
@@ -171,8 +612,12 @@ impl PortfolioRebalancer {
         amount: i128,
         _memo: String,
     ) -> Result<(), Error> {
+        ensure_not_paused(&env)?;
         if amount <= 0 {
-            return Err(Error::InvalidWithdrawAmount);
+            return Err(Error::InvalidAmount);
+        }
+        if !portfolio::is_asset_allowed(&env, &asset) {
+            return Err(Error::AssetNotAllowed);
         }
 
         if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
@@ -182,9 +627,10 @@ impl PortfolioRebalancer {
         let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
         portfolio::check_portfolio_invariants(&portfolio)?;
 
-        if !portfolio.is_active {
-            return Err(Error::PortfolioPaused);
-        }
+        portfolio::ensure_portfolio_active(&portfolio)?;
+        // The token's own decimals win over whatever was declared at creation.
+        let decimals = portfolio::ensure_token(&env, &asset)?;
+        portfolio.asset_decimals.set(asset.clone(), decimals);
 
         let steward: Address = env
             .storage()
@@ -197,143 +643,755 @@ impl PortfolioRebalancer {
         portfolio
             .current_balances
             .set(asset.clone(), current_balance + amount);
+        refresh_total_value(&env, portfolio_id, &mut portfolio);
+
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::record_cost_basis(
+            &env,
+            portfolio_id,
+            &portfolio,
+            &Map::from_array(&env, [(asset.clone(), amount)]),
+        );
+        portfolio::collect_deposit(&env, &portfolio, &steward, &asset, amount);
+        portfolio::emit_asset_deposit(
+            &env,
+            portfolio_id,
+            asset.clone(),
+            amount,
+            current_balance + amount,
+        );
+        portfolio::emit_portfolio_deposit(&env, portfolio_id, asset, amount);
+        Ok(())
+    }
+
+    /// Deposits `amount` of `asset` and gives it `target_bps` of the
+    /// allocation in one step, so a new asset never sits untracked. The other
+    /// targets are scaled proportionally into the remaining basis points and
+    /// the result must pass `update_target_allocations` validation. Owner
+    /// only; the tokens come from the owner.
+    pub fn deposit_with_target(
+        env: Env,
+        portfolio_id: u64,
+        asset: Address,
+        amount: i128,
+        target_bps: u32,
+    ) -> Result<(), Error> {
+        ensure_not_paused(&env)?;
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+        if target_bps == 0 || target_bps > ALLOCATION_DENOMINATOR {
+            return Err(Error::InvalidAllocation);
+        }
+        if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
+            return Err(Error::EmergencyStop);
+        }
+
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio::check_portfolio_invariants(&portfolio)?;
+        portfolio::ensure_portfolio_active(&portfolio)?;
+        portfolio.user.require_auth();
+
+        let decimals = portfolio::ensure_token(&env, &asset)?;
+        portfolio.asset_decimals.set(asset.clone(), decimals);
+        let mut others = portfolio.target_allocations.clone();
+        others.remove(asset.clone());
+        let mut new_allocations =
+            portfolio::scale_allocations(&env, &others, ALLOCATION_DENOMINATOR - target_bps);
+        new_allocations.set(asset.clone(), target_bps);
+        apply_target_allocations(&env, portfolio_id, &mut portfolio, &new_allocations)?;
+
+        let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+        portfolio
+            .current_balances
+            .set(asset.clone(), current_balance + amount);
+        refresh_total_value(&env, portfolio_id, &mut portfolio);
+
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::record_cost_basis(
+            &env,
+            portfolio_id,
+            &portfolio,
+            &Map::from_array(&env, [(asset.clone(), amount)]),
+        );
+        portfolio::collect_deposit(&env, &portfolio, &portfolio.user, &asset, amount);
+        portfolio::emit_asset_deposit(
+            &env,
+            portfolio_id,
+            asset.clone(),
+            amount,
+            current_balance + amount,
+        );
+        portfolio::emit_portfolio_deposit(&env, portfolio_id, asset, amount);
+        portfolio::emit_allocations_updated(&env, portfolio_id, new_allocations);
+        Ok(())
+    }
+
+    pub fn deposit_many(
+        env: Env,
+        portfolio_id: u64,
+        deposits: Map<Address, i128>,
+    ) -> Result<(), Error> {
+        ensure_not_paused(&env)?;
+        if deposits.is_empty() {
+            return Err(Error::InvalidAmount);
+        }
+        for (_, amount) in deposits.iter() {
+            if amount <= 0 {
+                return Err(Error::InvalidAmount);
+            }
+        }
+        portfolio::ensure_assets_allowed(&env, &deposits.keys())?;
+
+        if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
+            return Err(Error::EmergencyStop);
+        }
+
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio::check_portfolio_invariants(&portfolio)?;
+
+        portfolio::ensure_portfolio_active(&portfolio)?;
+        for asset in deposits.keys().iter() {
+            let decimals = portfolio::ensure_token(&env, &asset)?;
+            portfolio.asset_decimals.set(asset, decimals);
+        }
+
+        let steward: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Steward(portfolio_id))
+            .unwrap_or(portfolio.user.clone());
+        steward.require_auth();
+
+        for (asset, amount) in deposits.iter() {
+            let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+            portfolio
+                .current_balances
+                .set(asset, current_balance + amount);
+        }
+        refresh_total_value(&env, portfolio_id, &mut portfolio);
+
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::record_cost_basis(&env, portfolio_id, &portfolio, &deposits);
+        for (asset, amount) in deposits.iter() {
+            portfolio::collect_deposit(&env, &portfolio, &steward, &asset, amount);
+            let balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+            portfolio::emit_asset_deposit(&env, portfolio_id, asset.clone(), amount, balance);
+            portfolio::emit_portfolio_deposit(&env, portfolio_id, asset, amount);
+        }
+        Ok(())
+    }
+
+    /// Deposit-weighted average entry price of `asset`, or `None` if no
+    /// priced deposit of it has been made. An approximation for reporting;
+    /// see `CostBasis`.
+    pub fn get_cost_basis(env: Env, portfolio_id: u64, asset: Address) -> Option<CostBasis> {
+        let bases: Map<Address, CostBasis> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::CostBasis(portfolio_id))?;
+        bases.get(asset)
+    }
+
+    pub fn withdraw(
+        env: Env,
+        portfolio_id: u64,
+        asset: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        ensure_not_paused(&env)?;
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+
+        if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
+            return Err(Error::EmergencyStop);
+        }
+
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        portfolio::check_portfolio_invariants(&portfolio)?;
+
+        let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+        if current_balance < amount {
+            return Err(Error::InsufficientBalance);
+        }
+
+        let new_balance = current_balance - amount;
+        if new_balance == 0 {
+            portfolio.current_balances.remove(asset.clone());
+        } else {
+            portfolio.current_balances.set(asset.clone(), new_balance);
+        }
+
+        if !portfolio::portfolio_has_positive_balance(&portfolio) {
+            portfolio.is_active = false;
+        }
+        refresh_total_value(&env, portfolio_id, &mut portfolio);
+
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::release_to_owner(&env, &portfolio, &asset, amount);
+        portfolio::emit_asset_withdraw(&env, portfolio_id, asset.clone(), amount, new_balance);
+        portfolio::emit_portfolio_withdraw(&env, portfolio_id, asset, amount);
+        Ok(())
+    }
+
+    /// Returns the whole balance of an asset the portfolio holds but does not
+    /// target, which rebalancing would otherwise never touch. The tokens
+    /// move from custody back to the owner.
+    pub fn sweep_untracked(env: Env, portfolio_id: u64, asset: Address) -> Result<i128, Error> {
+        ensure_not_paused(&env)?;
+        if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
+            return Err(Error::EmergencyStop);
+        }
+
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if portfolio.target_allocations.contains_key(asset.clone()) {
+            return Err(Error::AssetNotAllowed);
+        }
+
+        let amount = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+        if amount <= 0 {
+            return Err(Error::InsufficientBalance);
+        }
+        portfolio.current_balances.remove(asset.clone());
+
+        if !portfolio::portfolio_has_positive_balance(&portfolio) {
+            portfolio.is_active = false;
+        }
+        refresh_total_value(&env, portfolio_id, &mut portfolio);
+
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::release_to_owner(&env, &portfolio, &asset, amount);
+        portfolio::emit_asset_withdraw(&env, portfolio_id, asset.clone(), amount, 0);
+        portfolio::emit_portfolio_withdraw(&env, portfolio_id, asset, amount);
+        Ok(amount)
+    }
+
+    /// Exits a portfolio in one call: every held balance moves from custody
+    /// back to the owner, the portfolio is deactivated, and it drops out of
+    /// `get_user_portfolios`. Returns the amounts sent per asset.
+    pub fn close_portfolio(env: Env, portfolio_id: u64) -> Result<Map<Address, i128>, Error> {
+        ensure_not_paused(&env)?;
+        if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
+            return Err(Error::EmergencyStop);
+        }
+
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+
+        let returned = portfolio.current_balances.clone();
+        portfolio.current_balances = Map::new(&env);
+        portfolio::record_value_under_management(&env, portfolio_id, -portfolio.total_value);
+        portfolio.total_value = 0;
+        portfolio.is_active = false;
+        portfolio.pause_reason = PauseReason::None;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        if portfolio::remove_user_portfolio(&env, &portfolio.user, portfolio_id) {
+            portfolio::record_portfolio_opened(&env, false);
+        }
+
+        for (asset, amount) in returned.iter() {
+            if amount > 0 {
+                portfolio::release_to_owner(&env, &portfolio, &asset, amount);
+            }
+        }
+        portfolio::emit_portfolio_closed(&env, portfolio_id, returned.clone());
+        Ok(returned)
+    }
+
+    pub fn check_rebalance_needed(env: Env, portfolio_id: u64) -> Result<bool, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let needed = drift_exceeds_threshold(&env, &portfolio);
+        match needed {
+            Ok(true) => portfolio::notify(
+                &env,
+                portfolio_id,
+                &portfolio.user,
+                NOTIFY_DRIFT_EXCEEDED,
+                symbol_short!("drift"),
+            ),
+            Err(Error::StaleData) => portfolio::notify(
+                &env,
+                portfolio_id,
+                &portfolio.user,
+                NOTIFY_STALE_DATA,
+                symbol_short!("stale"),
+            ),
+            _ => {}
+        }
+        needed
+    }
+
+    /// Explains the `check_rebalance_needed` answer: pause and emergency
+    /// stop win over cooldown, which wins over stale prices and drift.
+    pub fn rebalance_status(env: Env, portfolio_id: u64) -> Result<RebalanceStatus, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+
+        if Self::is_paused(env.clone())
+            || Self::is_emergency_stopped(env.clone())
+            || portfolio::ensure_portfolio_active(&portfolio).is_err()
+        {
+            return Ok(RebalanceStatus::Paused);
+        }
+
+        if env.ledger().timestamp() < rebalance_eligible_at(&env, &portfolio) {
+            return Ok(RebalanceStatus::Cooldown);
+        }
+
+        match drift_exceeds_threshold(&env, &portfolio) {
+            Ok(true) => Ok(RebalanceStatus::Needed),
+            Ok(false) => Ok(RebalanceStatus::WithinThreshold),
+            Err(_) => Ok(RebalanceStatus::Stale),
+        }
+    }
+
+    /// Ledger timestamp of the last rebalance, or of creation if the
+    /// portfolio has never been rebalanced.
+    pub fn get_last_rebalance(env: Env, portfolio_id: u64) -> Result<u64, Error> {
+        Ok(Self::load_portfolio(&env, portfolio_id)?.last_rebalance)
+    }
+
+    /// Seconds elapsed between `get_last_rebalance` and the current ledger
+    /// timestamp.
+    pub fn seconds_since_last_rebalance(env: Env, portfolio_id: u64) -> Result<u64, Error> {
+        let last_rebalance = Self::load_portfolio(&env, portfolio_id)?.last_rebalance;
+        Ok(env.ledger().timestamp().saturating_sub(last_rebalance))
+    }
+
+    /// Ledger timestamp at which both the rebalance cooldown and the
+    /// portfolio's minimum interval have elapsed; at or after it
+    /// `execute_rebalance` no longer fails with `CooldownActive`.
+    pub fn next_rebalance_eligible_at(env: Env, portfolio_id: u64) -> Result<u64, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        Ok(rebalance_eligible_at(&env, &portfolio))
+    }
+
+    /// True only when a keeper transaction would find the cooldown expired and
+    /// drift past threshold, i.e. `rebalance_status` is `Needed`.
+    pub fn can_rebalance_now(env: Env, portfolio_id: u64) -> Result<bool, Error> {
+        Ok(Self::rebalance_status(env, portfolio_id)? == RebalanceStatus::Needed)
+    }
+
+    pub fn execute_rebalance(
+        env: Env,
+        portfolio_id: u64,
+        actual_balances: Map<Address, i128>,
+    ) -> Result<RebalanceResult, Error> {
+        Self::execute_rebalance_internal(&env, portfolio_id, actual_balances, false, None, None)
+    }
+
+    /// Runs the same checks and trades as `execute_rebalance`, authorized by
+    /// the portfolio's keeper instead of its owner.
+    pub fn keeper_rebalance(
+        env: Env,
+        portfolio_id: u64,
+        actual_balances: Map<Address, i128>,
+    ) -> Result<RebalanceResult, Error> {
+        let keeper = Self::get_keeper(env.clone(), portfolio_id).ok_or(Error::NoKeeper)?;
+        Self::execute_rebalance_internal(
+            &env,
+            portfolio_id,
+            actual_balances,
+            false,
+            None,
+            Some(keeper),
+        )
+    }
+
+    pub fn set_keeper(env: Env, portfolio_id: u64, keeper: Address) -> Result<(), Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        env.storage()
+            .persistent()
+            .set(&DataKey::Keeper(portfolio_id), &keeper);
+        Ok(())
+    }
+
+    pub fn revoke_keeper(env: Env, portfolio_id: u64) -> Result<(), Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        env.storage()
+            .persistent()
+            .remove(&DataKey::Keeper(portfolio_id));
+        Ok(())
+    }
+
+    pub fn get_keeper(env: Env, portfolio_id: u64) -> Option<Address> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Keeper(portfolio_id))
+    }
+
+    /// Chooses which `("notify", ..)` events the portfolio emits, as a mask
+    /// of the `NOTIFY_*` bits; `0` turns them all off.
+    pub fn set_notify_prefs(env: Env, portfolio_id: u64, prefs: u32) -> Result<(), Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if prefs & !NOTIFY_ALL != 0 {
+            return Err(Error::InvalidNotifyPrefs);
+        }
+        let key = DataKey::NotifyPrefs(portfolio_id);
+        if prefs == 0 {
+            env.storage().persistent().remove(&key);
+        } else {
+            env.storage().persistent().set(&key, &prefs);
+        }
+        Ok(())
+    }
+
+    pub fn get_notify_prefs(env: Env, portfolio_id: u64) -> u32 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::NotifyPrefs(portfolio_id))
+            .unwrap_or(0)
+    }
+
+    /// Emergency rebalance that ignores the cooldown and minimum interval.
+    /// Staleness, slippage and pause checks still apply.
+    pub fn admin_force_rebalance(
+        env: Env,
+        portfolio_id: u64,
+        actual_balances: Map<Address, i128>,
+    ) -> Result<RebalanceResult, Error> {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+        Self::execute_rebalance_internal(
+            &env,
+            portfolio_id,
+            actual_balances,
+            true,
+            Some(admin),
+            None,
+        )
+    }
+
+    pub fn set_emergency_stop(env: Env, stop: bool) {
+        require_admin(&env);
+        env.storage().instance().set(&DataKey::EmergencyStop, &stop);
+        let reason = if stop {
+            PauseReason::AdminEmergency
+        } else {
+            PauseReason::None
+        };
+        env.storage()
+            .instance()
+            .set(&DataKey::ContractPauseReason, &reason);
+    }
+
+    /// Pins the numeraire new portfolios are valued in, in place of the
+    /// oracle's `base()`. The oracle must price it unless it is the base;
+    /// `None` clears the override. Existing portfolios keep their
+    /// `quote_asset`.
+    pub fn set_base_asset_override(env: Env, base_asset: Option<Asset>) -> Result<(), Error> {
+        require_admin(&env);
+        match base_asset {
+            Some(asset) => {
+                let reflector_address: Address = env
+                    .storage()
+                    .instance()
+                    .get(&DataKey::ReflectorAddress)
+                    .unwrap();
+                portfolio::quote_price(
+                    &env,
+                    &ReflectorClient::new(&env, &reflector_address),
+                    &asset,
+                    0,
+                    PRICE_MAX_AGE_SECONDS,
+                )?;
+                env.storage()
+                    .instance()
+                    .set(&DataKey::BaseAssetOverride, &asset);
+            }
+            None => env.storage().instance().remove(&DataKey::BaseAssetOverride),
+        }
+        Ok(())
+    }
+
+    pub fn get_base_asset_override(env: Env) -> Option<Asset> {
+        env.storage().instance().get(&DataKey::BaseAssetOverride)
+    }
+
+    /// Sets the fewest assets a portfolio's allocations may hold, from `1` up
+    /// to `MAX_PORTFOLIO_ASSETS`. Existing portfolios are not re-checked.
+    pub fn set_min_assets(env: Env, min_assets: u32) -> Result<(), Error> {
+        require_admin(&env);
+        if min_assets == 0 || min_assets > MAX_PORTFOLIO_ASSETS {
+            return Err(Error::InvalidMinAssets);
+        }
+        env.storage()
+            .instance()
+            .set(&DataKey::MinAssets, &min_assets);
+        Ok(())
+    }
+
+    pub fn get_min_assets(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MinAssets)
+            .unwrap_or(DEFAULT_MIN_PORTFOLIO_ASSETS)
+    }
+
+    /// Freezes every state-changing portfolio call until `unpause`; reads stay
+    /// available.
+    pub fn pause(env: Env) {
+        require_admin(&env);
+        env.storage().instance().set(&DataKey::Paused, &true);
+    }
+
+    pub fn unpause(env: Env) {
+        require_admin(&env);
+        env.storage().instance().set(&DataKey::Paused, &false);
+    }
+
+    pub fn is_paused(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::Paused)
+            .unwrap_or(false)
+    }
+
+    pub fn set_rebalance_cooldown(env: Env, cooldown_seconds: u64) -> Result<(), Error> {
+        require_admin(&env);
+        if cooldown_seconds > MAX_REBALANCE_COOLDOWN_SECONDS {
+            return Err(Error::InvalidCooldown);
+        }
+        env.storage()
+            .instance()
+            .set(&DataKey::RebalanceCooldown, &cooldown_seconds);
+        Ok(())
+    }
+
+    pub fn get_rebalance_cooldown(env: Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::RebalanceCooldown)
+            .unwrap_or(REBALANCE_COOLDOWN_SECONDS)
+    }
 
+    pub fn set_max_drift_bps(env: Env, max_drift_bps: u32) -> Result<(), Error> {
+        require_admin(&env);
+        if max_drift_bps == 0 || max_drift_bps > ALLOCATION_DENOMINATOR {
+            return Err(Error::InvalidMaxDrift);
+        }
         env.storage()
-            .persistent()
-            .set(&DataKey::Portfolio(portfolio_id), &portfolio);
-        portfolio::emit_portfolio_deposit(&env, portfolio_id, asset, amount);
+            .instance()
+            .set(&DataKey::MaxDriftBps, &max_drift_bps);
         Ok(())
     }
 
-    pub fn withdraw(
-        env: Env,
-        portfolio_id: u64,
-        asset: Address,
-        amount: i128,
-    ) -> Result<(), Error> {
-        if amount <= 0 {
-            return Err(Error::InvalidWithdrawAmount);
-        }
+    pub fn get_max_drift_bps(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MaxDriftBps)
+            .unwrap_or(DEFAULT_MAX_DRIFT_BPS)
+    }
 
-        if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
-            return Err(Error::EmergencyStop);
+    /// Sets the portfolio value below which `execute_rebalance` declines to
+    /// trade; `0` disables the floor.
+    pub fn set_min_rebalance_value(env: Env, min_rebalance_value: i128) -> Result<(), Error> {
+        require_admin(&env);
+        if min_rebalance_value < 0 {
+            return Err(Error::InvalidAmount);
         }
+        env.storage()
+            .instance()
+            .set(&DataKey::MinRebalanceValue, &min_rebalance_value);
+        Ok(())
+    }
 
-        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
-        portfolio.user.require_auth();
-        portfolio::check_portfolio_invariants(&portfolio)?;
+    pub fn get_min_rebalance_value(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MinRebalanceValue)
+            .unwrap_or(DEFAULT_MIN_REBALANCE_VALUE)
+    }
 
-        let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
-        if current_balance < amount {
-            return Err(Error::InsufficientBalance);
+    /// Sets the inclusive range every Reflector price, spot or TWAP, must fall
+    /// in to be valued or traded on, as a guard against oracle bugs.
+    pub fn set_price_bounds(env: Env, min_price: i128, max_price: i128) -> Result<(), Error> {
+        require_admin(&env);
+        if min_price < DEFAULT_MIN_SANE_PRICE || max_price < min_price {
+            return Err(Error::InvalidPriceBounds);
         }
+        env.storage()
+            .instance()
+            .set(&DataKey::PriceBounds, &(min_price, max_price));
+        Ok(())
+    }
 
-        let new_balance = current_balance - amount;
-        if new_balance == 0 {
-            portfolio.current_balances.remove(asset.clone());
-        } else {
-            portfolio.current_balances.set(asset.clone(), new_balance);
-        }
+    pub fn get_price_bounds(env: Env) -> (i128, i128) {
+        portfolio::price_bounds(&env)
+    }
 
-        if !portfolio::portfolio_has_positive_balance(&portfolio) {
-            portfolio.is_active = false;
-        }
+    pub fn add_allowed_asset(env: Env, asset: Address) {
+        require_admin(&env);
+        let mut whitelist = Self::asset_whitelist(&env);
+        whitelist.set(asset, true);
+        env.storage()
+            .instance()
+            .set(&DataKey::AssetWhitelist, &whitelist);
+    }
 
+    pub fn remove_allowed_asset(env: Env, asset: Address) {
+        require_admin(&env);
+        let mut whitelist = Self::asset_whitelist(&env);
+        whitelist.remove(asset);
         env.storage()
-            .persistent()
-            .set(&DataKey::Portfolio(portfolio_id), &portfolio);
-        portfolio::emit_portfolio_withdraw(&env, portfolio_id, asset, amount);
-        Ok(())
+            .instance()
+            .set(&DataKey::AssetWhitelist, &whitelist);
     }
 
-    pub fn check_rebalance_needed(env: Env, portfolio_id: u64) -> bool {
-        let portfolio: Portfolio = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-            .unwrap();
+    pub fn get_allowed_assets(env: Env) -> Vec<Address> {
+        Self::asset_whitelist(&env).keys()
+    }
 
-        let reflector_address: Address = env
+    pub fn is_asset_allowed(env: Env, asset: Address) -> bool {
+        portfolio::is_asset_allowed(&env, &asset)
+    }
+
+    pub fn set_reflector_address(env: Env, new_address: Address) {
+        require_admin(&env);
+        let old_address: Address = env
             .storage()
             .instance()
             .get(&DataKey::ReflectorAddress)
             .unwrap();
-        let reflector_client = ReflectorClient::new(&env, &reflector_address);
-
-        let total_value = match portfolio::calculate_portfolio_value(
-            &env,
-            &portfolio.current_balances,
-            &portfolio.asset_decimals,
-            &reflector_client,
-        ) {
-            Ok(val) => val,
-            Err(_) => return false,
-        };
+        env.storage()
+            .instance()
+            .set(&DataKey::ReflectorAddress, &new_address);
+        portfolio::cache_reflector_decimals(&env, &new_address);
+        portfolio::emit_reflector_rotated(&env, old_address, new_address);
+    }
 
-        if total_value == 0 {
-            return false;
+    /// Sets the Reflector every price lookup falls back to, asset by asset,
+    /// when the primary cannot price it: valuation, trade sizing, fees and
+    /// drift checks alike. `None` removes the fallback.
+    pub fn set_secondary_reflector(env: Env, address: Option<Address>) {
+        require_admin(&env);
+        match address {
+            Some(address) => env
+                .storage()
+                .instance()
+                .set(&DataKey::SecondaryReflector, &address),
+            None => env
+                .storage()
+                .instance()
+                .remove(&DataKey::SecondaryReflector),
         }
+    }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-        let preview = portfolio::build_rebalance_preview(&env, &portfolio, &reflector_client);
-        if let Ok(p) = preview {
-            p.rebalance_needed
-        } else {
-            false
-        }
+    pub fn get_secondary_reflector(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::SecondaryReflector)
+    }
 
-        for (asset, target_pct) in portfolio.target_allocations.iter() {
-            let balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
-            if let Some(price_data) =
-                reflector_client.lastprice(&crate::reflector::Asset::Stellar(asset))
-            {
-                let asset_value = portfolio::balance_to_value(balance, price_data.price);
-                let current_pct =
-                    ((asset_value * ALLOCATION_DENOMINATOR as i128) / total_value) as u32;
-                let drift = if current_pct >= target_pct {
-                    current_pct - target_pct
-                } else {
-                    target_pct - current_pct
-                };
-                if drift > portfolio.rebalance_threshold * (ALLOCATION_DENOMINATOR / 100) {
-                    return true;
+    /// Routes swaps from `from` to `to` through `via`, for pairs with no
+    /// direct market. `None` restores the direct swap.
+    pub fn set_route_hint(
+        env: Env,
+        from: Address,
+        to: Address,
+        via: Option<Address>,
+    ) -> Result<(), Error> {
+        require_admin(&env);
+        let mut routes: Map<(Address, Address), Address> = env
+            .storage()
+            .instance()
+            .get(&DataKey::RouteHint)
+            .unwrap_or(Map::new(&env));
+        match via {
+            Some(via) => {
+                if from == to || via == from || via == to {
+                    return Err(Error::DuplicateAsset);
                 }
+                routes.set((from, to), via);
+            }
+            None => {
+                routes.remove((from, to));
             }
         }
+        if routes.is_empty() {
+            env.storage().instance().remove(&DataKey::RouteHint);
+        } else {
+            env.storage().instance().set(&DataKey::RouteHint, &routes);
+        }
+        Ok(())
+    }
+
+    pub fn get_route_hint(env: Env, from: Address, to: Address) -> Option<Address> {
+        let routes: Map<(Address, Address), Address> =
+            env.storage().instance().get(&DataKey::RouteHint)?;
+        routes.get((from, to))
+    }
 
-        false
+    /// Whether the configured Reflector answers `base()`. A missing address,
+    /// an address with no contract behind it, or a failing call is unhealthy.
+    pub fn oracle_health(env: Env) -> bool {
+        let reflector_address: Option<Address> =
+            env.storage().instance().get(&DataKey::ReflectorAddress);
+        match reflector_address {
+            Some(address) => matches!(ReflectorClient::new(&env, &address).try_base(), Ok(Ok(_))),
+            None => false,
+        }
+    }
 
+    /// True when every target asset's latest Reflector tick is at most
+    /// `max_age` seconds old; a missing price counts as stale. One cheap call
+    /// for a freshness indicator before a manual rebalance.
+    pub fn prices_fresh(env: Env, portfolio_id: u64, max_age: u64) -> Result<bool, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let reflector_client = ReflectorClient::new(&env, &reflector_address);
+        let current_time = env.ledger().timestamp();
+        for (asset, _) in portfolio.target_allocations.iter() {
+            match portfolio::latest_tick(
+                &env,
+                &reflector_client,
+                &crate::reflector::Asset::Stellar(asset),
+            ) {
+                Some((_, tick)) if !tick.is_stale(current_time, max_age) => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
     }
 
-    pub fn execute_rebalance(
-        env: Env,
-        portfolio_id: u64,
-        actual_balances: Map<Address, i128>,
-    ) -> Result<(), Error> {
-        Self::execute_rebalance_internal(&env, portfolio_id, actual_balances, false, None)
+    pub fn is_emergency_stopped(env: Env) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::EmergencyStop)
+            .unwrap_or(false)
     }
 
-    pub fn admin_force_rebalance(
+    /// Hands the portfolio to `new_owner`, moving it between the two owners'
+    /// `get_user_portfolios` lists. Any steward or keeper the previous owner
+    /// appointed is cleared, so only the new owner can act on it.
+    pub fn transfer_portfolio_ownership(
         env: Env,
         portfolio_id: u64,
-        actual_balances: Map<Address, i128>,
+        new_owner: Address,
     ) -> Result<(), Error> {
-        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
-        admin.require_auth();
-        Self::execute_rebalance_internal(&env, portfolio_id, actual_balances, true, Some(admin))
-    }
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let previous_owner = portfolio.user.clone();
+        previous_owner.require_auth();
 
-    pub fn set_emergency_stop(env: Env, stop: bool) {
-        require_admin(&env);
-        env.storage().instance().set(&DataKey::EmergencyStop, &stop);
-        let reason = if stop {
-            PauseReason::AdminEmergency
-        } else {
-            PauseReason::None
-        };
+        portfolio.user = new_owner.clone();
+        save_portfolio(&env, portfolio_id, &portfolio);
         env.storage()
-            .instance()
-            .set(&DataKey::ContractPauseReason, &reason);
+            .persistent()
+            .remove(&DataKey::Steward(portfolio_id));
+        env.storage()
+            .persistent()
+            .remove(&DataKey::Keeper(portfolio_id));
+        portfolio::remove_user_portfolio(&env, &previous_owner, portfolio_id);
+        portfolio::add_user_portfolio(&env, &new_owner, portfolio_id);
+        portfolio::emit_ownership_transferred(&env, portfolio_id, previous_owner, new_owner);
+        Ok(())
     }
 
     pub fn transfer_stewardship(
@@ -341,11 +1399,7 @@ impl PortfolioRebalancer {
         portfolio_id: u64,
         new_steward: Address,
     ) -> Result<(), Error> {
-        let portfolio: Portfolio = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-            .unwrap();
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
 
         let current_steward: Address = env
             .storage()
@@ -356,35 +1410,26 @@ impl PortfolioRebalancer {
         env.storage()
             .persistent()
             .set(&DataKey::Steward(portfolio_id), &new_steward);
+        // `v1` data: `(portfolio_id, previous_steward, new_steward)`.
         env.events().publish(
             (
                 symbol_short!("portfolio"),
                 Symbol::new(&env, "steward_transferred"),
+                EVENT_TOPIC_VERSION,
             ),
             (portfolio_id, current_steward, new_steward),
         );
-        env.events()
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-            .publish(("portfolio", "steward_transferred"), (portfolio_id, current_steward, new_steward));
-
-            .publish((symbol_short!("portfolio"), Symbol::new(&env, "steward_transferred")), (portfolio_id, current_steward, new_steward));
-
         Ok(())
     }
 
     pub fn get_steward(env: Env, portfolio_id: u64) -> Address {
-        let portfolio: Portfolio = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-            .unwrap();
+        let portfolio = Self::load_portfolio(&env, portfolio_id).unwrap();
         env.storage()
             .persistent()
             .get(&DataKey::Steward(portfolio_id))
             .unwrap_or(portfolio.user)
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
     pub fn version(_env: Env) -> u32 {
         CONTRACT_VERSION
     }
@@ -393,7 +1438,6 @@ impl PortfolioRebalancer {
         CONTRACT_EVENT_SCHEMA_VERSION
     }
 
-
     pub fn capabilities(_env: Env) -> u32 {
         let mut flags: u32 = 0;
         flags |= CapabilityFlag::PerPortfolioSteward as u32;
@@ -402,7 +1446,6 @@ impl PortfolioRebalancer {
         flags
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
     pub fn capability_summary(env: Env) -> ContractCapabilitySummary {
         ContractCapabilitySummary {
             version: Self::version(env.clone()),
@@ -416,7 +1459,6 @@ impl PortfolioRebalancer {
         }
     }
 
-
     pub fn set_fee_config(env: Env, config: FeeConfig) {
         let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
         admin.require_auth();
@@ -424,8 +1466,11 @@ impl PortfolioRebalancer {
             panic!("fee_bps must be between 0 and 50");
         }
         env.storage().instance().set(&DataKey::FeeConfig, &config);
-        env.events()
-            .publish(Symbol::new(&env, "FeeConfigUpdated"), config);
+        // `v1` data: `FeeConfig`.
+        env.events().publish(
+            (Symbol::new(&env, "FeeConfigUpdated"), EVENT_TOPIC_VERSION),
+            config,
+        );
     }
 
     pub fn get_fee_config(env: Env) -> FeeConfig {
@@ -440,6 +1485,30 @@ impl PortfolioRebalancer {
             })
     }
 
+    pub fn set_treasury_fee_bps(env: Env, fee_bps: u32) -> Result<(), Error> {
+        require_admin(&env);
+        if fee_bps > MAX_TREASURY_FEE_BPS {
+            return Err(Error::InvalidFeeBps);
+        }
+        env.storage().instance().set(&DataKey::FeeBps, &fee_bps);
+        Ok(())
+    }
+
+    pub fn get_treasury_fee_bps(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::FeeBps).unwrap_or(0)
+    }
+
+    pub fn set_treasury(env: Env, treasury: Address) {
+        require_admin(&env);
+        env.storage().instance().set(&DataKey::Treasury, &treasury);
+    }
+
+    pub fn get_treasury(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::Treasury)
+    }
+
+    /// Swaps the contract code for a previously uploaded WASM, admin only.
+    /// Storage is untouched, so the new code must read the existing layout.
     pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) {
         let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
         admin.require_auth();
@@ -452,8 +1521,13 @@ impl PortfolioRebalancer {
         env.storage()
             .instance()
             .set(&DataKey::WasmHash, &new_wasm_hash);
+        // `v1` data: `UpgradeEvent`.
         env.events().publish(
-            ("portfolio", "upgraded"),
+            (
+                symbol_short!("contract"),
+                symbol_short!("upgraded"),
+                EVENT_TOPIC_VERSION,
+            ),
             UpgradeEvent {
                 from_hash: current_hash.unwrap_or(BytesN::from_array(&env, &[0u8; 32])),
                 to_hash: new_wasm_hash,
@@ -462,76 +1536,281 @@ impl PortfolioRebalancer {
         );
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
     /// Returns the minimum allowed rebalance threshold percentage.
-
     pub fn min_rebalance_threshold(_env: Env) -> u32 {
         MIN_REBALANCE_THRESHOLD
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
     /// Returns the maximum allowed rebalance threshold percentage.
-
     pub fn max_rebalance_threshold(_env: Env) -> u32 {
         MAX_REBALANCE_THRESHOLD
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
     /// Returns the minimum allowed slippage tolerance in basis points.
-
     pub fn min_slippage_tolerance_bps(_env: Env) -> u32 {
         MIN_SLIPPAGE_TOLERANCE_BPS
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-    /// Returns the maximum allowed slippage tolerance in basis points.
+    /// Returns the maximum allowed slippage tolerance in basis points.
+    pub fn max_slippage_tolerance_bps(_env: Env) -> u32 {
+        MAX_SLIPPAGE_TOLERANCE_BPS
+    }
+
+    /// Returns the maximum number of assets allowed in a portfolio.
+    pub fn max_portfolio_assets(_env: Env) -> u32 {
+        MAX_PORTFOLIO_ASSETS
+    }
+
+    pub fn preview_rebalance(env: Env, portfolio_id: u64) -> RebalancePreview {
+        let portfolio = Self::load_portfolio(&env, portfolio_id).unwrap();
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let reflector_client = ReflectorClient::new(&env, &reflector_address);
+        portfolio::build_rebalance_preview(&env, &portfolio, &reflector_client).unwrap_or(
+            RebalancePreview {
+                candidate_trades: Map::new(&env),
+                skipped_assets: soroban_sdk::vec![&env],
+                skip_reasons: Map::new(&env),
+                threshold_decisions: Map::new(&env),
+                rebalance_needed: false,
+                total_value: 0,
+            },
+        )
+    }
+
+    pub fn get_rebalance_history(env: Env, portfolio_id: u64) -> Vec<RebalanceRecord> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::RebalanceHistory(portfolio_id))
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Open portfolio count and approximate value under management across
+    /// the contract. Values are folded in whenever a portfolio's `total_value` is
+    /// rewritten: create, deposit, withdraw, rebalance and close.
+    pub fn get_global_stats(env: Env) -> GlobalStats {
+        portfolio::global_stats(&env)
+    }
+
+    /// Lifetime rebalance totals; all zero until the first rebalance.
+    pub fn get_portfolio_stats(env: Env, portfolio_id: u64) -> Result<PortfolioStats, Error> {
+        Self::load_portfolio(&env, portfolio_id)?;
+        Ok(env
+            .storage()
+            .persistent()
+            .get(&DataKey::PortfolioStats(portfolio_id))
+            .unwrap_or_default())
+    }
+
+    /// Prices (in the portfolio's quote asset) that drove the rebalance at
+    /// `index` in `get_rebalance_history`, keyed by target asset.
+    pub fn get_rebalance_prices(
+        env: Env,
+        portfolio_id: u64,
+        index: u32,
+    ) -> Result<Map<Address, crate::reflector::PriceData>, Error> {
+        Self::load_portfolio(&env, portfolio_id)?;
+        let record = Self::get_rebalance_history(env.clone(), portfolio_id)
+            .get(index)
+            .ok_or(Error::RebalanceRecordNotFound)?;
+        env.storage()
+            .persistent()
+            .get(&DataKey::RebalancePrices(portfolio_id, record.seq))
+            .ok_or(Error::RebalanceRecordNotFound)
+    }
+
+    pub fn get_current_allocations(
+        env: Env,
+        portfolio_id: u64,
+    ) -> Result<Map<Address, u32>, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let reflector_client = ReflectorClient::new(&env, &reflector_address);
+        portfolio::current_allocations(&env, &portfolio, &reflector_client)
+    }
+
+    pub fn get_asset_drifts(env: Env, portfolio_id: u64) -> Result<Map<Address, i128>, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let reflector_client = ReflectorClient::new(&env, &reflector_address);
+        portfolio::asset_drifts(&env, &portfolio, &reflector_client)
+    }
+
+    /// Current total value at Reflector prices; unlike `calculate_portfolio_value`,
+    /// a held asset without a price fails with `StaleData` instead of counting
+    /// as zero. An asset the primary Reflector cannot price is priced by the
+    /// secondary, if one is set.
+    pub fn get_portfolio_value(env: Env, portfolio_id: u64) -> Result<i128, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        portfolio::value_all_priced(
+            &env,
+            &portfolio,
+            &portfolio.current_balances,
+            &ReflectorClient::new(&env, &reflector_address),
+        )
+    }
+
+    /// Value of one position, priced exactly as `get_portfolio_value` prices
+    /// it, so the values of every held asset sum to that total. Zero for an
+    /// asset the portfolio holds none of.
+    pub fn get_asset_value(env: Env, portfolio_id: u64, asset: Address) -> Result<i128, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+        if balance == 0 {
+            return Ok(0);
+        }
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let mut position = Map::new(&env);
+        position.set(asset, balance);
+        portfolio::value_all_priced(
+            &env,
+            &portfolio,
+            &position,
+            &ReflectorClient::new(&env, &reflector_address),
+        )
+    }
+
+    /// Returns the exact trade map `execute_rebalance` would settle right now,
+    /// priced through the same path. Read-only; requires no authorization.
+    pub fn preview_rebalance_trades(
+        env: Env,
+        portfolio_id: u64,
+    ) -> Result<Map<Address, i128>, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let reflector_client = ReflectorClient::new(&env, &reflector_address);
+        let (trades, _, _) = portfolio::plan_rebalance_trades(&env, &portfolio, &reflector_client)?;
+        Ok(trades)
+    }
 
-    pub fn max_slippage_tolerance_bps(_env: Env) -> u32 {
-        MAX_SLIPPAGE_TOLERANCE_BPS
+    /// Trades that would convert the whole portfolio into `target_asset` at
+    /// current prices: every other holding sold in full and the proceeds
+    /// bought as `target_asset`. Read-only.
+    pub fn preview_liquidation(
+        env: Env,
+        portfolio_id: u64,
+        target_asset: Address,
+    ) -> Result<Map<Address, i128>, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let reflector_client = ReflectorClient::new(&env, &reflector_address);
+        portfolio::plan_liquidation(&env, &portfolio, &reflector_client, &target_asset)
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-    /// Returns the maximum number of assets allowed in a portfolio.
+    /// Target assets the portfolio holds no balance entry for. Rebalancing
+    /// sizes them from a zero balance; this lets a frontend flag them first.
+    pub fn get_unfunded_targets(env: Env, portfolio_id: u64) -> Result<Vec<Address>, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        Ok(portfolio::unfunded_targets(&env, &portfolio))
+    }
 
-    pub fn max_portfolio_assets(_env: Env) -> u32 {
-        MAX_PORTFOLIO_ASSETS
+    /// Number of trades `execute_rebalance` would settle right now, i.e. the
+    /// planned deltas above each asset's dust threshold. A cheap proxy for the
+    /// resources a keeper should budget. Read-only.
+    pub fn rebalance_trade_count(env: Env, portfolio_id: u64) -> Result<u32, Error> {
+        Ok(Self::preview_rebalance_trades(env, portfolio_id)?.len())
     }
 
-    pub fn preview_rebalance(env: Env, portfolio_id: u64) -> RebalancePreview {
-        let portfolio: Portfolio = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-            .unwrap();
+    /// Applies the trades `preview_rebalance_trades` would return to a copy of
+    /// the balances and reports the resulting basis-point allocations, so the
+    /// rounding left behind by trade sizing is visible. Does not mutate storage.
+    pub fn simulate_rebalance(env: Env, portfolio_id: u64) -> Result<Map<Address, u32>, Error> {
+        let portfolio = Self::load_portfolio(&env, portfolio_id)?;
         let reflector_address: Address = env
             .storage()
             .instance()
             .get(&DataKey::ReflectorAddress)
             .unwrap();
         let reflector_client = ReflectorClient::new(&env, &reflector_address);
-        portfolio::build_rebalance_preview(&env, &portfolio, &reflector_client).unwrap_or(
-            RebalancePreview {
-                candidate_trades: Map::new(&env),
-                skipped_assets: soroban_sdk::vec![&env],
-                skip_reasons: Map::new(&env),
-                threshold_decisions: Map::new(&env),
-                rebalance_needed: false,
-                total_value: 0,
-            },
-        )
+        let (trades, _, _) = portfolio::plan_rebalance_trades(&env, &portfolio, &reflector_client)?;
+
+        let mut simulated = portfolio.clone();
+        for (asset, amount) in trades.iter() {
+            let current = simulated.current_balances.get(asset.clone()).unwrap_or(0);
+            simulated.current_balances.set(asset, current + amount);
+        }
+        portfolio::current_allocations(&env, &simulated, &reflector_client)
+    }
+
+    pub fn deactivate_portfolio(env: Env, portfolio_id: u64) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        portfolio.is_active = false;
+        portfolio.pause_reason = PauseReason::None;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        if portfolio::remove_user_portfolio(&env, &portfolio.user, portfolio_id) {
+            portfolio::record_portfolio_opened(&env, false);
+        }
+        Ok(())
+    }
+
+    /// Admin recourse against a single harmful portfolio without pausing the
+    /// whole contract. The portfolio is marked `AdminEmergency`, which its
+    /// owner cannot lift with `reactivate_portfolio`; withdrawals still work.
+    pub fn admin_deactivate_portfolio(env: Env, portfolio_id: u64) -> Result<(), Error> {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.is_active = false;
+        portfolio.pause_reason = PauseReason::AdminEmergency;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        portfolio::emit_admin_deactivated(&env, portfolio_id, admin);
+        Ok(())
+    }
+
+    pub fn reactivate_portfolio(env: Env, portfolio_id: u64) -> Result<(), Error> {
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id)?;
+        portfolio.user.require_auth();
+        if portfolio.is_active {
+            return Ok(());
+        }
+        if portfolio.pause_reason == PauseReason::AdminEmergency {
+            return Err(Error::PortfolioPaused);
+        }
+        portfolio.is_active = true;
+        portfolio.pause_reason = PauseReason::None;
+        portfolio::check_portfolio_invariants(&portfolio)?;
+        save_portfolio(&env, portfolio_id, &portfolio);
+        if !Self::get_user_portfolios(env.clone(), portfolio.user.clone()).contains(portfolio_id) {
+            portfolio::add_user_portfolio(&env, &portfolio.user, portfolio_id);
+            portfolio::record_portfolio_opened(&env, true);
+        }
+        Ok(())
     }
 
     pub fn pause_portfolio(env: Env, portfolio_id: u64, reason: PauseReason) {
-        let mut portfolio: Portfolio = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-            .unwrap();
+        let mut portfolio = Self::load_portfolio(&env, portfolio_id).unwrap();
         portfolio.is_active = false;
         portfolio.pause_reason = reason;
-        env.storage()
-            .persistent()
-            .set(&DataKey::Portfolio(portfolio_id), &portfolio);
+        save_portfolio(&env, portfolio_id, &portfolio);
     }
 
     pub fn get_contract_pause_reason(env: Env) -> PauseReason {
@@ -541,7 +1820,21 @@ impl PortfolioRebalancer {
             .unwrap_or(PauseReason::None)
     }
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
+    /// Reads the contract-wide configuration in one call. Callable without
+    /// signing.
+    pub fn get_config(env: Env) -> ContractConfig {
+        ContractConfig {
+            admin: env.storage().instance().get(&DataKey::Admin).unwrap(),
+            reflector_address: env
+                .storage()
+                .instance()
+                .get(&DataKey::ReflectorAddress)
+                .unwrap(),
+            paused: Self::is_paused(env.clone()),
+            emergency_stop: Self::is_emergency_stopped(env.clone()),
+            fee_bps: Self::get_treasury_fee_bps(env),
+        }
+    }
 
     pub fn get_config_view(env: Env, portfolio_id: u64) -> ConfigView {
         let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
@@ -555,13 +1848,9 @@ impl PortfolioRebalancer {
             .instance()
             .get(&DataKey::EmergencyStop)
             .unwrap_or(false);
-        let portfolio: PortfolioOption = match env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(portfolio_id))
-        {
-            Some(p) => PortfolioOption::Some(p),
-            None => PortfolioOption::None,
+        let portfolio = match Self::load_portfolio(&env, portfolio_id) {
+            Ok(p) => PortfolioOption::Some(p),
+            Err(_) => PortfolioOption::None,
         };
         ConfigView {
             admin,
@@ -591,19 +1880,43 @@ impl PortfolioRebalancer {
             &portfolio.current_balances,
             &portfolio.asset_decimals,
             &reflector_client,
+            portfolio.twap_records,
+            portfolio.max_price_age,
+            &portfolio.quote_asset,
         )?;
 
         let mut assets: Vec<AssetValuation> = Vec::new(&env);
+        let price_decimals = portfolio::price_decimals(&env, &reflector_client);
+        let quote = portfolio::quote_price(
+            &env,
+            &reflector_client,
+            &portfolio.quote_asset,
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        )?;
 
         for (asset, target_pct) in portfolio.target_allocations.iter() {
             let quantity = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
-            let (oracle_price, usd_value) = if let Some(price_data) =
-                reflector_client.lastprice(&crate::reflector::Asset::Stellar(asset.clone()))
-            {
-                let val = portfolio::balance_to_value(quantity, price_data.price);
-                (price_data.price, val)
-            } else {
-                (0, 0)
+            let (oracle_price, usd_value) = match portfolio::resolve_price(
+                &env,
+                &reflector_client,
+                &crate::reflector::Asset::Stellar(asset.clone()),
+                portfolio.twap_records,
+                portfolio.max_price_age,
+            ) {
+                Ok(resolved) => {
+                    let price = portfolio::in_quote(resolved.price, quote, price_decimals);
+                    (
+                        price,
+                        portfolio::balance_to_value(
+                            quantity,
+                            price,
+                            price_decimals,
+                            portfolio::decimals_of(&portfolio.asset_decimals, &asset),
+                        ),
+                    )
+                }
+                Err(_) => (0, 0),
             };
 
             let current_pct = if total_value > 0 {
@@ -631,11 +1944,21 @@ impl PortfolioRebalancer {
         })
     }
 
-    fn load_portfolio(env: &Env, portfolio_id: u64) -> Result<Portfolio, Error> {
+    fn asset_whitelist(env: &Env) -> Map<Address, bool> {
         env.storage()
+            .instance()
+            .get(&DataKey::AssetWhitelist)
+            .unwrap_or(Map::new(env))
+    }
+
+    fn load_portfolio(env: &Env, portfolio_id: u64) -> Result<Portfolio, Error> {
+        let portfolio = env
+            .storage()
             .persistent()
             .get(&DataKey::Portfolio(portfolio_id))
-            .ok_or(Error::PortfolioNotFound)
+            .ok_or(Error::PortfolioNotFound)?;
+        extend_portfolio_ttl(env, portfolio_id);
+        Ok(portfolio)
     }
 
     fn execute_rebalance_internal(
@@ -644,40 +1967,62 @@ impl PortfolioRebalancer {
         actual_balances: Map<Address, i128>,
         bypass_cooldown: bool,
         override_admin: Option<Address>,
-    ) -> Result<(), Error> {
+        keeper: Option<Address>,
+    ) -> Result<RebalanceResult, Error> {
+        ensure_not_paused(env)?;
         if let Some(true) = env.storage().instance().get(&DataKey::EmergencyStop) {
             return Err(Error::EmergencyStop);
         }
 
         let mut portfolio = Self::load_portfolio(env, portfolio_id)?;
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-
         // Issue #861: validate allocations sum to exactly ALLOCATION_DENOMINATOR (10000 bps)
         if !portfolio::validate_allocations(&portfolio.target_allocations) {
             return Err(Error::InvalidAllocationSum);
         }
 
-
         portfolio::check_portfolio_invariants(&portfolio)?;
 
-        if !portfolio.is_active {
-            return Err(Error::PortfolioPaused);
+        portfolio::ensure_portfolio_active(&portfolio)?;
+
+        let via_allowance = keeper.is_some();
+        // A forced rebalance is authorized by the admin alone, so ops need
+        // no signature from the owner or steward.
+        match keeper {
+            Some(keeper) => keeper.require_auth(),
+            None if override_admin.is_some() => {}
+            None => {
+                let steward: Address = env
+                    .storage()
+                    .persistent()
+                    .get(&DataKey::Steward(portfolio_id))
+                    .unwrap_or(portfolio.user.clone());
+                steward.require_auth();
+            }
         }
 
-        let steward: Address = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Steward(portfolio_id))
-            .unwrap_or(portfolio.user.clone());
-        steward.require_auth();
-
         let current_time = guard_ledger_timestamp(env);
+        let cooldown_seconds = Self::get_rebalance_cooldown(env.clone());
+        if !bypass_cooldown
+            && current_time < portfolio.last_rebalance.saturating_add(cooldown_seconds)
+        {
+            return Err(Error::CooldownActive);
+        }
+        // A fixed cadence the owner opted into, independent of how often
+        // drift re-triggers.
         if !bypass_cooldown
             && current_time
                 < portfolio
                     .last_rebalance
-                    .saturating_add(REBALANCE_COOLDOWN_SECONDS)
+                    .saturating_add(portfolio.min_interval_seconds)
+        {
+            return Err(Error::CooldownActive);
+        }
+        if !bypass_cooldown
+            && current_time
+                < portfolio
+                    .last_rebalance
+                    .saturating_add(portfolio.initial_grace_seconds)
         {
             return Err(Error::CooldownActive);
         }
@@ -689,35 +2034,70 @@ impl PortfolioRebalancer {
             .unwrap();
         let reflector_client = ReflectorClient::new(env, &reflector_address);
 
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-        let mut current_prices = Map::new(&env);
-
-        let mut current_prices = Map::new(env);
+        // Dust portfolios would pay more in fees than the trades are worth.
+        let min_rebalance_value = Self::get_min_rebalance_value(env.clone());
+        if min_rebalance_value > 0
+            && portfolio::calculate_portfolio_value(
+                env,
+                &portfolio.current_balances,
+                &portfolio.asset_decimals,
+                &reflector_client,
+                portfolio.twap_records,
+                portfolio.max_price_age,
+                &portfolio.quote_asset,
+            )
+            .map_err(|_| Error::StaleData)?
+                < min_rebalance_value
+        {
+            return Err(Error::RebalanceNotNeeded);
+        }
 
-        for (asset, _) in portfolio.target_allocations.iter() {
-            if let Some(price_data) =
-                reflector_client.lastprice(&crate::reflector::Asset::Stellar(asset.clone()))
-            {
-                current_prices.set(asset.clone(), price_data.price);
-            } else {
-                return Err(Error::MissingPrice);
+        let treasury_fee_bps = Self::get_treasury_fee_bps(env.clone());
+        let mut treasury_fee = None;
+        if treasury_fee_bps > 0 {
+            if let Some(treasury) = Self::get_treasury(env.clone()) {
+                if let Some((fee_asset, fee_amount)) = portfolio::charge_fee(
+                    env,
+                    &mut portfolio,
+                    &reflector_client,
+                    treasury_fee_bps,
+                    None,
+                )? {
+                    treasury_fee = Some((treasury, fee_asset, fee_amount));
+                }
+            }
+        }
+        let mut portfolio_fee = None;
+        let portfolio_fee_bps = portfolio.fee_bps;
+        if portfolio_fee_bps > 0 {
+            if let Some(collector) = portfolio.fee_collector.clone() {
+                let fee_asset = portfolio.fee_asset.clone();
+                if let Some((fee_asset, fee_amount)) = portfolio::charge_fee(
+                    env,
+                    &mut portfolio,
+                    &reflector_client,
+                    portfolio_fee_bps,
+                    fee_asset.as_ref(),
+                )? {
+                    portfolio_fee = Some((collector, fee_asset, fee_amount));
+                }
             }
         }
 
-        let total_value = match portfolio::calculate_portfolio_value(
-            env,
-            &portfolio.current_balances,
-            &portfolio.asset_decimals,
-            &reflector_client,
-        ) {
-            Ok(v) => v,
-            Err(_) => return Err(Error::StaleData),
-        };
-
-        let mut snapshot = portfolio.clone();
-        snapshot.total_value = total_value;
+        let unfunded = portfolio::unfunded_targets(env, &portfolio);
+        let (trades, prices, downsized_assets) =
+            portfolio::plan_rebalance_trades(env, &portfolio, &reflector_client)?;
+        if trades.is_empty() {
+            return Err(Error::RebalanceNotNeeded);
+        }
 
-        let trades = portfolio::calculate_rebalance_trades(env, &snapshot, &current_prices);
+        let drifts = portfolio::asset_drifts(env, &portfolio, &reflector_client)?;
+        // A forced rebalance is the human review the drift breaker asks for.
+        if override_admin.is_none()
+            && portfolio::largest_drift(&drifts) > Self::get_max_drift_bps(env.clone())
+        {
+            return Err(Error::ExcessiveDrift);
+        }
 
         let fee_config = Self::get_fee_config(env.clone());
         let effective_fee_bps = if fee_config.enabled {
@@ -726,65 +2106,36 @@ impl PortfolioRebalancer {
             0
         };
 
-        let mut has_actual_balances = false;
-        for (_, _) in actual_balances.iter() {
-            has_actual_balances = true;
-            break;
-        }
-        if has_actual_balances {
-            let total_value = match portfolio::calculate_portfolio_value(
-                env,
-                &portfolio.current_balances,
-                &portfolio.asset_decimals,
-                &reflector_client,
-            ) {
-                Ok(v) => v,
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-                Err(_) => return Err(Error::MissingPrice),
-
-                Err(_) => return Err(Error::StaleData),
-
-            };
-
-            if total_value > 0 {
-                for (asset, target_pct) in portfolio.target_allocations.iter() {
-                    let price_data = reflector_client
-                        .lastprice(&crate::reflector::Asset::Stellar(asset.clone()))
-                        .unwrap();
-                    let price = price_data.price;
-                    let expected_value =
-                        (total_value * target_pct as i128) / ALLOCATION_DENOMINATOR as i128;
-                    let decimals = portfolio
-                        .asset_decimals
-                        .get(asset.clone())
-                        .unwrap_or(DEFAULT_ASSET_DECIMALS);
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-                    let expected_value = (total_value * target_pct as i128) / 100;
-
-                    let expected_value = (total_value * target_pct as i128) / ALLOCATION_DENOMINATOR as i128;
-
-                    let decimals = portfolio.asset_decimals.get(asset.clone()).unwrap_or(DEFAULT_ASSET_DECIMALS);
-                    let expected_balance =
-                        portfolio::value_to_balance(expected_value, price, decimals);
-                    let actual_balance = actual_balances.get(asset.clone()).unwrap_or(0);
-                    let expected_abs = if expected_balance >= 0 {
-                        expected_balance
-                    } else {
-                        -expected_balance
-                    };
-                    if expected_abs > 0 {
-                        let diff = expected_balance - actual_balance;
-                        let diff_abs = if diff >= 0 { diff } else { -diff };
-                        let slippage_bps = (diff_abs * 10000) / expected_abs;
-                        if slippage_bps > snapshot.slippage_tolerance as i128 {
-                            return Err(Error::SlippageExceeded);
-                        }
+        // Slippage is measured against the planned trades, so assets the plan
+        // leaves alone (dust, no-trade band) are expected to stay put.
+        if !actual_balances.is_empty() {
+            for (asset, _) in portfolio.target_allocations.iter() {
+                let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+                let expected_balance = current_balance + trades.get(asset.clone()).unwrap_or(0);
+                let actual_balance = actual_balances.get(asset.clone()).unwrap_or(0);
+                let expected_abs = if expected_balance >= 0 {
+                    expected_balance
+                } else {
+                    -expected_balance
+                };
+                if expected_abs > 0 {
+                    let diff = expected_balance - actual_balance;
+                    let diff_abs = if diff >= 0 { diff } else { -diff };
+                    let slippage_bps = (diff_abs * 10000) / expected_abs;
+                    if slippage_bps > portfolio.slippage_tolerance as i128 {
+                        return Err(Error::SlippageExceeded);
                     }
                 }
             }
         }
 
-        for (asset, amount) in trades.iter() {
+        // Checks-effects-interactions: balances and `last_rebalance` are
+        // saved before any token contract is called, so a callback into this
+        // contract already sees the cooldown running.
+        let ordered_trades = portfolio::order_trades_by_drift(env, &trades, &drifts);
+        let mut executed_trades = Map::new(env);
+        let mut trade_fees = Map::new(env);
+        for (asset, amount) in ordered_trades.iter() {
             let fee_amount = if effective_fee_bps > 0 {
                 (amount.abs() * effective_fee_bps as i128) / 10000
             } else {
@@ -795,26 +2146,449 @@ impl PortfolioRebalancer {
             portfolio
                 .current_balances
                 .set(asset.clone(), current + effective_amount);
+            executed_trades.set(asset.clone(), effective_amount);
+            trade_fees.set(asset, fee_amount);
         }
-        portfolio.total_value = total_value;
         portfolio.last_rebalance = current_time;
-        env.storage()
-            .persistent()
-            .set(&DataKey::Portfolio(portfolio_id), &portfolio);
+        portfolio.initial_grace_seconds = 0;
+        save_portfolio(env, portfolio_id, &portfolio);
+
+        let custody = env.current_contract_address();
+        if let Some((treasury, fee_asset, fee_amount)) = treasury_fee {
+            portfolio::pay_from_holdings(
+                env,
+                &portfolio,
+                &treasury,
+                &fee_asset,
+                fee_amount,
+                via_allowance,
+            );
+            portfolio::emit_treasury_fee(env, portfolio_id, fee_asset, fee_amount);
+        }
+        if let Some((collector, fee_asset, fee_amount)) = portfolio_fee {
+            portfolio::pay_from_holdings(
+                env,
+                &portfolio,
+                &collector,
+                &fee_asset,
+                fee_amount,
+                via_allowance,
+            );
+            portfolio::emit_portfolio_fee(env, portfolio_id, collector, fee_asset, fee_amount);
+        }
+        // Most off-target assets settle first; a transfer that fails is
+        // rolled back on its own so the remaining trades still go through.
+        let price_decimals = portfolio::price_decimals(env, &reflector_client);
+        let mut value_traded = 0i128;
+        let mut trade_values = Map::new(env);
+        let mut failed_assets = Vec::new(env);
+        for (asset, amount) in ordered_trades.iter() {
+            let effective_amount = executed_trades.get(asset.clone()).unwrap_or(0);
+            // A self-custodied portfolio trades in the owner's own wallet, so
+            // only contract custody moves tokens for the trade itself.
+            let settled = portfolio.custody_mode == CustodyMode::SelfCustody
+                || portfolio::try_transfer_trade(
+                    env,
+                    &asset,
+                    &custody,
+                    &portfolio.user,
+                    amount,
+                    via_allowance,
+                );
+            if !settled {
+                let current = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+                portfolio
+                    .current_balances
+                    .set(asset.clone(), current - effective_amount);
+                executed_trades.remove(asset.clone());
+                portfolio::emit_trade_failed(env, portfolio_id, asset.clone(), amount);
+                failed_assets.push_back(asset);
+                continue;
+            }
+            let fee_amount = trade_fees.get(asset.clone()).unwrap_or(0);
+            if fee_amount > 0 {
+                portfolio::pay_from_holdings(
+                    env,
+                    &portfolio,
+                    &fee_config.fee_recipient,
+                    &asset,
+                    fee_amount,
+                    via_allowance,
+                );
+            }
+            let price = prices.get(asset.clone()).map(|p| p.price).unwrap_or(0);
+            let trade_value = portfolio::balance_to_value(
+                effective_amount,
+                price,
+                price_decimals,
+                portfolio::decimals_of(&portfolio.asset_decimals, &asset),
+            );
+            value_traded += trade_value.abs();
+            trade_values.set(asset.clone(), trade_value);
+            portfolio::emit_trade(env, portfolio_id, asset, effective_amount, price);
+        }
+        if executed_trades.is_empty() {
+            return Err(Error::TradeExecutionFailed);
+        }
+
+        let total_value = portfolio::calculate_portfolio_value(
+            env,
+            &portfolio.current_balances,
+            &portfolio.asset_decimals,
+            &reflector_client,
+            portfolio.twap_records,
+            portfolio.max_price_age,
+            &portfolio.quote_asset,
+        )?;
+        portfolio::record_value_under_management(
+            env,
+            portfolio_id,
+            total_value - portfolio.total_value,
+        );
+        portfolio.total_value = total_value;
+        save_portfolio(env, portfolio_id, &portfolio);
+        portfolio::record_rebalance(
+            env,
+            portfolio_id,
+            current_time,
+            total_value,
+            executed_trades.len(),
+            failed_assets,
+            prices,
+        );
+        portfolio::record_stats(
+            env,
+            portfolio_id,
+            value_traded,
+            portfolio::largest_drift(&drifts),
+        );
 
         if let Some(admin) = override_admin {
-            portfolio::emit_cooldown_override(env, portfolio_id, admin, current_time);
+            portfolio::emit_rebalance_forced(env, portfolio_id, admin, current_time);
+        }
+        if !unfunded.is_empty() {
+            portfolio::emit_unfunded_targets(env, portfolio_id, unfunded);
         }
         portfolio::emit_portfolio_rebalanced(env, portfolio_id, current_time);
-        Ok(())
+        portfolio::notify(
+            env,
+            portfolio_id,
+            &portfolio.user,
+            NOTIFY_REBALANCE_EXECUTED,
+            symbol_short!("rebalance"),
+        );
+        Ok(RebalanceResult {
+            trades: executed_trades,
+            total_value,
+            downsized_assets,
+            hops: portfolio::plan_trade_hops(env, &trade_values),
+        })
+    }
+}
+
+fn save_portfolio(env: &Env, portfolio_id: u64, portfolio: &Portfolio) {
+    env.storage()
+        .persistent()
+        .set(&DataKey::Portfolio(portfolio_id), portfolio);
+    extend_portfolio_ttl(env, portfolio_id);
+}
+
+/// Keeps a touched portfolio, and the contract instance it depends on, from
+/// being archived.
+fn extend_portfolio_ttl(env: &Env, portfolio_id: u64) {
+    env.storage().persistent().extend_ttl(
+        &DataKey::Portfolio(portfolio_id),
+        PORTFOLIO_TTL_THRESHOLD_LEDGERS,
+        PORTFOLIO_TTL_EXTEND_LEDGERS,
+    );
+    env.storage().instance().extend_ttl(
+        PORTFOLIO_TTL_THRESHOLD_LEDGERS,
+        PORTFOLIO_TTL_EXTEND_LEDGERS,
+    );
+}
+
+/// Validates `new_allocations` as `update_target_allocations` requires and
+/// installs them on `portfolio`, learning the decimals of any new asset.
+fn apply_target_allocations(
+    env: &Env,
+    portfolio_id: u64,
+    portfolio: &mut Portfolio,
+    new_allocations: &Map<Address, u32>,
+) -> Result<(), Error> {
+    if portfolio::allocations_use_percentage_scale(new_allocations) {
+        return Err(Error::AllocationsNotBasisPoints);
+    }
+    if !portfolio::validate_allocations(new_allocations) {
+        return Err(Error::InvalidAllocation);
+    }
+    if new_allocations.len() > MAX_PORTFOLIO_ASSETS {
+        return Err(Error::TooManyAssets);
+    }
+    if new_allocations.len() < PortfolioRebalancer::get_min_assets(env.clone()) {
+        return Err(Error::TooFewAssets);
+    }
+    portfolio::ensure_assets_allowed(env, &new_allocations.keys())?;
+    portfolio::ensure_price_feeds(env, &new_allocations.keys())?;
+    // A dropped asset's balance would be left untracked; it must be
+    // withdrawn first.
+    for (asset, _) in portfolio.target_allocations.iter() {
+        if !new_allocations.contains_key(asset.clone())
+            && portfolio.current_balances.get(asset).unwrap_or(0) != 0
+        {
+            return Err(Error::AssetHasBalance);
+        }
+    }
+
+    for (asset, _) in new_allocations.iter() {
+        if !portfolio.asset_decimals.contains_key(asset.clone()) {
+            let decimals = portfolio::ensure_token(env, &asset)?;
+            portfolio.asset_decimals.set(asset, decimals);
+        }
+    }
+    portfolio.target_allocations = new_allocations.clone();
+
+    portfolio::validate_portfolio_storage_footprint(env, portfolio_id, portfolio)?;
+    portfolio::check_portfolio_invariants(portfolio)
+}
+
+fn validate_max_price_age(max_price_age: u64) -> Result<(), Error> {
+    if max_price_age == 0 || max_price_age > PRICE_MAX_AGE_SECONDS {
+        return Err(Error::InvalidMaxPriceAge);
+    }
+    Ok(())
+}
+
+fn validate_portfolio_config(
+    env: &Env,
+    target_allocations: &Map<Address, u32>,
+    asset_decimals: &Map<Address, u32>,
+    rebalance_threshold: u32,
+    slippage_tolerance: u32,
+    slippage_policy_version: u32,
+) -> Result<(), Error> {
+    if portfolio::allocations_use_percentage_scale(target_allocations) {
+        return Err(Error::AllocationsNotBasisPoints);
+    }
+    if !portfolio::validate_allocations(target_allocations) {
+        return Err(Error::InvalidAllocation);
+    }
+    portfolio::ensure_assets_allowed(env, &target_allocations.keys())?;
+    if !validate_asset_decimals(target_allocations, asset_decimals) {
+        return Err(Error::InvalidAssetDecimals);
+    }
+    if target_allocations.len() > MAX_PORTFOLIO_ASSETS {
+        return Err(Error::TooManyAssets);
+    }
+    if target_allocations.len() < PortfolioRebalancer::get_min_assets(env.clone()) {
+        return Err(Error::TooFewAssets);
+    }
+    portfolio::ensure_tokens(env, &target_allocations.keys())?;
+    portfolio::ensure_price_feeds(env, &target_allocations.keys())?;
+
+    if !portfolio::validate_rebalance_threshold(rebalance_threshold) {
+        return Err(Error::InvalidThreshold);
+    }
+
+    if !(MIN_SLIPPAGE_TOLERANCE_BPS..=MAX_SLIPPAGE_TOLERANCE_BPS).contains(&slippage_tolerance) {
+        return Err(Error::InvalidSlippageTolerance);
+    }
+
+    if !portfolio::validate_slippage_policy_version(slippage_policy_version) {
+        return Err(Error::UnsupportedSlippagePolicyVersion);
+    }
+    Ok(())
+}
+
+fn new_portfolio(
+    env: &Env,
+    user: Address,
+    target_allocations: Map<Address, u32>,
+    asset_decimals: Map<Address, u32>,
+    rebalance_threshold: u32,
+    slippage_tolerance: u32,
+    slippage_policy_version: u32,
+) -> Portfolio {
+    let reflector_address: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::ReflectorAddress)
+        .unwrap();
+    Portfolio {
+        user,
+        target_allocations,
+        current_balances: Map::new(env),
+        asset_decimals,
+        rebalance_threshold,
+        slippage_tolerance,
+        slippage_policy_version,
+        last_rebalance: guard_ledger_timestamp(env),
+        total_value: 0,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: PortfolioRebalancer::get_base_asset_override(env.clone())
+            .unwrap_or_else(|| ReflectorClient::new(env, &reflector_address).base()),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
     }
 }
 
+/// Earliest timestamp the next rebalance may run: the later of the
+/// contract-wide cooldown and the portfolio's own minimum interval.
+fn rebalance_eligible_at(env: &Env, portfolio: &Portfolio) -> u64 {
+    let wait = PortfolioRebalancer::get_rebalance_cooldown(env.clone())
+        .max(portfolio.min_interval_seconds)
+        .max(portfolio.initial_grace_seconds);
+    portfolio.last_rebalance.saturating_add(wait)
+}
+
+/// Assigns the next portfolio id to `portfolio`, stores it, and indexes it
+/// under its owner.
+fn insert_portfolio(env: &Env, portfolio: &Portfolio) -> Result<u64, Error> {
+    let portfolio_id: u64 = env
+        .storage()
+        .persistent()
+        .get(&DataKey::NextPortfolioId)
+        .unwrap_or(1);
+
+    let _estimated_footprint =
+        portfolio::validate_portfolio_storage_footprint(env, portfolio_id, portfolio)?;
+
+    env.storage()
+        .persistent()
+        .set(&DataKey::NextPortfolioId, &(portfolio_id + 1));
+    portfolio::check_portfolio_invariants(portfolio)?;
+
+    save_portfolio(env, portfolio_id, portfolio);
+    portfolio::add_user_portfolio(env, &portfolio.user, portfolio_id);
+    portfolio::record_portfolio_opened(env, true);
+    // Without an override every new portfolio is quoted in the oracle's base.
+    if PortfolioRebalancer::get_base_asset_override(env.clone()).is_some() {
+        let reflector_address: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::ReflectorAddress)
+            .unwrap();
+        let in_base = portfolio.quote_asset == ReflectorClient::new(env, &reflector_address).base();
+        portfolio::set_quoted_in_base(env, portfolio_id, in_base);
+    }
+    portfolio::record_value_under_management(env, portfolio_id, portfolio.total_value);
+    portfolio::emit_portfolio_created(
+        env,
+        portfolio_id,
+        portfolio.user.clone(),
+        portfolio.rebalance_threshold,
+    );
+    Ok(portfolio_id)
+}
+
+/// Re-values `current_balances` at current Reflector prices. A stale feed
+/// leaves the previous `total_value` in place rather than failing the
+/// balance update; rebalancing re-checks freshness before trading.
+fn refresh_total_value(env: &Env, portfolio_id: u64, portfolio: &mut Portfolio) {
+    let reflector_address: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::ReflectorAddress)
+        .unwrap();
+    let reflector_client = ReflectorClient::new(env, &reflector_address);
+    if let Ok(total_value) = portfolio::calculate_portfolio_value(
+        env,
+        &portfolio.current_balances,
+        &portfolio.asset_decimals,
+        &reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
+    ) {
+        portfolio::record_value_under_management(
+            env,
+            portfolio_id,
+            total_value - portfolio.total_value,
+        );
+        portfolio.total_value = total_value;
+    }
+}
+
+/// True when any target asset has drifted past `rebalance_threshold`.
+/// Missing or stale prices surface as `StaleData`.
+fn drift_exceeds_threshold(env: &Env, portfolio: &Portfolio) -> Result<bool, Error> {
+    let reflector_address: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::ReflectorAddress)
+        .unwrap();
+    let reflector_client = ReflectorClient::new(env, &reflector_address);
+
+    let current_prices = portfolio::fetch_target_prices(
+        env,
+        &portfolio.target_allocations,
+        &reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
+    )?;
+
+    let total_value = portfolio::calculate_portfolio_value(
+        env,
+        &portfolio.current_balances,
+        &portfolio.asset_decimals,
+        &reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
+    )
+    .map_err(|_| Error::StaleData)?;
+
+    if total_value == 0 {
+        return Ok(false);
+    }
+
+    let price_decimals = portfolio::price_decimals(env, &reflector_client);
+    for (asset, target_pct) in portfolio.target_allocations.iter() {
+        let balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+        let price = current_prices.get(asset.clone()).unwrap();
+        let asset_value = portfolio::balance_to_value(
+            balance,
+            price,
+            price_decimals,
+            portfolio::decimals_of(&portfolio.asset_decimals, &asset),
+        );
+        let current_pct = ((asset_value * ALLOCATION_DENOMINATOR as i128) / total_value) as u32;
+        let drift = current_pct.abs_diff(target_pct);
+        if drift > portfolio.rebalance_threshold {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
 fn require_admin(env: &Env) {
     let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
     admin.require_auth();
 }
 
+fn ensure_not_paused(env: &Env) -> Result<(), Error> {
+    if let Some(true) = env.storage().instance().get(&DataKey::Paused) {
+        return Err(Error::ContractPaused);
+    }
+    Ok(())
+}
+
 fn validate_asset_decimals(
     allocations: &Map<Address, u32>,
     asset_decimals: &Map<Address, u32>,
@@ -1,5 +1,5 @@
 use crate::types::*;
-use soroban_sdk::{symbol_short, xdr::ToXdr, Address, Env, Map, Symbol, Vec};
+use soroban_sdk::{symbol_short, token, xdr::ToXdr, Address, Env, Map, Symbol, Vec};
 
 pub fn validate_allocations(allocations: &Map<Address, u32>) -> bool {
     if allocations.is_empty() {
@@ -8,7 +8,7 @@ pub fn validate_allocations(allocations: &Map<Address, u32>) -> bool {
 
     let mut total = 0u32;
     for (_, percentage) in allocations.iter() {
-        if percentage == 0 {
+        if percentage == 0 || percentage > ALLOCATION_DENOMINATOR {
             return false;
         }
         total = match total.checked_add(percentage) {
@@ -19,15 +19,354 @@ pub fn validate_allocations(allocations: &Map<Address, u32>) -> bool {
     total == ALLOCATION_DENOMINATOR
 }
 
-pub fn balance_to_value(balance: i128, price: i128) -> i128 {
-    (balance * price) / 10i128.pow(REFLECTOR_PRICE_DECIMALS)
+/// Detects callers still passing whole-percent weights (summing to 100)
+/// instead of basis points (summing to `ALLOCATION_DENOMINATOR`).
+pub fn allocations_use_percentage_scale(allocations: &Map<Address, u32>) -> bool {
+    let mut total = 0u32;
+    for (_, weight) in allocations.iter() {
+        total = total.saturating_add(weight);
+    }
+    !allocations.is_empty() && total == 100
+}
+
+/// Rescales weights that sum to roughly 100 (whole percent) or roughly
+/// `ALLOCATION_DENOMINATOR` (basis points) so they sum to exactly
+/// `ALLOCATION_DENOMINATOR`, e.g. frontend percentages that rounded to 99 or
+/// 101. Sums outside half to one and a half times the nearer scale are
+/// rejected rather than normalized. Rounding follows `scale_allocations`.
+pub fn normalize_allocations(
+    env: &Env,
+    raw: &Map<Address, u32>,
+) -> Result<Map<Address, u32>, Error> {
+    let mut total = 0u64;
+    for (_, weight) in raw.iter() {
+        if weight == 0 {
+            return Err(Error::InvalidAllocation);
+        }
+        total += weight as u64;
+    }
+    let nominal = if total <= 2 * 100 {
+        100
+    } else {
+        ALLOCATION_DENOMINATOR as u64
+    };
+    if raw.is_empty() || total * 2 < nominal || total * 2 > nominal * 3 {
+        return Err(Error::InvalidAllocation);
+    }
+
+    let normalized = scale_allocations(env, raw, ALLOCATION_DENOMINATOR);
+    if !validate_allocations(&normalized) {
+        return Err(Error::InvalidAllocation);
+    }
+    Ok(normalized)
+}
+
+/// Scales positive `weights` proportionally so they sum to exactly `to`.
+/// Rounding units go to the largest remainders, earlier assets first on
+/// ties. Empty weights stay empty.
+pub fn scale_allocations(env: &Env, weights: &Map<Address, u32>, to: u32) -> Map<Address, u32> {
+    let mut total = 0u64;
+    for (_, weight) in weights.iter() {
+        total += weight as u64;
+    }
+    let mut scaled_weights = Map::new(env);
+    if total == 0 {
+        return scaled_weights;
+    }
+    let mut remainders = Map::new(env);
+    let mut assigned = 0u64;
+    for (asset, weight) in weights.iter() {
+        let scaled = weight as u64 * to as u64;
+        scaled_weights.set(asset.clone(), (scaled / total) as u32);
+        remainders.set(asset, scaled % total);
+        assigned += scaled / total;
+    }
+    for _ in assigned..to as u64 {
+        let mut best: Option<(Address, u64)> = None;
+        for (asset, remainder) in remainders.iter() {
+            if best.as_ref().is_none_or(|(_, top)| remainder > *top) {
+                best = Some((asset, remainder));
+            }
+        }
+        let (asset, _) = best.unwrap();
+        scaled_weights.set(
+            asset.clone(),
+            scaled_weights.get(asset.clone()).unwrap() + 1,
+        );
+        remainders.remove(asset);
+    }
+    scaled_weights
 }
 
-pub fn value_to_balance(value: i128, price: i128, _asset_decimals: u32) -> i128 {
+/// The oracle's `decimals()`, read from `DataKey::ReflectorDecimals` when
+/// `reflector_client` is the configured primary, so valuation loops skip a
+/// cross-contract call. Any other oracle, such as the secondary fallback, is
+/// asked directly.
+pub fn price_decimals(env: &Env, reflector_client: &crate::reflector::ReflectorClient) -> u32 {
+    let primary: Option<Address> = env.storage().instance().get(&DataKey::ReflectorAddress);
+    if primary.as_ref() == Some(&reflector_client.address) {
+        if let Some(decimals) = env.storage().instance().get(&DataKey::ReflectorDecimals) {
+            return decimals;
+        }
+    }
+    reflector_client.decimals()
+}
+
+/// The configured `(min, max)` sane range for a raw Reflector price,
+/// inclusive, at the oracle's decimals.
+pub fn price_bounds(env: &Env) -> (i128, i128) {
+    env.storage()
+        .instance()
+        .get(&DataKey::PriceBounds)
+        .unwrap_or((DEFAULT_MIN_SANE_PRICE, DEFAULT_MAX_SANE_PRICE))
+}
+
+/// `StaleData` unless `price` is within `price_bounds`; an out-of-range
+/// price is treated as a broken feed rather than valued or traded on.
+pub fn check_price_bounds(env: &Env, price: i128) -> Result<(), Error> {
+    let (min_price, max_price) = price_bounds(env);
+    if price < min_price || price > max_price {
+        return Err(Error::StaleData);
+    }
+    Ok(())
+}
+
+/// Refreshes the cached decimals for a newly configured `reflector`. An
+/// oracle that cannot answer leaves no cache, so reads fall back to asking
+/// it directly.
+pub fn cache_reflector_decimals(env: &Env, reflector: &Address) {
+    match crate::reflector::ReflectorClient::new(env, reflector).try_decimals() {
+        Ok(Ok(decimals)) => env
+            .storage()
+            .instance()
+            .set(&DataKey::ReflectorDecimals, &decimals),
+        _ => env.storage().instance().remove(&DataKey::ReflectorDecimals),
+    }
+}
+
+/// `price_decimals` is the oracle's own `decimals()`, so values stay in the
+/// same quote units whatever precision the Reflector deployment reports.
+/// Values `balance` (in the asset's smallest unit) at `price` per whole
+/// token. The result is in `VALUE_DECIMALS` precision whatever the token's
+/// decimals, so values of different assets can be summed.
+pub fn balance_to_value(
+    balance: i128,
+    price: i128,
+    price_decimals: u32,
+    asset_decimals: u32,
+) -> i128 {
+    if asset_decimals <= VALUE_DECIMALS {
+        (balance * price * 10i128.pow(VALUE_DECIMALS - asset_decimals)) / 10i128.pow(price_decimals)
+    } else {
+        (balance * price) / 10i128.pow(price_decimals + asset_decimals - VALUE_DECIMALS)
+    }
+}
+
+/// Inverse of `balance_to_value`: the balance, in the asset's smallest unit,
+/// worth `value` at `price`.
+pub fn value_to_balance(
+    value: i128,
+    price: i128,
+    price_decimals: u32,
+    asset_decimals: u32,
+) -> i128 {
+    value_to_balance_rounded(
+        value,
+        price,
+        price_decimals,
+        asset_decimals,
+        RoundingMode::Truncate,
+    )
+}
+
+/// `value_to_balance` with the final division rounded according to `mode`.
+pub fn value_to_balance_rounded(
+    value: i128,
+    price: i128,
+    price_decimals: u32,
+    asset_decimals: u32,
+    mode: RoundingMode,
+) -> i128 {
     if price == 0 {
         return 0;
     }
-    (value * 10i128.pow(REFLECTOR_PRICE_DECIMALS)) / price
+    if asset_decimals <= VALUE_DECIMALS {
+        div_round(
+            value * 10i128.pow(price_decimals),
+            price * 10i128.pow(VALUE_DECIMALS - asset_decimals),
+            mode,
+        )
+    } else {
+        div_round(value * 10i128.pow(price_decimals), price, mode)
+            * 10i128.pow(asset_decimals - VALUE_DECIMALS)
+    }
+}
+
+/// `numerator / denominator` rounded according to `mode`.
+pub fn div_round(numerator: i128, denominator: i128, mode: RoundingMode) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if remainder == 0 {
+        return quotient;
+    }
+    let away = if (numerator < 0) != (denominator < 0) {
+        quotient - 1
+    } else {
+        quotient + 1
+    };
+    let twice = remainder.abs() * 2;
+    let divisor = denominator.abs();
+    match mode {
+        RoundingMode::Truncate => quotient,
+        RoundingMode::HalfUp if twice >= divisor => away,
+        RoundingMode::Nearest if twice > divisor || (twice == divisor && quotient % 2 != 0) => away,
+        _ => quotient,
+    }
+}
+
+/// Decimals recorded for `asset`, falling back to `DEFAULT_ASSET_DECIMALS`.
+pub fn decimals_of(asset_decimals: &Map<Address, u32>, asset: &Address) -> u32 {
+    asset_decimals
+        .get(asset.clone())
+        .unwrap_or(DEFAULT_ASSET_DECIMALS)
+}
+
+/// A price read through `resolve_price`.
+pub struct ResolvedPrice {
+    /// Oracle that answered: the primary, or the secondary fallback.
+    pub oracle: Address,
+    /// That oracle's latest tick, at its own decimals; its timestamp vouches
+    /// for `price`.
+    pub tick: crate::reflector::PriceData,
+    /// Price used for valuation, at the primary oracle's decimals: the TWAP
+    /// over `twap_records` records when TWAP pricing is enabled and the oracle
+    /// can provide one, otherwise the spot price.
+    pub price: i128,
+}
+
+/// Latest tick for `asset` from `reflector_client` or, when it has none or
+/// its call fails, from the `DataKey::SecondaryReflector`, with the address
+/// of the oracle that answered.
+pub fn latest_tick(
+    env: &Env,
+    reflector_client: &crate::reflector::ReflectorClient,
+    asset: &crate::reflector::Asset,
+) -> Option<(Address, crate::reflector::PriceData)> {
+    if let Ok(Ok(Some(tick))) = reflector_client.try_lastprice(asset) {
+        return Some((reflector_client.address.clone(), tick));
+    }
+    let secondary: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::SecondaryReflector)
+        .filter(|secondary| *secondary != reflector_client.address)?;
+    match crate::reflector::ReflectorClient::new(env, &secondary).try_lastprice(asset) {
+        Ok(Ok(Some(tick))) => Some((secondary, tick)),
+        _ => None,
+    }
+}
+
+/// The single path every valuation and rebalance reads prices through.
+/// Asks `reflector_client` (the primary) first and falls back to the
+/// secondary per asset, rescaling its prices to the primary's decimals; the
+/// secondary is expected to quote the same base asset. `MissingPrice` when
+/// neither oracle has a price, `StaleData` when the tick is older than
+/// `max_price_age` or the spot or TWAP falls outside `price_bounds`.
+pub fn resolve_price(
+    env: &Env,
+    reflector_client: &crate::reflector::ReflectorClient,
+    asset: &crate::reflector::Asset,
+    twap_records: u32,
+    max_price_age: u64,
+) -> Result<ResolvedPrice, Error> {
+    let (oracle, tick) = latest_tick(env, reflector_client, asset).ok_or(Error::MissingPrice)?;
+    if tick.is_stale(env.ledger().timestamp(), max_price_age) {
+        return Err(Error::StaleData);
+    }
+    let oracle_client = crate::reflector::ReflectorClient::new(env, &oracle);
+    let mut spot = tick.price;
+    let mut price = spot;
+    if twap_records > 0 {
+        if let Ok(Ok(Some(average))) = oracle_client.try_twap(asset, &twap_records) {
+            price = average;
+        }
+    }
+    if oracle != reflector_client.address {
+        let from = price_decimals(env, &oracle_client);
+        let to = price_decimals(env, reflector_client);
+        spot = rescale_price(spot, from, to);
+        price = rescale_price(price, from, to);
+    }
+    check_price_bounds(env, spot)?;
+    check_price_bounds(env, price)?;
+    Ok(ResolvedPrice {
+        oracle,
+        tick,
+        price,
+    })
+}
+
+/// `price` re-expressed from `from` to `to` oracle decimals.
+fn rescale_price(price: i128, from: u32, to: u32) -> i128 {
+    if to >= from {
+        price * 10i128.pow(to - from)
+    } else {
+        price / 10i128.pow(from - to)
+    }
+}
+
+/// The primary oracle's `base()`, or the secondary's when the primary's call
+/// fails.
+fn oracle_base(
+    env: &Env,
+    reflector_client: &crate::reflector::ReflectorClient,
+) -> Result<crate::reflector::Asset, Error> {
+    if let Ok(Ok(base)) = reflector_client.try_base() {
+        return Ok(base);
+    }
+    let secondary: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::SecondaryReflector)
+        .ok_or(Error::StaleData)?;
+    match crate::reflector::ReflectorClient::new(env, &secondary).try_base() {
+        Ok(Ok(base)) => Ok(base),
+        _ => Err(Error::StaleData),
+    }
+}
+
+/// Price of `quote_asset` in the oracle's base asset, or `None` when the quote
+/// is the base itself and prices pass through unchanged.
+pub fn quote_price(
+    env: &Env,
+    reflector_client: &crate::reflector::ReflectorClient,
+    quote_asset: &crate::reflector::Asset,
+    twap_records: u32,
+    max_price_age: u64,
+) -> Result<Option<i128>, Error> {
+    if *quote_asset == oracle_base(env, reflector_client)? {
+        return Ok(None);
+    }
+    let resolved = resolve_price(
+        env,
+        reflector_client,
+        quote_asset,
+        twap_records,
+        max_price_age,
+    )?;
+    if resolved.price <= 0 {
+        return Err(Error::MissingPrice);
+    }
+    Ok(Some(resolved.price))
+}
+
+/// Re-expresses a base-denominated price in the quote priced at
+/// `quote_price`, keeping the oracle's decimals.
+pub fn in_quote(price: i128, quote_price: Option<i128>, price_decimals: u32) -> i128 {
+    match quote_price {
+        Some(quote_price) => (price * 10i128.pow(price_decimals)) / quote_price,
+        None => price,
+    }
 }
 
 pub fn calculate_portfolio_value(
@@ -35,49 +374,709 @@ pub fn calculate_portfolio_value(
     balances: &Map<Address, i128>,
     asset_decimals: &Map<Address, u32>,
     reflector_client: &crate::reflector::ReflectorClient,
+    twap_records: u32,
+    max_price_age_seconds: u64,
+    quote_asset: &crate::reflector::Asset,
 ) -> Result<i128, Error> {
     let mut total_value = 0i128;
-    let _current_time = env.ledger().timestamp();
+    let price_decimals = price_decimals(env, reflector_client);
+    let quote = quote_price(
+        env,
+        reflector_client,
+        quote_asset,
+        twap_records,
+        max_price_age_seconds,
+    )
+    .map_err(|_| Error::StaleData)?;
 
     for (asset, balance) in balances.iter() {
-        let _decimals = asset_decimals
-            .get(asset.clone())
-            .unwrap_or(DEFAULT_ASSET_DECIMALS);
-        if let Some(price_data) =
-            reflector_client.lastprice(&crate::reflector::Asset::Stellar(asset))
-        {
-            total_value += balance_to_value(balance, price_data.price);
+        let decimals = decimals_of(asset_decimals, &asset);
+        match resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset),
+            twap_records,
+            max_price_age_seconds,
+        ) {
+            Ok(resolved) => {
+                let price = in_quote(resolved.price, quote, price_decimals);
+                total_value += balance_to_value(balance, price, price_decimals, decimals);
+            }
+            Err(Error::MissingPrice) => {}
+            Err(_) => return Err(Error::StaleData),
         }
     }
 
     Ok(total_value)
 }
 
+/// Values `balances` like `calculate_portfolio_value`, except that a held
+/// asset neither the primary nor the secondary Reflector can price fails with
+/// `StaleData` instead of counting as zero.
+pub fn value_all_priced(
+    env: &Env,
+    portfolio: &Portfolio,
+    balances: &Map<Address, i128>,
+    reflector_client: &crate::reflector::ReflectorClient,
+) -> Result<i128, Error> {
+    for (asset, _) in balances.iter() {
+        resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset),
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        )
+        .map_err(|_| Error::StaleData)?;
+    }
+    calculate_portfolio_value(
+        env,
+        balances,
+        &portfolio.asset_decimals,
+        reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
+    )
+    .map_err(|_| Error::StaleData)
+}
+
+pub fn fetch_target_prices(
+    env: &Env,
+    target_allocations: &Map<Address, u32>,
+    reflector_client: &crate::reflector::ReflectorClient,
+    twap_records: u32,
+    max_price_age: u64,
+    quote_asset: &crate::reflector::Asset,
+) -> Result<Map<Address, i128>, Error> {
+    let price_decimals = price_decimals(env, reflector_client);
+    let quote = quote_price(
+        env,
+        reflector_client,
+        quote_asset,
+        twap_records,
+        max_price_age,
+    )
+    .map_err(|_| Error::StaleData)?;
+    let mut prices = Map::new(env);
+
+    for (asset, _) in target_allocations.iter() {
+        let resolved = resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset.clone()),
+            twap_records,
+            max_price_age,
+        )
+        .map_err(|_| Error::StaleData)?;
+        prices.set(asset, in_quote(resolved.price, quote, price_decimals));
+    }
+
+    Ok(prices)
+}
+
+/// Values each held balance at Reflector prices and returns its share of the
+/// total in basis points. Empty when the portfolio holds nothing of value.
+pub fn current_allocations(
+    env: &Env,
+    portfolio: &Portfolio,
+    reflector_client: &crate::reflector::ReflectorClient,
+) -> Result<Map<Address, u32>, Error> {
+    let price_decimals = price_decimals(env, reflector_client);
+    let mut values = Map::new(env);
+    let mut total_value = 0i128;
+
+    for (asset, balance) in portfolio.current_balances.iter() {
+        let resolved = resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset.clone()),
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        )
+        .map_err(|_| Error::StaleData)?;
+        let value = balance_to_value(
+            balance,
+            resolved.price,
+            price_decimals,
+            decimals_of(&portfolio.asset_decimals, &asset),
+        );
+        total_value += value;
+        values.set(asset, value);
+    }
+
+    let mut allocations = Map::new(env);
+    if total_value <= 0 {
+        return Ok(allocations);
+    }
+    for (asset, value) in values.iter() {
+        let share = (value * ALLOCATION_DENOMINATOR as i128) / total_value;
+        allocations.set(asset, share as u32);
+    }
+    Ok(allocations)
+}
+
+/// Signed drift of each target asset in basis points: live share minus target,
+/// so overweight assets are positive and underweight assets negative.
+pub fn asset_drifts(
+    env: &Env,
+    portfolio: &Portfolio,
+    reflector_client: &crate::reflector::ReflectorClient,
+) -> Result<Map<Address, i128>, Error> {
+    let current = current_allocations(env, portfolio, reflector_client)?;
+    let mut drifts = Map::new(env);
+    for (asset, target) in portfolio.target_allocations.iter() {
+        let share = current.get(asset.clone()).unwrap_or(0);
+        drifts.set(asset, share as i128 - target as i128);
+    }
+    Ok(drifts)
+}
+
+/// Largest gap between an asset's live share and its target, in basis
+/// points, across an `asset_drifts` map.
+pub fn largest_drift(drifts: &Map<Address, i128>) -> u32 {
+    let mut max_drift = 0;
+    for (_, drift) in drifts.iter() {
+        max_drift = max_drift.max(drift.unsigned_abs() as u32);
+    }
+    max_drift
+}
+
+/// Orders planned trades by the absolute drift of their asset, largest
+/// first. Ties keep the map's key order.
+pub fn order_trades_by_drift(
+    env: &Env,
+    trades: &Map<Address, i128>,
+    drifts: &Map<Address, i128>,
+) -> Vec<(Address, i128)> {
+    let mut ordered: Vec<(Address, i128)> = Vec::new(env);
+    for (asset, amount) in trades.iter() {
+        let drift = drifts.get(asset.clone()).unwrap_or(0).unsigned_abs();
+        let mut index = ordered.len();
+        for (i, (other, _)) in ordered.iter().enumerate() {
+            if drifts.get(other).unwrap_or(0).unsigned_abs() < drift {
+                index = i as u32;
+                break;
+            }
+        }
+        ordered.insert(index, (asset, amount));
+    }
+    ordered
+}
+
+/// Matches sells to buys in `trade_values` (signed, in quote units) in map
+/// order and expands each match into swap legs. A pair with a route hint
+/// trades through its intermediate in two hops; any other swaps directly.
+pub fn plan_trade_hops(env: &Env, trade_values: &Map<Address, i128>) -> Vec<TradeHop> {
+    let routes: Map<(Address, Address), Address> = env
+        .storage()
+        .instance()
+        .get(&DataKey::RouteHint)
+        .unwrap_or(Map::new(env));
+    let mut sells: Vec<(Address, i128)> = Vec::new(env);
+    let mut buys: Vec<(Address, i128)> = Vec::new(env);
+    for (asset, value) in trade_values.iter() {
+        if value < 0 {
+            sells.push_back((asset, -value));
+        } else if value > 0 {
+            buys.push_back((asset, value));
+        }
+    }
+
+    let mut hops = Vec::new(env);
+    let (mut i, mut j) = (0, 0);
+    let (mut sold, mut bought) = (0i128, 0i128);
+    while i < sells.len() && j < buys.len() {
+        let (from, sell_value) = sells.get(i).unwrap();
+        let (to, buy_value) = buys.get(j).unwrap();
+        let value = (sell_value - sold).min(buy_value - bought);
+        match routes.get((from.clone(), to.clone())) {
+            Some(via) => {
+                hops.push_back(TradeHop {
+                    from,
+                    to: via.clone(),
+                    value,
+                });
+                hops.push_back(TradeHop {
+                    from: via,
+                    to,
+                    value,
+                });
+            }
+            None => hops.push_back(TradeHop { from, to, value }),
+        }
+        sold += value;
+        bought += value;
+        if sold == sell_value {
+            i += 1;
+            sold = 0;
+        }
+        if bought == buy_value {
+            j += 1;
+            bought = 0;
+        }
+    }
+    hops
+}
+
+/// Target assets with no `current_balances` entry: never deposited, or
+/// withdrawn to nothing.
+pub fn unfunded_targets(env: &Env, portfolio: &Portfolio) -> Vec<Address> {
+    let mut unfunded = Vec::new(env);
+    for (asset, _) in portfolio.target_allocations.iter() {
+        if !portfolio.current_balances.contains_key(asset.clone()) {
+            unfunded.push_back(asset);
+        }
+    }
+    unfunded
+}
+
+/// Gives every target asset an explicit balance entry, zero for those in
+/// `unfunded_targets`, so trade sizing never reaches a target through a
+/// defaulted lookup. Returns the assets that were filled in.
+pub fn fill_missing_balances(env: &Env, portfolio: &mut Portfolio) -> Vec<Address> {
+    let unfunded = unfunded_targets(env, portfolio);
+    for asset in unfunded.iter() {
+        portfolio.current_balances.set(asset, 0);
+    }
+    unfunded
+}
+
 pub fn calculate_rebalance_trades(
     env: &Env,
     portfolio: &Portfolio,
     current_prices: &Map<Address, i128>,
-) -> Map<Address, i128> {
+    price_decimals: u32,
+) -> Result<Map<Address, i128>, Error> {
     let mut trades = Map::new(env);
     let total_value = portfolio.total_value;
+    if total_value <= 0 {
+        return Ok(trades);
+    }
 
+    let mut target_value_sum = 0i128;
+    let mut rounding_slack = 0i128;
     for (asset, target_percentage) in portfolio.target_allocations.iter() {
         let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
         let target_value =
             (total_value * target_percentage as i128) / ALLOCATION_DENOMINATOR as i128;
-        let asset_decimals = asset_decimals_for(portfolio, asset.clone());
 
         if let Some(price) = current_prices.get(asset.clone()) {
-            let target_balance = value_to_balance(target_value, price, asset_decimals);
-            let trade_amount = target_balance - current_balance;
+            let decimals = decimals_of(&portfolio.asset_decimals, &asset);
+            let current_value = balance_to_value(current_balance, price, price_decimals, decimals);
+            let drift_bps = ((current_value - target_value).abs() * ALLOCATION_DENOMINATOR as i128)
+                / total_value;
+            let target_balance = value_to_balance_rounded(
+                target_value,
+                price,
+                price_decimals,
+                decimals,
+                portfolio.rounding_mode,
+            );
+            // A short position cannot be reached by moving tokens in and out.
+            if target_balance < 0 {
+                return Err(Error::InfeasibleTarget);
+            }
+            target_value_sum += balance_to_value(target_balance, price, price_decimals, decimals);
+            // Rounding up may leave each target one rounding step above its share.
+            let step = 10i128.pow(decimals.saturating_sub(VALUE_DECIMALS));
+            rounding_slack += balance_to_value(step, price, price_decimals, decimals) + 1;
+
+            if portfolio.no_trade_band_bps > 0 && drift_bps <= portfolio.no_trade_band_bps as i128 {
+                continue;
+            }
+            let trade_amount = clamp_to_min_balance(
+                portfolio,
+                &asset,
+                current_balance,
+                clamp_trade_notional(
+                    portfolio,
+                    scale_for_drift(
+                        portfolio,
+                        scale_trade(portfolio, target_balance - current_balance),
+                        drift_bps,
+                    ),
+                    price,
+                    price_decimals,
+                    decimals,
+                ),
+            );
 
-            if trade_amount.abs() > MIN_TRADE_AMOUNT_STROOPS {
+            if trade_amount.abs() > min_trade_amount_for(portfolio, asset.clone()) {
                 trades.set(asset, trade_amount);
             }
         }
     }
 
-    trades
+    // Targets are carved out of `total_value`, so together they can never be
+    // worth more than it; if they are, the prices and the total disagree.
+    if target_value_sum > total_value + rounding_slack {
+        return Err(Error::InfeasibleTarget);
+    }
+
+    Ok(trades)
+}
+
+/// Trade deltas paired with the per-asset prices they were sized at and the
+/// assets whose trade was downsized for spot/TWAP divergence.
+pub type TradePlan = (
+    Map<Address, i128>,
+    Map<Address, crate::reflector::PriceData>,
+    Vec<Address>,
+);
+
+/// `|spot - twap| * 10000 / twap` for `asset`, or `None` when the oracle has
+/// no usable TWAP. Portfolios valuing at spot are checked against a
+/// `DIVERGENCE_TWAP_RECORDS` window.
+pub fn twap_divergence_bps(
+    reflector_client: &crate::reflector::ReflectorClient,
+    asset: &Address,
+    spot: &crate::reflector::PriceData,
+    twap_records: u32,
+) -> Option<i128> {
+    let records = if twap_records > 0 {
+        twap_records
+    } else {
+        DIVERGENCE_TWAP_RECORDS
+    };
+    // A Reflector whose `twap` traps or is missing yields no reading rather
+    // than aborting the rebalance.
+    let Ok(Ok(Some(average))) =
+        reflector_client.try_twap(&crate::reflector::Asset::Stellar(asset.clone()), &records)
+    else {
+        return None;
+    };
+    if average <= 0 {
+        return None;
+    }
+    Some((spot.price - average).abs() * ALLOCATION_DENOMINATOR as i128 / average)
+}
+
+/// Scales each trade whose asset diverges past `max_twap_divergence_bps` by
+/// `limit / divergence`, dropping it once it falls to the dust threshold.
+/// Returns the affected assets.
+pub fn limit_divergent_trades(
+    env: &Env,
+    portfolio: &Portfolio,
+    trades: &mut Map<Address, i128>,
+    divergences: &Map<Address, i128>,
+) -> Vec<Address> {
+    let mut downsized = Vec::new(env);
+    let limit = portfolio.max_twap_divergence_bps as i128;
+    if limit == 0 {
+        return downsized;
+    }
+    for (asset, amount) in trades.clone().iter() {
+        let divergence = divergences.get(asset.clone()).unwrap_or(0);
+        if divergence <= limit {
+            continue;
+        }
+        let scaled = amount * limit / divergence;
+        if scaled.abs() > min_trade_amount_for(portfolio, asset.clone()) {
+            trades.set(asset.clone(), scaled);
+        } else {
+            trades.remove(asset.clone());
+        }
+        downsized.push_back(asset);
+    }
+    downsized
+}
+
+/// Prices the target assets and sizes the trades a rebalance would settle.
+pub fn plan_rebalance_trades(
+    env: &Env,
+    portfolio: &Portfolio,
+    reflector_client: &crate::reflector::ReflectorClient,
+) -> Result<TradePlan, Error> {
+    let price_decimals = price_decimals(env, reflector_client);
+    let quote = quote_price(
+        env,
+        reflector_client,
+        &portfolio.quote_asset,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+    )?;
+    let mut current_prices = Map::new(env);
+    let mut price_snapshot = Map::new(env);
+    let mut divergences = Map::new(env);
+    for (asset, _) in portfolio.target_allocations.iter() {
+        let resolved = resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset.clone()),
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        )?;
+        if portfolio.max_twap_divergence_bps > 0 {
+            if let Some(divergence) = twap_divergence_bps(
+                &crate::reflector::ReflectorClient::new(env, &resolved.oracle),
+                &asset,
+                &resolved.tick,
+                portfolio.twap_records,
+            ) {
+                divergences.set(asset.clone(), divergence);
+            }
+        }
+        let price = in_quote(resolved.price, quote, price_decimals);
+        current_prices.set(asset.clone(), price);
+        price_snapshot.set(
+            asset,
+            crate::reflector::PriceData {
+                price,
+                timestamp: resolved.tick.timestamp,
+            },
+        );
+    }
+
+    let total_value = calculate_portfolio_value(
+        env,
+        &portfolio.current_balances,
+        &portfolio.asset_decimals,
+        reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
+    )
+    .map_err(|_| Error::StaleData)?;
+    // Every target would be zero, so any trade would empty the holdings.
+    if total_value == 0 {
+        return Err(Error::ZeroPortfolioValue);
+    }
+
+    let mut snapshot = portfolio.clone();
+    snapshot.total_value = total_value;
+    fill_missing_balances(env, &mut snapshot);
+    let mut trades = calculate_rebalance_trades(env, &snapshot, &current_prices, price_decimals)?;
+    let downsized = limit_divergent_trades(env, &snapshot, &mut trades, &divergences);
+    Ok((trades, price_snapshot, downsized))
+}
+
+/// Sizes the trades that would move every holding into `target_asset`, by
+/// running the rebalance calculation against a single 100% target. The
+/// owner's trade shaping (partial ratio, no-trade band, notional cap, drift
+/// scaling, balance floors and dust thresholds) is left out, so every other
+/// holding is sold in full.
+pub fn plan_liquidation(
+    env: &Env,
+    portfolio: &Portfolio,
+    reflector_client: &crate::reflector::ReflectorClient,
+    target_asset: &Address,
+) -> Result<Map<Address, i128>, Error> {
+    let mut liquidation = portfolio.clone();
+    let mut targets = Map::new(env);
+    let mut no_dust = Map::new(env);
+    for (asset, _) in portfolio.current_balances.iter() {
+        targets.set(asset.clone(), 0);
+        no_dust.set(asset, 0);
+    }
+    targets.set(target_asset.clone(), ALLOCATION_DENOMINATOR);
+    no_dust.set(target_asset.clone(), 0);
+    if !liquidation
+        .asset_decimals
+        .contains_key(target_asset.clone())
+    {
+        let decimals = ensure_token(env, target_asset)?;
+        liquidation
+            .asset_decimals
+            .set(target_asset.clone(), decimals);
+    }
+    liquidation.target_allocations = targets;
+    liquidation.min_trade_amounts = no_dust;
+    liquidation.rebalance_ratio_bps = FULL_REBALANCE_RATIO_BPS;
+    liquidation.no_trade_band_bps = 0;
+    liquidation.max_trade_notional = 0;
+    liquidation.drift_scaling_cap_bps = 0;
+    liquidation.min_balances = Map::new(env);
+
+    let prices = fetch_target_prices(
+        env,
+        &liquidation.target_allocations,
+        reflector_client,
+        liquidation.twap_records,
+        liquidation.max_price_age,
+        &liquidation.quote_asset,
+    )?;
+    liquidation.total_value = calculate_portfolio_value(
+        env,
+        &liquidation.current_balances,
+        &liquidation.asset_decimals,
+        reflector_client,
+        liquidation.twap_records,
+        liquidation.max_price_age,
+        &liquidation.quote_asset,
+    )?;
+    if liquidation.total_value == 0 {
+        return Err(Error::ZeroPortfolioValue);
+    }
+    calculate_rebalance_trades(
+        env,
+        &liquidation,
+        &prices,
+        price_decimals(env, reflector_client),
+    )
+}
+
+/// Debits `total_value * fee_bps / 10000` worth of `fee_asset`, or of the
+/// portfolio's largest holding when `None`, from `current_balances`. Returns
+/// the asset and amount charged, if any; the caller moves the tokens to the
+/// fee's recipient once state is saved.
+pub fn charge_fee(
+    env: &Env,
+    portfolio: &mut Portfolio,
+    reflector_client: &crate::reflector::ReflectorClient,
+    fee_bps: u32,
+    fee_asset: Option<&Address>,
+) -> Result<Option<(Address, i128)>, Error> {
+    let total_value = calculate_portfolio_value(
+        env,
+        &portfolio.current_balances,
+        &portfolio.asset_decimals,
+        reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
+    )?;
+    let fee_value = (total_value * fee_bps as i128) / ALLOCATION_DENOMINATOR as i128;
+    if fee_value <= 0 {
+        return Ok(None);
+    }
+
+    let price_decimals = price_decimals(env, reflector_client);
+    let quote = quote_price(
+        env,
+        reflector_client,
+        &portfolio.quote_asset,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+    )?;
+    let mut charged: Option<(Address, i128, i128)> = None;
+    for (asset, balance) in portfolio.current_balances.iter() {
+        if fee_asset.is_some_and(|fee_asset| *fee_asset != asset) {
+            continue;
+        }
+        let resolved = match resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset.clone()),
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        ) {
+            Ok(resolved) => resolved,
+            Err(Error::MissingPrice) => continue,
+            Err(error) => return Err(error),
+        };
+        let price = in_quote(resolved.price, quote, price_decimals);
+        let value = balance_to_value(
+            balance,
+            price,
+            price_decimals,
+            decimals_of(&portfolio.asset_decimals, &asset),
+        );
+        if charged.as_ref().is_none_or(|(_, _, best)| value > *best) {
+            charged = Some((asset, price, value));
+        }
+    }
+
+    let Some((asset, price, _)) = charged else {
+        return Ok(None);
+    };
+    let balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
+    let amount = value_to_balance(
+        fee_value,
+        price,
+        price_decimals,
+        decimals_of(&portfolio.asset_decimals, &asset),
+    )
+    .min(balance);
+    if amount <= 0 {
+        return Ok(None);
+    }
+
+    portfolio
+        .current_balances
+        .set(asset.clone(), balance - amount);
+    Ok(Some((asset, amount)))
+}
+
+/// Pulls a deposit from `from` into this contract for contract-custodied
+/// portfolios; self-custodied tokens stay where they are.
+pub fn collect_deposit(
+    env: &Env,
+    portfolio: &Portfolio,
+    from: &Address,
+    asset: &Address,
+    amount: i128,
+) {
+    if portfolio.custody_mode == CustodyMode::ContractCustody {
+        token::Client::new(env, asset).transfer(from, &env.current_contract_address(), &amount);
+    }
+}
+
+/// Sends `amount` of a contract-custodied asset back to the owner; a
+/// self-custodied owner already holds it.
+pub fn release_to_owner(env: &Env, portfolio: &Portfolio, asset: &Address, amount: i128) {
+    if portfolio.custody_mode == CustodyMode::ContractCustody {
+        token::Client::new(env, asset).transfer(
+            &env.current_contract_address(),
+            &portfolio.user,
+            &amount,
+        );
+    }
+}
+
+/// Pays `amount` of `asset` to `to` out of wherever the portfolio's tokens
+/// are held. A self-custodied owner signs the transfer, or has approved this
+/// contract when a keeper is acting.
+pub fn pay_from_holdings(
+    env: &Env,
+    portfolio: &Portfolio,
+    to: &Address,
+    asset: &Address,
+    amount: i128,
+    via_allowance: bool,
+) {
+    let token_client = token::Client::new(env, asset);
+    let contract = env.current_contract_address();
+    match portfolio.custody_mode {
+        CustodyMode::ContractCustody => token_client.transfer(&contract, to, &amount),
+        CustodyMode::SelfCustody if via_allowance => {
+            token_client.transfer_from(&contract, &portfolio.user, to, &amount)
+        }
+        CustodyMode::SelfCustody => token_client.transfer(&portfolio.user, to, &amount),
+    }
+}
+
+/// Settles one trade between custody and the owner: buys pull into custody
+/// (through the owner's allowance when `via_allowance`), sells pay out.
+/// Reports a failing token call instead of aborting; the failed transfer's
+/// own effects are rolled back by the host.
+pub fn try_transfer_trade(
+    env: &Env,
+    asset: &Address,
+    custody: &Address,
+    owner: &Address,
+    amount: i128,
+    via_allowance: bool,
+) -> bool {
+    let token_client = token::Client::new(env, asset);
+    if amount > 0 && via_allowance {
+        matches!(
+            token_client.try_transfer_from(custody, owner, custody, &amount),
+            Ok(Ok(_))
+        )
+    } else if amount > 0 {
+        matches!(
+            token_client.try_transfer(owner, custody, &amount),
+            Ok(Ok(_))
+        )
+    } else if amount < 0 {
+        matches!(
+            token_client.try_transfer(custody, owner, &(-amount)),
+            Ok(Ok(_))
+        )
+    } else {
+        true
+    }
 }
 
 pub fn build_rebalance_preview(
@@ -85,7 +1084,6 @@ pub fn build_rebalance_preview(
     portfolio: &Portfolio,
     reflector_client: &crate::reflector::ReflectorClient,
 ) -> Result<RebalancePreview, Error> {
-    let current_time = env.ledger().timestamp();
     let mut candidate_trades = Map::new(env);
     let mut skipped_assets = Vec::new(env);
     let mut skip_reasons = Map::new(env);
@@ -97,6 +1095,9 @@ pub fn build_rebalance_preview(
         &portfolio.current_balances,
         &portfolio.asset_decimals,
         reflector_client,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+        &portfolio.quote_asset,
     ) {
         Ok(value) if value > 0 => value,
         Ok(_) => {
@@ -112,20 +1113,38 @@ pub fn build_rebalance_preview(
         Err(_) => return Err(Error::PreviewUnavailable),
     };
 
+    let price_decimals = price_decimals(env, reflector_client);
+    let quote = quote_price(
+        env,
+        reflector_client,
+        &portfolio.quote_asset,
+        portfolio.twap_records,
+        portfolio.max_price_age,
+    )
+    .map_err(|_| Error::PreviewUnavailable)?;
     let mut current_prices = Map::new(env);
     for (asset, _) in portfolio.target_allocations.iter() {
-        if let Some(price_data) =
-            reflector_client.lastprice(&crate::reflector::Asset::Stellar(asset.clone()))
-        {
-            if price_data.is_stale(current_time, 3600) {
+        match resolve_price(
+            env,
+            reflector_client,
+            &crate::reflector::Asset::Stellar(asset.clone()),
+            portfolio.twap_records,
+            portfolio.max_price_age,
+        ) {
+            Ok(resolved) => {
+                current_prices.set(
+                    asset.clone(),
+                    in_quote(resolved.price, quote, price_decimals),
+                );
+            }
+            Err(Error::MissingPrice) => {
+                skipped_assets.push_back(asset.clone());
+                skip_reasons.set(asset.clone(), AssetSkipReason::MissingPrice);
+            }
+            Err(_) => {
                 skipped_assets.push_back(asset.clone());
                 skip_reasons.set(asset.clone(), AssetSkipReason::StalePrice);
-                continue;
             }
-            current_prices.set(asset.clone(), price_data.price);
-        } else {
-            skipped_assets.push_back(asset.clone());
-            skip_reasons.set(asset.clone(), AssetSkipReason::MissingPrice);
         }
     }
 
@@ -136,16 +1155,13 @@ pub fn build_rebalance_preview(
 
         let price = current_prices.get(asset.clone()).unwrap();
         let current_balance = portfolio.current_balances.get(asset.clone()).unwrap_or(0);
-        let current_asset_value = balance_to_value(current_balance, price);
+        let decimals = decimals_of(&portfolio.asset_decimals, &asset);
+        let current_asset_value =
+            balance_to_value(current_balance, price, price_decimals, decimals);
         let current_percent_u32 =
             ((current_asset_value * ALLOCATION_DENOMINATOR as i128) / total_value) as u32;
-        let drift = if current_percent_u32 >= target_percent {
-            current_percent_u32 - target_percent
-        } else {
-            target_percent - current_percent_u32
-        };
-        let exceeds_threshold =
-            drift > portfolio.rebalance_threshold * (ALLOCATION_DENOMINATOR / 100);
+        let drift = current_percent_u32.abs_diff(target_percent);
+        let exceeds_threshold = drift > portfolio.rebalance_threshold;
         if exceeds_threshold {
             rebalance_needed = true;
         }
@@ -161,11 +1177,31 @@ pub fn build_rebalance_preview(
         );
 
         let target_value = (total_value * target_percent as i128) / ALLOCATION_DENOMINATOR as i128;
-        let ad = asset_decimals_for(portfolio, asset.clone());
-        let target_balance = value_to_balance(target_value, price, ad);
-        let trade_amount = target_balance - current_balance;
+        let target_balance = value_to_balance_rounded(
+            target_value,
+            price,
+            price_decimals,
+            decimals,
+            portfolio.rounding_mode,
+        );
+        let trade_amount = clamp_to_min_balance(
+            portfolio,
+            &asset,
+            current_balance,
+            clamp_trade_notional(
+                portfolio,
+                scale_for_drift(
+                    portfolio,
+                    scale_trade(portfolio, target_balance - current_balance),
+                    drift as i128,
+                ),
+                price,
+                price_decimals,
+                decimals,
+            ),
+        );
 
-        if trade_amount.abs() <= MIN_TRADE_AMOUNT_STROOPS {
+        if trade_amount.abs() <= min_trade_amount_for(portfolio, asset.clone()) {
             skipped_assets.push_back(asset.clone());
             skip_reasons.set(asset.clone(), AssetSkipReason::BelowMinTrade);
         } else if !exceeds_threshold {
@@ -186,11 +1222,80 @@ pub fn build_rebalance_preview(
     })
 }
 
-pub fn asset_decimals_for(portfolio: &Portfolio, asset: Address) -> u32 {
+/// Distinguishes an owner-deactivated portfolio (`pause_reason` is `None`)
+/// from one paused for a recorded reason.
+pub fn ensure_portfolio_active(portfolio: &Portfolio) -> Result<(), Error> {
+    if portfolio.is_active {
+        Ok(())
+    } else if portfolio.pause_reason == PauseReason::None {
+        Err(Error::PortfolioInactive)
+    } else {
+        Err(Error::PortfolioPaused)
+    }
+}
+
+/// Scales a full move toward target by the portfolio's `rebalance_ratio_bps`.
+pub fn scale_trade(portfolio: &Portfolio, full_trade: i128) -> i128 {
+    full_trade * portfolio.rebalance_ratio_bps as i128 / FULL_REBALANCE_RATIO_BPS as i128
+}
+
+/// With `drift_scaling_cap_bps` set, sizes `trade` by how far `drift_bps` is
+/// past `rebalance_threshold`: `(drift - threshold) / (cap - threshold)`,
+/// clamped to `[0, 1]`, so trades stay small just over the threshold.
+pub fn scale_for_drift(portfolio: &Portfolio, trade: i128, drift_bps: i128) -> i128 {
+    let cap = portfolio.drift_scaling_cap_bps as i128;
+    let threshold = portfolio.rebalance_threshold as i128;
+    if cap == 0 || drift_bps >= cap {
+        return trade;
+    }
+    if drift_bps <= threshold {
+        return 0;
+    }
+    trade * (drift_bps - threshold) / (cap - threshold)
+}
+
+/// Limits `trade` to `max_trade_notional` worth of the asset at `price`, so a
+/// large move converges over several rebalances. A cap of `0` leaves it as is.
+pub fn clamp_trade_notional(
+    portfolio: &Portfolio,
+    trade: i128,
+    price: i128,
+    price_decimals: u32,
+    asset_decimals: u32,
+) -> i128 {
+    let cap = portfolio.max_trade_notional;
+    if cap == 0 || balance_to_value(trade.abs(), price, price_decimals, asset_decimals) <= cap {
+        return trade;
+    }
+    let capped = value_to_balance(cap, price, price_decimals, asset_decimals);
+    if trade < 0 {
+        -capped
+    } else {
+        capped
+    }
+}
+
+/// Shrinks a sell so it leaves at least the asset's `min_balances` floor;
+/// buys, and assets without a floor, pass through.
+pub fn clamp_to_min_balance(
+    portfolio: &Portfolio,
+    asset: &Address,
+    current_balance: i128,
+    trade: i128,
+) -> i128 {
+    if trade >= 0 {
+        return trade;
+    }
+    let floor = portfolio.min_balances.get(asset.clone()).unwrap_or(0);
+    let sellable = (current_balance - floor).max(0);
+    trade.max(-sellable)
+}
+
+fn min_trade_amount_for(portfolio: &Portfolio, asset: Address) -> i128 {
     portfolio
-        .asset_decimals
+        .min_trade_amounts
         .get(asset)
-        .unwrap_or(DEFAULT_ASSET_DECIMALS)
+        .unwrap_or(MIN_TRADE_AMOUNT_STROOPS)
 }
 
 pub fn check_portfolio_invariants(portfolio: &Portfolio) -> Result<(), Error> {
@@ -218,7 +1323,7 @@ pub fn estimate_portfolio_storage_footprint(
     portfolio: &Portfolio,
 ) -> u32 {
     let xdr = portfolio.clone().to_xdr(env);
-    xdr.len() as u32
+    xdr.len()
 }
 
 #[cfg(test)]
@@ -265,44 +1370,524 @@ pub fn validate_portfolio_storage_footprint(
     Ok(estimate)
 }
 
-pub fn emit_portfolio_created(env: &Env, portfolio_id: u64, user: Address) {
+// Every event ends its topics with `EVENT_TOPIC_VERSION`; the payload shape
+// documented on each helper is the one for that version.
+
+/// `("portfolio", "created", v1)` data: `(portfolio_id, user, rebalance_threshold)`.
+pub fn emit_portfolio_created(
+    env: &Env,
+    portfolio_id: u64,
+    user: Address,
+    rebalance_threshold: u32,
+) {
     env.events().publish(
-        (symbol_short!("portfolio"), symbol_short!("created")),
-        (portfolio_id, user),
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("created"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, user, rebalance_threshold),
     );
 }
 
+/// `("portfolio", "deposit", v1)` data: `(portfolio_id, asset, amount)`.
 pub fn emit_portfolio_deposit(env: &Env, portfolio_id: u64, asset: Address, amount: i128) {
     env.events().publish(
-        (symbol_short!("portfolio"), symbol_short!("deposit")),
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("deposit"),
+            EVENT_TOPIC_VERSION,
+        ),
         (portfolio_id, asset, amount),
     );
 }
 
+/// `("portfolio", "withdraw", v1)` data: `(portfolio_id, asset, amount)`.
 pub fn emit_portfolio_withdraw(env: &Env, portfolio_id: u64, asset: Address, amount: i128) {
     env.events().publish(
-        (symbol_short!("portfolio"), symbol_short!("withdraw")),
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("withdraw"),
+            EVENT_TOPIC_VERSION,
+        ),
         (portfolio_id, asset, amount),
     );
 }
 
+/// `("deposit", asset, v1)` data: `(portfolio_id, amount, balance)`, where
+/// `balance` is the asset's recorded balance after the deposit.
+pub fn emit_asset_deposit(
+    env: &Env,
+    portfolio_id: u64,
+    asset: Address,
+    amount: i128,
+    balance: i128,
+) {
+    env.events().publish(
+        (symbol_short!("deposit"), asset, EVENT_TOPIC_VERSION),
+        (portfolio_id, amount, balance),
+    );
+}
+
+/// `("withdraw", asset, v1)` data: `(portfolio_id, amount, balance)`, where
+/// `balance` is the asset's recorded balance after the withdrawal.
+pub fn emit_asset_withdraw(
+    env: &Env,
+    portfolio_id: u64,
+    asset: Address,
+    amount: i128,
+    balance: i128,
+) {
+    env.events().publish(
+        (symbol_short!("withdraw"), asset, EVENT_TOPIC_VERSION),
+        (portfolio_id, amount, balance),
+    );
+}
+
+/// `("allocations", "updated", v1)` data: `(portfolio_id, allocations)`.
+pub fn emit_allocations_updated(env: &Env, portfolio_id: u64, allocations: Map<Address, u32>) {
+    env.events().publish(
+        (
+            Symbol::new(env, "allocations"),
+            symbol_short!("updated"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, allocations),
+    );
+}
+
+/// Best-effort check that `asset` is a deployed token contract: it must
+/// answer `decimals()`. Catches mistyped addresses before they hold balances
+/// that can never be priced or settled. Returns the reported decimals.
+pub fn ensure_token(env: &Env, asset: &Address) -> Result<u32, Error> {
+    match token::Client::new(env, asset).try_decimals() {
+        Ok(Ok(decimals)) if decimals <= MAX_ASSET_DECIMALS => Ok(decimals),
+        Ok(Ok(_)) => Err(Error::InvalidAssetDecimals),
+        _ => Err(Error::NotAToken),
+    }
+}
+
+/// An empty whitelist leaves assets unrestricted; once the admin allows any
+/// asset, only whitelisted assets are accepted.
+pub fn is_asset_allowed(env: &Env, asset: &Address) -> bool {
+    let whitelist: Map<Address, bool> = env
+        .storage()
+        .instance()
+        .get(&DataKey::AssetWhitelist)
+        .unwrap_or(Map::new(env));
+    whitelist.is_empty() || whitelist.contains_key(asset.clone())
+}
+
+/// Runs `ensure_token` over every asset, so a target whose decimals cannot
+/// be looked up is refused before any valuation depends on it.
+pub fn ensure_tokens(env: &Env, assets: &Vec<Address>) -> Result<(), Error> {
+    for asset in assets.iter() {
+        ensure_token(env, &asset)?;
+    }
+    Ok(())
+}
+
+pub fn ensure_assets_allowed(env: &Env, assets: &Vec<Address>) -> Result<(), Error> {
+    for asset in assets.iter() {
+        if !is_asset_allowed(env, &asset) {
+            return Err(Error::AssetNotAllowed);
+        }
+    }
+    Ok(())
+}
+
+/// Fails with `MissingPrice` unless Reflector has a feed for every asset,
+/// so an untracked asset is caught before it is stored rather than at the
+/// first valuation.
+pub fn ensure_price_feeds(env: &Env, assets: &Vec<Address>) -> Result<(), Error> {
+    let reflector_address: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::ReflectorAddress)
+        .unwrap();
+    let reflector_client = crate::reflector::ReflectorClient::new(env, &reflector_address);
+    for asset in assets.iter() {
+        if latest_tick(
+            env,
+            &reflector_client,
+            &crate::reflector::Asset::Stellar(asset),
+        )
+        .is_none()
+        {
+            return Err(Error::MissingPrice);
+        }
+    }
+    Ok(())
+}
+
+pub fn add_user_portfolio(env: &Env, user: &Address, portfolio_id: u64) {
+    let key = DataKey::UserPortfolios(user.clone());
+    let mut ids: Vec<u64> = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or(Vec::new(env));
+    ids.push_back(portfolio_id);
+    env.storage().persistent().set(&key, &ids);
+}
+
+/// Drops `portfolio_id` from `user`'s list; `false` when it was not listed.
+pub fn remove_user_portfolio(env: &Env, user: &Address, portfolio_id: u64) -> bool {
+    let key = DataKey::UserPortfolios(user.clone());
+    let mut ids: Vec<u64> = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or(Vec::new(env));
+    match ids.first_index_of(portfolio_id) {
+        Some(index) => {
+            ids.remove(index);
+            env.storage().persistent().set(&key, &ids);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Appends a history entry and stores the prices that drove it under
+/// `RebalancePrices(portfolio_id, seq)`. Both are trimmed together to the
+/// last `MAX_REBALANCE_HISTORY` rebalances.
+pub fn record_rebalance(
+    env: &Env,
+    portfolio_id: u64,
+    timestamp: u64,
+    total_value: i128,
+    trade_count: u32,
+    failed_assets: Vec<Address>,
+    prices: Map<Address, crate::reflector::PriceData>,
+) {
+    let key = DataKey::RebalanceHistory(portfolio_id);
+    let mut history: Vec<RebalanceRecord> = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or(Vec::new(env));
+    let seq = history.last().map(|r| r.seq + 1).unwrap_or(0);
+    while history.len() >= MAX_REBALANCE_HISTORY {
+        if let Some(evicted) = history.pop_front() {
+            env.storage()
+                .persistent()
+                .remove(&DataKey::RebalancePrices(portfolio_id, evicted.seq));
+        }
+    }
+    history.push_back(RebalanceRecord {
+        seq,
+        timestamp,
+        total_value,
+        trade_count,
+        failed_assets,
+    });
+    env.storage().persistent().set(&key, &history);
+    env.storage()
+        .persistent()
+        .set(&DataKey::RebalancePrices(portfolio_id, seq), &prices);
+}
+
+/// Folds one rebalance into the portfolio's lifetime `PortfolioStats`.
+pub fn record_stats(env: &Env, portfolio_id: u64, value_traded: i128, drift_corrected_bps: u32) {
+    let key = DataKey::PortfolioStats(portfolio_id);
+    let mut stats: PortfolioStats = env.storage().persistent().get(&key).unwrap_or_default();
+    stats.rebalance_count += 1;
+    stats.total_value_traded += value_traded;
+    stats.total_drift_corrected_bps += drift_corrected_bps as u64;
+    stats.average_drift_corrected_bps =
+        (stats.total_drift_corrected_bps / stats.rebalance_count as u64) as u32;
+    env.storage().persistent().set(&key, &stats);
+}
+
+/// Folds each deposit into the asset's running `CostBasis` at the current
+/// spot price. A deposit whose price is missing or stale is left out, since
+/// the deposit itself must still succeed.
+pub fn record_cost_basis(
+    env: &Env,
+    portfolio_id: u64,
+    portfolio: &Portfolio,
+    deposits: &Map<Address, i128>,
+) {
+    let reflector_address: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::ReflectorAddress)
+        .unwrap();
+    let reflector_client = crate::reflector::ReflectorClient::new(env, &reflector_address);
+    let Ok(quote) = quote_price(
+        env,
+        &reflector_client,
+        &portfolio.quote_asset,
+        0,
+        portfolio.max_price_age,
+    ) else {
+        return;
+    };
+    let price_decimals = price_decimals(env, &reflector_client);
+
+    let key = DataKey::CostBasis(portfolio_id);
+    let mut bases: Map<Address, CostBasis> = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or(Map::new(env));
+    for (asset, amount) in deposits.iter() {
+        let Ok(resolved) = resolve_price(
+            env,
+            &reflector_client,
+            &crate::reflector::Asset::Stellar(asset.clone()),
+            0,
+            portfolio.max_price_age,
+        ) else {
+            continue;
+        };
+        let price = in_quote(resolved.price, quote, price_decimals);
+        let basis = bases.get(asset.clone()).unwrap_or_default();
+        let quantity = basis.quantity + amount;
+        bases.set(
+            asset,
+            CostBasis {
+                quantity,
+                average_price: (basis.quantity * basis.average_price + amount * price) / quantity,
+            },
+        );
+    }
+    env.storage().persistent().set(&key, &bases);
+}
+
+/// Contract-wide totals kept under `DataKey::GlobalStats`.
+pub fn global_stats(env: &Env) -> GlobalStats {
+    env.storage()
+        .instance()
+        .get(&DataKey::GlobalStats)
+        .unwrap_or_default()
+}
+
+/// Folds a change in one portfolio's stored `total_value` into the
+/// contract-wide value under management. The total is kept in the oracle's
+/// base asset, so a portfolio marked `ForeignQuote` is skipped rather than
+/// mixing units.
+pub fn record_value_under_management(env: &Env, portfolio_id: u64, value_delta: i128) {
+    if value_delta == 0
+        || env
+            .storage()
+            .persistent()
+            .has(&DataKey::ForeignQuote(portfolio_id))
+    {
+        return;
+    }
+    let mut stats = global_stats(env);
+    stats.total_value_under_management += value_delta;
+    env.storage().instance().set(&DataKey::GlobalStats, &stats);
+}
+
+/// Marks whether `portfolio_id` is valued in the oracle's base asset and so
+/// counts toward the value under management.
+pub fn set_quoted_in_base(env: &Env, portfolio_id: u64, in_base: bool) {
+    let key = DataKey::ForeignQuote(portfolio_id);
+    if in_base {
+        env.storage().persistent().remove(&key);
+    } else {
+        env.storage().persistent().set(&key, &true);
+    }
+}
+
+/// Counts a portfolio in when it is created or reactivated and out again when
+/// it is closed or deactivated.
+pub fn record_portfolio_opened(env: &Env, opened: bool) {
+    let mut stats = global_stats(env);
+    if opened {
+        stats.portfolio_count += 1;
+    } else {
+        stats.portfolio_count = stats.portfolio_count.saturating_sub(1);
+    }
+    env.storage().instance().set(&DataKey::GlobalStats, &stats);
+}
+
+/// `("portfolio", "portfolio_fee", v1)` data: `(portfolio_id, collector, asset, amount)`.
+pub fn emit_portfolio_fee(
+    env: &Env,
+    portfolio_id: u64,
+    collector: Address,
+    asset: Address,
+    amount: i128,
+) {
+    env.events().publish(
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(env, "portfolio_fee"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, collector, asset, amount),
+    );
+}
+
+/// `("portfolio", "treasury_fee", v1)` data: `(portfolio_id, asset, amount)`.
+pub fn emit_treasury_fee(env: &Env, portfolio_id: u64, asset: Address, amount: i128) {
+    env.events().publish(
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(env, "treasury_fee"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, asset, amount),
+    );
+}
+
+/// `("portfolio", "unfunded_targets", v1)` data: `(portfolio_id, assets)`.
+pub fn emit_unfunded_targets(env: &Env, portfolio_id: u64, assets: Vec<Address>) {
+    env.events().publish(
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(env, "unfunded_targets"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, assets),
+    );
+}
+
+/// `("portfolio", "trade_failed", v1)` data: `(portfolio_id, asset, amount)`.
+pub fn emit_trade_failed(env: &Env, portfolio_id: u64, asset: Address, amount: i128) {
+    env.events().publish(
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(env, "trade_failed"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, asset, amount),
+    );
+}
+
+/// `("trade", asset, v1)` data: `(portfolio_id, delta, price)`.
+pub fn emit_trade(env: &Env, portfolio_id: u64, asset: Address, delta: i128, price: i128) {
+    env.events().publish(
+        (symbol_short!("trade"), asset, EVENT_TOPIC_VERSION),
+        (portfolio_id, delta, price),
+    );
+}
+
+/// `("portfolio", "rebalanced", v1)` data: `(portfolio_id, timestamp)`.
 pub fn emit_portfolio_rebalanced(env: &Env, portfolio_id: u64, timestamp: u64) {
     env.events().publish(
-        (symbol_short!("portfolio"), Symbol::new(env, "rebalanced")),
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(env, "rebalanced"),
+            EVENT_TOPIC_VERSION,
+        ),
         (portfolio_id, timestamp),
     );
 }
 
-pub fn emit_cooldown_override(env: &Env, portfolio_id: u64, admin: Address, timestamp: u64) {
+/// `("notify", kind, v1)` data: `(portfolio_id, owner)`, emitted only when
+/// the portfolio's `DataKey::NotifyPrefs` has `bit` set.
+pub fn notify(env: &Env, portfolio_id: u64, owner: &Address, bit: u32, kind: Symbol) {
+    let prefs: u32 = env
+        .storage()
+        .persistent()
+        .get(&DataKey::NotifyPrefs(portfolio_id))
+        .unwrap_or(0);
+    if prefs & bit != 0 {
+        env.events().publish(
+            (symbol_short!("notify"), kind, EVENT_TOPIC_VERSION),
+            (portfolio_id, owner.clone()),
+        );
+    }
+}
+
+/// `("rebalance", "forced", v1)` data: `(portfolio_id, admin, timestamp)`.
+pub fn emit_rebalance_forced(env: &Env, portfolio_id: u64, admin: Address, timestamp: u64) {
     env.events().publish(
         (
-            symbol_short!("portfolio"),
-            Symbol::new(env, "cooldown_override"),
+            symbol_short!("rebalance"),
+            symbol_short!("forced"),
+            EVENT_TOPIC_VERSION,
         ),
         (portfolio_id, admin, timestamp),
     );
 }
 
+/// `("reflector", "rotated", v1)` data: `(old_address, new_address)`.
+pub fn emit_reflector_rotated(env: &Env, old_address: Address, new_address: Address) {
+    env.events().publish(
+        (
+            symbol_short!("reflector"),
+            symbol_short!("rotated"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (old_address, new_address),
+    );
+}
+
+/// `("admin", "proposed", v1)` data: `(current_admin, proposed_admin)`.
+pub fn emit_admin_proposed(env: &Env, current_admin: Address, proposed_admin: Address) {
+    env.events().publish(
+        (
+            symbol_short!("admin"),
+            symbol_short!("proposed"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (current_admin, proposed_admin),
+    );
+}
+
+/// `("admin", "accepted", v1)` data: `(previous_admin, new_admin)`.
+pub fn emit_admin_accepted(env: &Env, previous_admin: Address, new_admin: Address) {
+    env.events().publish(
+        (
+            symbol_short!("admin"),
+            symbol_short!("accepted"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (previous_admin, new_admin),
+    );
+}
+
+/// `("admin", "deactivated", v1)` data: `(portfolio_id, admin)`.
+pub fn emit_admin_deactivated(env: &Env, portfolio_id: u64, admin: Address) {
+    env.events().publish(
+        (
+            symbol_short!("admin"),
+            Symbol::new(env, "deactivated"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, admin),
+    );
+}
+
+/// `("portfolio", "closed", v1)` data: `(portfolio_id, returned)`.
+pub fn emit_portfolio_closed(env: &Env, portfolio_id: u64, returned: Map<Address, i128>) {
+    env.events().publish(
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("closed"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, returned),
+    );
+}
+
+/// `("portfolio", "owner_transferred", v1)` data: `(portfolio_id, previous_owner, new_owner)`.
+pub fn emit_ownership_transferred(
+    env: &Env,
+    portfolio_id: u64,
+    previous_owner: Address,
+    new_owner: Address,
+) {
+    env.events().publish(
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(env, "owner_transferred"),
+            EVENT_TOPIC_VERSION,
+        ),
+        (portfolio_id, previous_owner, new_owner),
+    );
+}
+
+pub fn validate_rebalance_threshold(threshold: u32) -> bool {
+    (MIN_REBALANCE_THRESHOLD..=MAX_REBALANCE_THRESHOLD).contains(&threshold)
+}
+
 pub fn validate_slippage_policy_version(version: u32) -> bool {
     version == CURRENT_SLIPPAGE_POLICY_VERSION
 }
@@ -2,8 +2,12 @@ extern crate std;
 
 use super::*;
 use soroban_sdk::{
-    testutils::{Address as _, Ledger, MockAuth, MockAuthInvoke},
-    vec, Address, Env, IntoVal, Map, String,
+    symbol_short,
+    testutils::{
+        storage::Persistent as _, Address as _, AuthorizedFunction, AuthorizedInvocation, Events,
+        Ledger, MockAuth, MockAuthInvoke,
+    },
+    token, vec, Address, Env, IntoVal, Map, String, Symbol,
 };
 
 fn allocation_decimals(
@@ -34,16 +38,52 @@ fn create_portfolio_with_defaults(
         &rebalance_threshold,
         &slippage_tolerance,
         &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
     )
 }
 
+fn create_token(env: &Env) -> Address {
+    env.register_stellar_asset_contract_v2(Address::generate(env))
+        .address()
+}
+
+fn mint(env: &Env, token: &Address, to: &Address, amount: i128) {
+    soroban_sdk::token::StellarAssetClient::new(env, token).mint(to, &amount);
+}
+
+fn token_balance(env: &Env, token: &Address, owner: &Address) -> i128 {
+    soroban_sdk::token::Client::new(env, token).balance(owner)
+}
+
+/// Creates a 50/50 portfolio over two fresh tokens holding 200M / 100M units
+/// in custody, with the owner funded so the buy side can settle.
+fn create_drifted_token_portfolio(
+    env: &Env,
+    client: &PortfolioRebalancerClient,
+    user: &Address,
+) -> (u64, Address, Address) {
+    let asset1 = create_token(env);
+    let asset2 = create_token(env);
+    let mut allocations = Map::new(env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(env, client, user, &allocations, 500, 50);
+    client.set_custody_mode(&pid, &CustodyMode::ContractCustody);
+
+    mint(env, &asset1, user, 200_000_000);
+    mint(env, &asset2, user, 200_000_000);
+    client.deposit(&pid, &asset1, &200_000_000, &String::from_str(env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(env, ""));
+    (pid, asset1, asset2)
+}
+
 const BENCHMARK_TOLERANCE_PERCENT: u64 = 20;
 const BASELINE_INITIALIZE_CPU: u64 = 1_500_000;
 const BASELINE_INITIALIZE_MEM: u64 = 200_000;
 const BASELINE_CREATE_PORTFOLIO_CPU: u64 = 2_500_000;
 const BASELINE_CREATE_PORTFOLIO_MEM: u64 = 300_000;
 const BASELINE_EXECUTE_REBALANCE_CPU: u64 = 5_000_000;
-const BASELINE_EXECUTE_REBALANCE_MEM: u64 = 500_000;
+const BASELINE_EXECUTE_REBALANCE_MEM: u64 = 560_000;
 const BASELINE_DEPOSIT_CPU: u64 = 2_000_000;
 const BASELINE_DEPOSIT_MEM: u64 = 250_000;
 
@@ -170,259 +210,463 @@ mod reflector_without_prices {
     }
 }
 
-#[test]
-fn test_create_portfolio() {
-    let env = Env::default();
-    env.mock_all_auths();
-    env.ledger().with_mut(|li| {
-        li.sequence_number = 1;
-    });
+mod reflector_with_stale_prices {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, Env, Symbol, Vec};
 
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    #[contract]
+    pub struct ReflectorWithStalePrices;
 
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
+    #[contractimpl]
+    impl ReflectorWithStalePrices {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
 
-    client.initialize(&admin, &reflector_id);
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
 
-    let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    allocations.set(asset1, 5000);
-    allocations.set(asset2, 5000);
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
 
-    let portfolio_id = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+        pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
+            Some(PriceData {
+                price: 100_00000000000000i128,
+                timestamp: env.ledger().timestamp().saturating_sub(7200),
+            })
+        }
 
-    assert!(portfolio_id > 0);
+        pub fn twap(_env: Env, _asset: Asset, _records: u32) -> Option<i128> {
+            Some(100_00000000000000i128)
+        }
+    }
 }
 
-#[test]
-fn test_deposit_valid() {
-    let env = Env::default();
-    env.mock_all_auths();
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+mod reflector_with_divergent_twap {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, contracttype, Env, Symbol, Vec};
 
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
+    #[contract]
+    pub struct ReflectorWithDivergentTwap;
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    #[contracttype]
+    pub enum DataKey {
+        TwapUnavailable,
+        TwapTraps,
+    }
 
-    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
+    #[contractimpl]
+    impl ReflectorWithDivergentTwap {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
 
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 1000);
-}
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
 
-#[test]
-#[should_panic(expected = "Error(Contract, #17)")]
-fn test_deposit_invalid_amount() {
-    let env = Env::default();
-    env.mock_all_auths();
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
 
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
+        pub fn set_twap_unavailable(env: Env, unavailable: bool) {
+            env.storage()
+                .instance()
+                .set(&DataKey::TwapUnavailable, &unavailable);
+        }
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+        pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
+            Some(PriceData {
+                price: 100_00000000000000i128,
+                timestamp: env.ledger().timestamp(),
+            })
+        }
 
-    let result = client.try_deposit(&pid, &asset, &0, &String::from_str(&env, ""));
-    assert_eq!(result, Err(Ok(Error::InvalidWithdrawAmount)));
-    client.deposit(&pid, &asset, &0, &String::from_str(&env, ""));
+        pub fn set_twap_traps(env: Env, traps: bool) {
+            env.storage().instance().set(&DataKey::TwapTraps, &traps);
+        }
+
+        pub fn twap(env: Env, _asset: Asset, _records: u32) -> Option<i128> {
+            if env
+                .storage()
+                .instance()
+                .get(&DataKey::TwapTraps)
+                .unwrap_or(false)
+            {
+                panic!("twap unavailable");
+            }
+            let unavailable: bool = env
+                .storage()
+                .instance()
+                .get(&DataKey::TwapUnavailable)
+                .unwrap_or(false);
+            if unavailable {
+                None
+            } else {
+                Some(50_00000000000000i128)
+            }
+        }
+    }
 }
 
-#[test]
-fn test_check_rebalance_needed_no_drift() {
-    let env = Env::default();
-    env.mock_all_auths();
+mod reflector_with_decimals {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, contracttype, Env, Symbol, Vec};
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 1000;
-    });
+    #[contract]
+    pub struct ReflectorWithDecimals;
 
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    #[contracttype]
+    pub enum DataKey {
+        Decimals,
+    }
 
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
+    /// Quotes every asset at 100 in whatever precision `set_decimals` selects.
+    #[contractimpl]
+    impl ReflectorWithDecimals {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
 
-    let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    allocations.set(asset1.clone(), 5000);
-    allocations.set(asset2.clone(), 5000);
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
 
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+        pub fn set_decimals(env: Env, decimals: u32) {
+            env.storage().instance().set(&DataKey::Decimals, &decimals);
+        }
 
-    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
-    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+        pub fn decimals(env: Env) -> u32 {
+            env.storage()
+                .instance()
+                .get(&DataKey::Decimals)
+                .unwrap_or(14)
+        }
 
-    assert!(!client.check_rebalance_needed(&pid));
+        pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
+            Some(PriceData {
+                price: 100 * 10i128.pow(Self::decimals(env.clone())),
+                timestamp: env.ledger().timestamp(),
+            })
+        }
+
+        pub fn twap(env: Env, _asset: Asset, _records: u32) -> Option<i128> {
+            Some(100 * 10i128.pow(Self::decimals(env)))
+        }
+    }
 }
 
-#[test]
-fn test_check_rebalance_needed_with_drift() {
-    let env = Env::default();
-    env.mock_all_auths();
+mod reflector_with_aged_prices {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, Env, Symbol, Vec};
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 1000;
-    });
+    #[contract]
+    pub struct ReflectorWithAgedPrices;
 
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
+    /// Quotes every asset at 100 with a tick published 60 seconds ago.
+    #[contractimpl]
+    impl ReflectorWithAgedPrices {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
 
-    let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    allocations.set(asset1.clone(), 5000);
-    allocations.set(asset2.clone(), 5000);
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
 
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
 
-    client.deposit(&pid, &asset1, &200, &String::from_str(&env, ""));
-    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+        pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
+            Some(PriceData {
+                price: 100_00000000000000i128,
+                timestamp: env.ledger().timestamp().saturating_sub(60),
+            })
+        }
 
-    assert!(client.check_rebalance_needed(&pid));
+        pub fn twap(_env: Env, _asset: Asset, _records: u32) -> Option<i128> {
+            Some(100_00000000000000i128)
+        }
+    }
 }
 
-#[test]
-fn test_execute_rebalance_success() {
-    let env = Env::default();
-    env.mock_all_auths();
+mod reflector_with_stale_asset {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, Symbol, Vec};
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
+    #[contract]
+    pub struct ReflectorWithStaleAsset;
 
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
+    #[contracttype]
+    pub enum DataKey {
+        StaleAsset,
+    }
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
+    /// Quotes every asset at 100 with a current tick, except the configured
+    /// stale asset, whose tick is two hours old.
+    #[contractimpl]
+    impl ReflectorWithStaleAsset {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
 
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 15000;
-    });
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
 
-    let actual_balances = Map::new(&env);
-    client.execute_rebalance(&pid, &actual_balances);
+        pub fn set_stale_asset(env: Env, asset: Address) {
+            env.storage().instance().set(&DataKey::StaleAsset, &asset);
+        }
 
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.last_rebalance, 15000);
+        pub fn lastprice(env: Env, asset: Asset) -> Option<PriceData> {
+            let stale_asset = env
+                .storage()
+                .instance()
+                .get::<DataKey, Address>(&DataKey::StaleAsset);
+            let age = match asset {
+                Asset::Stellar(address) if stale_asset.as_ref() == Some(&address) => 7200,
+                _ => 0,
+            };
+            Some(PriceData {
+                price: 100_00000000000000i128,
+                timestamp: env.ledger().timestamp().saturating_sub(age),
+            })
+        }
+
+        pub fn twap(_env: Env, _asset: Asset, _records: u32) -> Option<i128> {
+            Some(100_00000000000000i128)
+        }
+    }
 }
 
-#[test]
-fn test_fee_config_supports_platform_name_and_zero_fee() {
-    let env = Env::default();
-    env.mock_all_auths();
+mod reflector_with_cross_rates {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, Env, Symbol, Vec};
 
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
+    #[contract]
+    pub struct ReflectorWithCrossRates;
 
-    let recipient = Address::generate(&env);
-    let config = FeeConfig {
-        platform_name: String::from_str(&env, "Acme Vault"),
-        fee_bps: 0,
-        fee_recipient: recipient.clone(),
-        enabled: true,
-    };
+    /// Base USD: every Stellar asset is 100 USD and one EUR is 2 USD.
+    #[contractimpl]
+    impl ReflectorWithCrossRates {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
 
-    client.set_fee_config(&config);
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
 
-    let persisted = client.get_fee_config();
-    assert_eq!(
-        persisted.platform_name,
-        String::from_str(&env, "Acme Vault")
-    );
-    assert_eq!(persisted.fee_bps, 0);
-    assert_eq!(persisted.fee_recipient, recipient);
-    assert!(persisted.enabled);
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
+
+        pub fn lastprice(env: Env, asset: Asset) -> Option<PriceData> {
+            let price = match asset {
+                Asset::Stellar(_) => 100_00000000000000i128,
+                Asset::Other(symbol) if symbol == Symbol::new(&env, "EUR") => 2_00000000000000i128,
+                Asset::Other(_) => return None,
+            };
+            Some(PriceData {
+                price,
+                timestamp: env.ledger().timestamp(),
+            })
+        }
+
+        pub fn twap(env: Env, asset: Asset, _records: u32) -> Option<i128> {
+            Self::lastprice(env, asset).map(|data| data.price)
+        }
+    }
+}
+
+mod reflector_with_settable_price {
+    use crate::reflector::{Asset, PriceData};
+    use soroban_sdk::{contract, contractimpl, contracttype, Env, Symbol, Vec};
+
+    #[contract]
+    pub struct ReflectorWithSettablePrice;
+
+    #[contracttype]
+    pub enum DataKey {
+        Price,
+    }
+
+    #[contractimpl]
+    impl ReflectorWithSettablePrice {
+        pub fn base(env: Env) -> Asset {
+            Asset::Other(Symbol::new(&env, "USD"))
+        }
+
+        pub fn assets(env: Env) -> Vec<Asset> {
+            Vec::new(&env)
+        }
+
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
+
+        pub fn set_price(env: Env, price: i128) {
+            env.storage().instance().set(&DataKey::Price, &price);
+        }
+
+        pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
+            Some(PriceData {
+                price: env
+                    .storage()
+                    .instance()
+                    .get(&DataKey::Price)
+                    .unwrap_or(100_00000000000000i128),
+                timestamp: env.ledger().timestamp(),
+            })
+        }
+
+        pub fn twap(env: Env, asset: Asset, _records: u32) -> Option<i128> {
+            Self::lastprice(env, asset).map(|price_data| price_data.price)
+        }
+    }
+}
+
+mod reentrant_token {
+    use crate::PortfolioRebalancerClient;
+    use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, Map};
+
+    #[contract]
+    pub struct ReentrantToken;
+
+    #[contracttype]
+    pub enum DataKey {
+        Target,
+        ReentryBlocked,
+    }
+
+    /// A token whose `transfer` calls back into `execute_rebalance` on the
+    /// rebalancer it was pointed at, recording whether the call was refused.
+    /// Transfers are plain no-ops until `set_target` arms it.
+    #[contractimpl]
+    impl ReentrantToken {
+        pub fn set_target(env: Env, rebalancer: Address, portfolio_id: u64) {
+            env.storage()
+                .instance()
+                .set(&DataKey::Target, &(rebalancer, portfolio_id));
+        }
+
+        pub fn reentry_blocked(env: Env) -> Option<bool> {
+            env.storage().instance().get(&DataKey::ReentryBlocked)
+        }
+
+        pub fn decimals(_env: Env) -> u32 {
+            7
+        }
+
+        pub fn transfer(env: Env, _from: Address, _to: Address, _amount: i128) {
+            let Some((rebalancer, portfolio_id)) = env
+                .storage()
+                .instance()
+                .get::<_, (Address, u64)>(&DataKey::Target)
+            else {
+                return;
+            };
+            let result = PortfolioRebalancerClient::new(&env, &rebalancer)
+                .try_execute_rebalance(&portfolio_id, &Map::new(&env));
+            env.storage()
+                .instance()
+                .set(&DataKey::ReentryBlocked, &result.is_err());
+        }
+    }
+}
+
+mod decimals_token {
+    use soroban_sdk::{contract, contractimpl, symbol_short, Env};
+
+    #[contract]
+    pub struct DecimalsToken;
+
+    /// A token that only reports the decimals it was configured with.
+    #[contractimpl]
+    impl DecimalsToken {
+        pub fn set_decimals(env: Env, decimals: u32) {
+            env.storage()
+                .instance()
+                .set(&symbol_short!("decimals"), &decimals);
+        }
+
+        pub fn decimals(env: Env) -> u32 {
+            env.storage()
+                .instance()
+                .get(&symbol_short!("decimals"))
+                .unwrap()
+        }
+    }
+}
+
+mod failing_token {
+    use soroban_sdk::{contract, contractimpl, symbol_short, Address, Env};
+
+    #[contract]
+    pub struct FailingToken;
+
+    /// A token whose transfers succeed as no-ops until `disable` is called,
+    /// after which every transfer fails.
+    #[contractimpl]
+    impl FailingToken {
+        pub fn disable(env: Env) {
+            env.storage()
+                .instance()
+                .set(&symbol_short!("disabled"), &true);
+        }
+
+        pub fn decimals(_env: Env) -> u32 {
+            7
+        }
+
+        pub fn transfer(env: Env, _from: Address, _to: Address, _amount: i128) {
+            if env.storage().instance().has(&symbol_short!("disabled")) {
+                panic!("transfer disabled");
+            }
+        }
+    }
 }
 
 #[test]
-fn test_rebalance_applies_non_zero_fee_to_trade_amount() {
+fn test_create_portfolio() {
     let env = Env::default();
     env.mock_all_auths();
-
     env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
+        li.sequence_number = 1;
     });
 
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
+
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
+
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    allocations.set(asset1.clone(), 5000);
-    allocations.set(asset2.clone(), 5000);
-
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.deposit(&pid, &asset1, &1000, &String::from_str(&env, ""));
-
-    let recipient = Address::generate(&env);
-    let config = FeeConfig {
-        platform_name: String::from_str(&env, "Acme Vault"),
-        fee_bps: 100,
-        fee_recipient: recipient,
-        enabled: true,
-    };
-    client.set_fee_config(&config);
-
-    env.ledger().with_mut(|li| {
-        li.timestamp = 15000;
-    });
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1, 5000);
+    allocations.set(asset2, 5000);
 
-    client.execute_rebalance(&pid, &Map::new(&env));
+    let portfolio_id = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.current_balances.get(asset1).unwrap(), 495);
-    assert_eq!(portfolio.current_balances.get(asset2).unwrap(), 495);
+    assert!(portfolio_id > 0);
 }
 
 #[test]
-fn test_execute_rebalance_cooldown() {
+fn test_get_target_allocations_and_balances() {
     let env = Env::default();
     env.mock_all_auths();
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -430,22 +674,30 @@ fn test_execute_rebalance_cooldown() {
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    allocations.set(asset1.clone(), 6000);
+    allocations.set(asset2.clone(), 4000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10010;
-    });
+    assert_eq!(client.get_target_allocations(&pid), allocations);
+    assert!(client.get_balances(&pid).is_empty());
 
-    let actual_balances = Map::new(&env);
-    let result = client.try_execute_rebalance(&pid, &actual_balances);
-    assert_eq!(result, Err(Ok(Error::CooldownActive)));
+    client.deposit(&pid, &asset1, &700, &String::from_str(&env, ""));
+    let balances = client.get_balances(&pid);
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances.get(asset1), Some(700));
+    assert_eq!(balances, client.get_portfolio(&pid).current_balances);
+
+    assert_eq!(
+        client.try_get_balances(&(pid + 1)),
+        Err(Ok(Error::PortfolioNotFound))
+    );
 }
 
 #[test]
-fn test_emergency_stop() {
+fn test_create_portfolio_emits_created_event() {
     let env = Env::default();
     env.mock_all_auths();
 
@@ -457,699 +709,6070 @@ fn test_emergency_stop() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-
-    client.set_emergency_stop(&true);
-    let result = client.try_deposit(&pid, &asset, &100, &String::from_str(&env, ""));
-    assert_eq!(result, Err(Ok(Error::EmergencyStop)));
-    client.set_emergency_stop(&false);
+    allocations.set(create_token(&env), 10000);
+    let portfolio_id = create_portfolio_with_defaults(&env, &client, &user, &allocations, 250, 50);
 
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("created"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+    let (event_id, event_user, event_threshold): (u64, Address, u32) = data.into_val(&env);
+    assert_eq!(event_id, portfolio_id);
+    assert_eq!(event_user, user);
+    assert_eq!(event_threshold, 250);
 }
 
 #[test]
-fn test_stale_data() {
+fn test_deposit_valid() {
     let env = Env::default();
     env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-    mod stale_reflector {
-        use crate::reflector::{Asset, PriceData};
-        use soroban_sdk::{contract, contractimpl, Env, Symbol, Vec};
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-        #[contract]
-        pub struct StaleReflector;
+    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
 
-        #[contractimpl]
-        impl StaleReflector {
-            pub fn base(_env: Env) -> Asset {
-                Asset::Other(Symbol::new(&_env, "USD"))
-            }
-            pub fn assets(_env: Env) -> Vec<Asset> {
-                Vec::new(&_env)
-            }
-            pub fn decimals(_env: Env) -> u32 {
-                14
-            }
-            pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
-                let current = env.ledger().timestamp();
-                Some(PriceData {
-                    price: 100_00000000000000,
-                    timestamp: current - 7200,
-                })
-            }
-            pub fn twap(_env: Env, _asset: Asset, _records: u32) -> Option<i128> {
-                Some(0)
-            }
-        }
-    }
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 1000);
+}
 
+#[test]
+fn test_update_target_allocations_success() {
+    let env = Env::default();
+    env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let stale_reflector_id = env.register_contract(None, stale_reflector::StaleReflector);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
-    client.initialize(&admin, &stale_reflector_id);
+    client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 15000;
-    });
+    let asset3 = create_token(&env);
+    let mut new_allocations = Map::new(&env);
+    new_allocations.set(asset1.clone(), 2000);
+    new_allocations.set(asset3.clone(), 8000);
+    client.update_target_allocations(&pid, &new_allocations);
 
-    let actual_balances = Map::new(&env);
-    let _result = client.try_execute_rebalance(&pid, &actual_balances);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.target_allocations, new_allocations);
+    assert_eq!(
+        portfolio.asset_decimals.get(asset3).unwrap(),
+        DEFAULT_ASSET_DECIMALS
+    );
+
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (
+            Symbol::new(&env, "allocations"),
+            symbol_short!("updated"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
 }
 
 #[test]
-fn test_edge_case_single_asset() {
+fn test_rebalance_trade_count_matches_non_dust_deltas() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    client.initialize(&Address::generate(&env), &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
+    let heavy = create_token(&env);
+    let dust = create_token(&env);
+    let light = create_token(&env);
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-
-    let owner = Address::generate(&env);
-    let pid = create_portfolio_with_defaults(&env, &client, &owner, &allocations, 5, 50);
-    assert!(!client.check_rebalance_needed(&pid));
+    allocations.set(heavy.clone(), 4000);
+    allocations.set(dust.clone(), 3000);
+    allocations.set(light.clone(), 3000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    // `dust` sits half a minimum trade below its target.
+    client.deposit(&pid, &heavy, &500_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &dust, &299_500_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &light, &200_500_000, &String::from_str(&env, ""));
+
+    let trades = client.preview_rebalance_trades(&pid);
+    assert!(!trades.contains_key(dust));
+    assert_eq!(trades.len(), 2);
+    assert_eq!(client.rebalance_trade_count(&pid), 2);
 }
 
 #[test]
-fn test_portfolio_validation() {
+fn test_no_trade_band_leaves_near_target_asset_untouched() {
     let env = Env::default();
-    let mut allocations = Map::new(&env);
-
-    allocations.set(Address::generate(&env), 6000);
-    allocations.set(Address::generate(&env), 4000);
-    assert!(crate::portfolio::validate_allocations(&allocations));
-
-    let mut invalid_allocations = Map::new(&env);
-    invalid_allocations.set(Address::generate(&env), 6000);
-    invalid_allocations.set(Address::generate(&env), 3000);
-    assert!(!crate::portfolio::validate_allocations(
-        &invalid_allocations
-    ));
-}
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-fn allocation_map_from_percentages(env: &Env, bps_values: &[u32]) -> Map<Address, u32> {
-    let mut allocations = Map::new(env);
-    for bps in bps_values {
-        allocations.set(Address::generate(env), *bps);
-    }
-    allocations
-}
+    let heavy = create_token(&env);
+    let near = create_token(&env);
+    let light = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(heavy.clone(), 4000);
+    allocations.set(near.clone(), 3000);
+    allocations.set(light.clone(), 3000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    // Drifts of +1000, -100 and -900 bps.
+    client.deposit(&pid, &heavy, &500_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &near, &290_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &light, &210_000_000, &String::from_str(&env, ""));
+    assert!(client
+        .preview_rebalance_trades(&pid)
+        .contains_key(near.clone()));
 
-fn random_bps_with_target_sum(seed: &mut u64, count: usize, target_sum: u32) -> [u32; 12] {
-    let mut values = [0u32; 12];
-    let mut remaining = target_sum;
-    let limit = count.min(12);
-    for (i, value) in values.iter_mut().enumerate().take(limit) {
-        if i + 1 == limit {
-            *value = remaining;
-            break;
-        }
-        *seed ^= *seed << 13;
-        *seed ^= *seed >> 7;
-        *seed ^= *seed << 17;
-        let next = if remaining == 0 {
-            0
-        } else {
-            ((*seed as u32) % (remaining + 1)).min(remaining)
-        };
-        *value = next;
-        remaining -= next;
-    }
-    values
+    assert_eq!(
+        client.try_set_no_trade_band(&pid, &501),
+        Err(Ok(Error::InvalidTradeLimit))
+    );
+    client.set_no_trade_band(&pid, &200);
+    let trades = client.preview_rebalance_trades(&pid);
+    assert!(!trades.contains_key(near));
+    assert_eq!(trades.get(heavy), Some(-100_000_000));
+    assert_eq!(trades.get(light), Some(90_000_000));
 }
 
 #[test]
-fn test_validate_allocations_randomized_sum_10000_accepts_500_vectors() {
+fn test_update_target_allocations_invalid_keeps_previous() {
     let env = Env::default();
-    let mut seed = 0xC0FFEEu64;
-    for _ in 0..500 {
-        let mut adjusted = [0u32; 10];
-        let mut remaining = 10000u32;
-        for (i, slot) in adjusted.iter_mut().enumerate() {
-            let slots_left = 10 - i;
-            if slots_left == 1 {
-                *slot = remaining;
-                break;
-            }
-            seed ^= seed << 13;
-            seed ^= seed >> 7;
-            seed ^= seed << 17;
-            let max_for_slot = remaining - (slots_left as u32 - 1);
-            let next = 1 + ((seed as u32) % max_for_slot);
-            *slot = next;
-            remaining -= next;
-        }
-        let allocations = allocation_map_from_percentages(&env, &adjusted);
-        assert!(crate::portfolio::validate_allocations(&allocations));
-    }
-}
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-#[test]
-fn test_validate_allocations_randomized_sum_9999_rejects_500_vectors() {
-    let env = Env::default();
-    let mut seed = 0xBAD5EEDu64;
-    for _ in 0..500 {
-        let raw = random_bps_with_target_sum(&mut seed, 10, 9999);
-        let allocations = allocation_map_from_percentages(&env, &raw[..10]);
-        assert!(!crate::portfolio::validate_allocations(&allocations));
-    }
-}
+    let mut allocations = Map::new(&env);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-#[test]
-fn test_validate_allocations_randomized_sum_10001_rejects_500_vectors() {
-    let env = Env::default();
-    let mut seed = 0xDEADBEEFu64;
-    for _ in 0..500 {
-        let raw = random_bps_with_target_sum(&mut seed, 10, 10001);
-        let allocations = allocation_map_from_percentages(&env, &raw[..10]);
-        assert!(!crate::portfolio::validate_allocations(&allocations));
-    }
-}
+    let mut new_allocations = Map::new(&env);
+    new_allocations.set(asset1, 6000);
+    new_allocations.set(asset2, 3000);
+    let result = client.try_update_target_allocations(&pid, &new_allocations);
+    assert_eq!(result, Err(Ok(Error::InvalidAllocation)));
 
-#[test]
-fn test_validate_allocations_empty_map_boundary() {
-    let env = Env::default();
-    let allocations = Map::new(&env);
-    assert!(!crate::portfolio::validate_allocations(&allocations));
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.target_allocations, allocations);
 }
 
 #[test]
-fn test_validate_allocations_single_asset_full_boundary() {
+fn test_update_target_allocations_rejects_dropping_funded_asset() {
     let env = Env::default();
-    let allocations = allocation_map_from_percentages(&env, &[10000]);
-    assert!(crate::portfolio::validate_allocations(&allocations));
-}
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-#[test]
-fn test_validate_allocations_ten_assets_equal_weight() {
-    let env = Env::default();
-    let allocations = allocation_map_from_percentages(
-        &env,
-        &[1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000],
-    );
-    assert!(crate::portfolio::validate_allocations(&allocations));
+    let mut allocations = Map::new(&env);
+    let funded = create_token(&env);
+    let empty = create_token(&env);
+    let kept = create_token(&env);
+    allocations.set(funded.clone(), 4000);
+    allocations.set(empty.clone(), 3000);
+    allocations.set(kept.clone(), 3000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &funded, &1000, &String::from_str(&env, ""));
+
+    let mut without_funded = Map::new(&env);
+    without_funded.set(empty.clone(), 5000);
+    without_funded.set(kept.clone(), 5000);
+    let result = client.try_update_target_allocations(&pid, &without_funded);
+    assert_eq!(result, Err(Ok(Error::AssetHasBalance)));
+    assert_eq!(client.get_portfolio(&pid).target_allocations, allocations);
+
+    let mut without_empty = Map::new(&env);
+    without_empty.set(funded, 5000);
+    without_empty.set(kept, 5000);
+    client.update_target_allocations(&pid, &without_empty);
+    assert_eq!(client.get_portfolio(&pid).target_allocations, without_empty);
 }
 
 #[test]
-fn test_validate_allocations_fractional_three_way_split() {
+fn test_update_target_allocations_enforces_max_assets() {
     let env = Env::default();
-    // 33.33% + 33.33% + 33.34% = 100.00%
-    let allocations = allocation_map_from_percentages(&env, &[3333, 3333, 3334]);
-    assert!(crate::portfolio::validate_allocations(&allocations));
-}
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-#[test]
-fn test_validate_allocations_overflow_rejected() {
-    let env = Env::default();
-    let allocations = allocation_map_from_percentages(&env, &[u32::MAX, 1]);
-    assert!(!crate::portfolio::validate_allocations(&allocations));
-}
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 5000);
+    allocations.set(create_token(&env), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-fn build_trade_test_portfolio(
-    env: &Env,
-    allocations: &[(Address, u32)],
-    balances: &[(Address, i128)],
-    total_value: i128,
-) -> Portfolio {
-    let mut target_allocations = Map::new(env);
-    for (asset, bps) in allocations {
-        target_allocations.set(asset.clone(), *bps);
-    }
-    let mut current_balances = Map::new(env);
-    for (asset, balance) in balances {
-        current_balances.set(asset.clone(), *balance);
+    let share = ALLOCATION_DENOMINATOR / MAX_PORTFOLIO_ASSETS;
+    let mut at_cap = Map::new(&env);
+    for _ in 0..MAX_PORTFOLIO_ASSETS {
+        at_cap.set(create_token(&env), share);
     }
-    let asset_decimals = allocation_decimals(env, &target_allocations, DEFAULT_ASSET_DECIMALS);
-    Portfolio {
-        user: Address::generate(env),
-        target_allocations,
-        current_balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value,
-        is_active: true,
-        pause_reason: PauseReason::None,
+    client.update_target_allocations(&pid, &at_cap);
+    assert_eq!(
+        client.get_portfolio(&pid).target_allocations.len(),
+        MAX_PORTFOLIO_ASSETS
+    );
+
+    let over = MAX_PORTFOLIO_ASSETS + 1;
+    let over_share = ALLOCATION_DENOMINATOR / over;
+    let mut over_cap = Map::new(&env);
+    for i in 0..over {
+        let weight = if i == 0 {
+            ALLOCATION_DENOMINATOR - over_share * (over - 1)
+        } else {
+            over_share
+        };
+        over_cap.set(create_token(&env), weight);
     }
+    let result = client.try_update_target_allocations(&pid, &over_cap);
+    assert_eq!(result, Err(Ok(Error::TooManyAssets)));
+    assert_eq!(client.get_portfolio(&pid).target_allocations, at_cap);
 }
 
 #[test]
-fn test_calculate_rebalance_trades_excludes_below_minimum_stroops() {
+fn test_twap_valuation_differs_from_spot() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    let asset3 = Address::generate(&env);
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_divergent_twap::ReflectorWithDivergentTwap,
+    );
+    let reflector =
+        reflector_with_divergent_twap::ReflectorWithDivergentTwapClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(asset1.clone(), 10000);
-    allocations.set(asset2.clone(), 10000);
-    allocations.set(asset3.clone(), 10000);
-
-    let target_balance = 50_000_000i128;
-    let mut balances = Map::new(&env);
-    balances.set(
-        asset1.clone(),
-        target_balance - (MIN_TRADE_AMOUNT_STROOPS / 2),
-    );
-    balances.set(asset2.clone(), target_balance - MIN_TRADE_AMOUNT_STROOPS);
-    balances.set(
-        asset3.clone(),
-        target_balance - (MIN_TRADE_AMOUNT_STROOPS + 1),
-    );
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
 
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations,
-        current_balances: balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: target_balance,
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+    let spot = client.get_portfolio_value_usd(&pid);
+    assert_eq!(spot.total_usd_value, 100_000);
 
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 10i128.pow(14));
-    prices.set(asset2.clone(), 10i128.pow(14));
-    prices.set(asset3.clone(), 10i128.pow(14));
+    client.set_twap_records(&pid, &5);
+    assert_eq!(client.get_portfolio(&pid).twap_records, 5);
+    let twap = client.get_portfolio_value_usd(&pid);
+    assert_eq!(twap.total_usd_value, 50_000);
+    assert_eq!(
+        twap.assets.get(0).unwrap().oracle_price,
+        50_00000000000000i128
+    );
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
-    assert!(!trades.contains_key(asset1));
-    assert!(!trades.contains_key(asset2));
-    assert_eq!(trades.get(asset3).unwrap(), MIN_TRADE_AMOUNT_STROOPS + 1);
+    reflector.set_twap_unavailable(&true);
+    let fallback = client.get_portfolio_value_usd(&pid);
+    assert_eq!(fallback.total_usd_value, 100_000);
 }
 
 #[test]
-fn test_calculate_rebalance_trades_2_asset() {
+fn test_twap_divergence_downsizes_trades() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_divergent_twap::ReflectorWithDivergentTwap,
+    );
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
     let mut allocations = Map::new(&env);
     allocations.set(asset1.clone(), 5000);
     allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &200_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
 
-    let mut balances = Map::new(&env);
-    balances.set(asset1.clone(), 150 * 10i128.pow(14));
-    balances.set(asset2.clone(), 50 * 10i128.pow(14));
-
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations,
-        current_balances: balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: 200 * 10i128.pow(14),
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+    let full = client.preview_rebalance_trades(&pid);
+    assert_eq!(full.get(asset1.clone()), Some(-50_000_000));
+    assert_eq!(full.get(asset2.clone()), Some(50_000_000));
 
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 10i128.pow(14));
-    prices.set(asset2.clone(), 10i128.pow(14));
+    assert_eq!(
+        client.try_set_max_twap_divergence(&pid, &(ALLOCATION_DENOMINATOR + 1)),
+        Err(Ok(Error::InvalidTradeLimit))
+    );
+    // Spot 100 against a TWAP of 50 is a 10000 bps gap, four times the limit.
+    client.set_max_twap_divergence(&pid, &2500);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
+    // A trapping `twap` leaves divergence unknown instead of aborting.
+    let oracle =
+        reflector_with_divergent_twap::ReflectorWithDivergentTwapClient::new(&env, &reflector_id);
+    oracle.set_twap_traps(&true);
+    assert_eq!(client.preview_rebalance_trades(&pid), full);
+    oracle.set_twap_traps(&false);
 
-    assert_eq!(trades.get(asset1.clone()).unwrap(), -50 * 10i128.pow(14));
-    assert_eq!(trades.get(asset2.clone()).unwrap(), 50 * 10i128.pow(14));
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result.trades.get(asset1.clone()), Some(-12_500_000));
+    assert_eq!(result.trades.get(asset2.clone()), Some(12_500_000));
+    assert_eq!(result.downsized_assets.len(), 2);
+    assert!(result.downsized_assets.contains(asset1));
+    assert!(result.downsized_assets.contains(asset2));
 }
 
 #[test]
-fn test_calculate_rebalance_trades_two_asset_direction_correctness() {
+fn test_max_trade_notional_clamps_large_trades() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    let portfolio = build_trade_test_portfolio(
-        &env,
-        &[(asset1.clone(), 5000), (asset2.clone(), 5000)],
-        &[(asset1.clone(), 70_000_000), (asset2.clone(), 30_000_000)],
-        100_000_000,
-    );
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 10i128.pow(14));
-    prices.set(asset2.clone(), 10i128.pow(14));
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &200_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
+    assert_eq!(
+        client.preview_rebalance_trades(&pid).get(asset1.clone()),
+        Some(-50_000_000)
+    );
 
-    assert_eq!(trades.get(asset1).unwrap(), -20_000_000);
-    assert_eq!(trades.get(asset2).unwrap(), 20_000_000);
+    assert_eq!(
+        client.try_set_max_trade_notional(&pid, &-1),
+        Err(Ok(Error::InvalidTradeLimit))
+    );
+    // 50_000_000 units at a price of 100 are worth 5_000_000_000; cap at a fifth.
+    client.set_max_trade_notional(&pid, &1_000_000_000);
+    let trades = client.preview_rebalance_trades(&pid);
+    assert_eq!(trades.get(asset1), Some(-10_000_000));
+    assert_eq!(trades.get(asset2), Some(10_000_000));
 }
 
 #[test]
-fn test_calculate_rebalance_trades_5_asset() {
+fn test_set_twap_records_rejects_out_of_range() {
     let env = Env::default();
-    let a1 = Address::generate(&env);
-    let a2 = Address::generate(&env);
-    let a3 = Address::generate(&env);
-    let a4 = Address::generate(&env);
-    let a5 = Address::generate(&env);
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(a1.clone(), 2000);
-    allocations.set(a2.clone(), 2000);
-    allocations.set(a3.clone(), 2000);
-    allocations.set(a4.clone(), 2000);
-    allocations.set(a5.clone(), 2000);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let mut balances = Map::new(&env);
-    balances.set(a1.clone(), 50 * 10i128.pow(14));
-    balances.set(a2.clone(), 150 * 10i128.pow(14));
-    balances.set(a3.clone(), 100 * 10i128.pow(14));
-    balances.set(a4.clone(), 20 * 10i128.pow(14));
-    balances.set(a5.clone(), 180 * 10i128.pow(14));
-
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations,
-        current_balances: balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: 500 * 10i128.pow(14),
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+    let result = client.try_set_twap_records(&pid, &(MAX_TWAP_RECORDS + 1));
+    assert_eq!(result, Err(Ok(Error::InvalidTwapRecords)));
+    assert_eq!(client.get_portfolio(&pid).twap_records, 0);
+}
 
-    let mut prices = Map::new(&env);
-    prices.set(a1.clone(), 10i128.pow(14));
-    prices.set(a2.clone(), 10i128.pow(14));
-    prices.set(a3.clone(), 10i128.pow(14));
-    prices.set(a4.clone(), 10i128.pow(14));
-    prices.set(a5.clone(), 10i128.pow(14));
+#[test]
+fn test_get_all_portfolio_ids_pages_through_every_portfolio() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_all_portfolio_ids(&0, &10), vec![&env]);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let mut created = Vec::new(&env);
+    for _ in 0..5 {
+        let user = Address::generate(&env);
+        created.push_back(create_portfolio_with_defaults(
+            &env,
+            &client,
+            &user,
+            &allocations,
+            500,
+            50,
+        ));
+    }
 
-    assert_eq!(trades.get(a1).unwrap(), 50 * 10i128.pow(14));
-    assert_eq!(trades.get(a2).unwrap(), -50 * 10i128.pow(14));
-    assert!(!trades.contains_key(a3));
-    assert_eq!(trades.get(a4).unwrap(), 80 * 10i128.pow(14));
-    assert_eq!(trades.get(a5).unwrap(), -80 * 10i128.pow(14));
+    let mut paged = Vec::new(&env);
+    let mut start = 0;
+    loop {
+        let page = client.get_all_portfolio_ids(&start, &2);
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() <= 2);
+        paged.append(&page);
+        start += page.len();
+    }
+    assert_eq!(paged, created);
+    assert_eq!(client.get_all_portfolio_ids(&4, &10).len(), 1);
+    assert_eq!(client.get_all_portfolio_ids(&0, &u32::MAX).len(), 5);
 }
 
 #[test]
-fn test_calculate_rebalance_trades_direction_buy_sell() {
+fn test_oracle_health_reports_responsive_reflector() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-
-    let mut allocations = Map::new(&env);
-    allocations.set(asset1.clone(), 5000);
-    allocations.set(asset2.clone(), 5000);
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    assert!(!client.oracle_health());
 
-    let mut balances = Map::new(&env);
-    balances.set(asset1.clone(), 120 * 10i128.pow(14));
-    balances.set(asset2.clone(), 80 * 10i128.pow(14));
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert!(client.oracle_health());
 
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations,
-        current_balances: balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: 200 * 10i128.pow(14),
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+    client.set_reflector_address(&Address::generate(&env));
+    assert!(!client.oracle_health());
+}
 
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 10i128.pow(14));
-    prices.set(asset2.clone(), 10i128.pow(14));
+#[test]
+fn test_get_portfolio_unknown_id_returns_not_found() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-    let trade_a1 = trades.get(asset1).unwrap();
-    let trade_a2 = trades.get(asset2).unwrap();
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    assert!(
-        trade_a1 < 0,
-        "Overweight asset should result in a sell (negative) trade"
+    assert_eq!(
+        client.try_get_portfolio(&(pid + 1)),
+        Err(Ok(Error::PortfolioNotFound))
     );
-    assert!(
-        trade_a2 > 0,
-        "Underweight asset should result in a buy (positive) trade"
+    assert_eq!(
+        client.try_check_rebalance_needed(&(pid + 1)),
+        Err(Ok(Error::PortfolioNotFound))
+    );
+    assert_eq!(
+        client.try_deposit(&(pid + 1), &user, &100, &String::from_str(&env, "")),
+        Err(Ok(Error::PortfolioNotFound))
+    );
+    assert_eq!(
+        client.try_execute_rebalance(&(pid + 1), &Map::new(&env)),
+        Err(Ok(Error::PortfolioNotFound))
     );
-    assert_eq!(trade_a1, -20 * 10i128.pow(14));
-    assert_eq!(trade_a2, 20 * 10i128.pow(14));
 }
 
 #[test]
-fn test_calculate_rebalance_trades_price_precision() {
+#[should_panic(expected = "Error(Contract, #26)")]
+fn test_deposit_invalid_amount() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(asset1.clone(), 6000);
-    allocations.set(asset2.clone(), 4000);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let mut balances = Map::new(&env);
-    balances.set(asset1.clone(), 150 * 10i128.pow(14));
-    balances.set(asset2.clone(), 125 * 10i128.pow(13));
+    let result = client.try_deposit(&pid, &asset, &0, &String::from_str(&env, ""));
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    client.deposit(&pid, &asset, &0, &String::from_str(&env, ""));
+}
 
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations,
-        current_balances: balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: 100 * 10i128.pow(14),
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+#[test]
+fn test_create_portfolio_rejects_asset_without_decimals() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
 
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 50_000_000_000_000);
-    prices.set(asset2.clone(), 200_000_000_000_000);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
+    // An address with no deployed contract behind it, as from a typo, cannot
+    // answer `decimals()`.
+    let token = create_token(&env);
+    let not_a_token = Address::generate(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(token.clone(), 5000);
+    allocations.set(not_a_token.clone(), 5000);
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::NotAToken)));
+    assert!(client.get_user_portfolios(&user).is_empty());
 
-    assert_eq!(trades.get(asset1).unwrap(), -30 * 10i128.pow(14));
-    assert_eq!(trades.get(asset2).unwrap(), 75 * 10i128.pow(13));
+    let mut single = Map::new(&env);
+    single.set(token, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &single, 500, 50);
+    assert_eq!(
+        client.try_update_target_allocations(&pid, &allocations),
+        Err(Ok(Error::NotAToken))
+    );
 }
 
 #[test]
-fn test_calculate_rebalance_trades_three_asset_rebalance_path() {
+fn test_deposit_negative_amount_rejected() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    let asset3 = Address::generate(&env);
-    let portfolio = build_trade_test_portfolio(
-        &env,
-        &[
-            (asset1.clone(), 5000),
-            (asset2.clone(), 3000),
-            (asset3.clone(), 2000),
-        ],
-        &[
-            (asset1.clone(), 40_000_000),
-            (asset2.clone(), 40_000_000),
-            (asset3.clone(), 20_000_000),
-        ],
-        100_000_000,
-    );
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 10i128.pow(14));
-    prices.set(asset2.clone(), 10i128.pow(14));
-    prices.set(asset3.clone(), 10i128.pow(14));
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
-    assert_eq!(trades.get(asset1).unwrap(), 10_000_000);
-    assert_eq!(trades.get(asset2).unwrap(), -10_000_000);
-    assert!(!trades.contains_key(asset3));
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let result = client.try_deposit(&pid, &asset, &-100, &String::from_str(&env, ""));
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    assert_eq!(client.get_portfolio(&pid).current_balances.get(asset), None);
 }
 
 #[test]
-fn test_calculate_rebalance_trades_exact_boundary() {
+fn test_sweep_untracked_returns_only_non_target_assets() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    let asset3 = Address::generate(&env);
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
+    let tracked = create_token(&env);
+    let untracked = create_token(&env);
     let mut allocations = Map::new(&env);
-    allocations.set(asset1.clone(), 4000);
-    allocations.set(asset2.clone(), 3000);
-    allocations.set(asset3.clone(), 3000);
+    allocations.set(tracked.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_custody_mode(&pid, &CustodyMode::ContractCustody);
+
+    mint(&env, &tracked, &user, 100);
+    mint(&env, &untracked, &user, 40);
+    client.deposit(&pid, &tracked, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &untracked, &40, &String::from_str(&env, ""));
+
+    assert_eq!(client.sweep_untracked(&pid, &untracked), 40);
+    assert_eq!(token_balance(&env, &untracked, &user), 40);
+    assert_eq!(token_balance(&env, &untracked, &contract_id), 0);
+    let portfolio = client.get_portfolio(&pid);
+    assert!(portfolio.current_balances.get(untracked.clone()).is_none());
+    assert_eq!(portfolio.current_balances.get(tracked.clone()), Some(100));
 
-    let mut balances = Map::new(&env);
-    let target1 = 40_000_000i128;
-    let target2 = 30_000_000i128;
-    let target3 = 30_000_000i128;
+    assert_eq!(
+        client.try_sweep_untracked(&pid, &tracked),
+        Err(Ok(Error::AssetNotAllowed))
+    );
+    assert_eq!(
+        client.try_sweep_untracked(&pid, &untracked),
+        Err(Ok(Error::InsufficientBalance))
+    );
+}
 
-    balances.set(asset1.clone(), target1 - MIN_TRADE_AMOUNT_STROOPS);
-    balances.set(asset2.clone(), target2 - (MIN_TRADE_AMOUNT_STROOPS - 1));
-    balances.set(asset3.clone(), target3 - (MIN_TRADE_AMOUNT_STROOPS + 1));
+#[test]
+fn test_deposit_normalizes_balances_by_token_decimals() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
 
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations,
-        current_balances: balances,
-        asset_decimals,
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: 100_000_000i128,
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+    let six = env.register_contract(None, decimals_token::DecimalsToken);
+    decimals_token::DecimalsTokenClient::new(&env, &six).set_decimals(&6);
+    let seven = env.register_contract(None, decimals_token::DecimalsToken);
+    decimals_token::DecimalsTokenClient::new(&env, &seven).set_decimals(&7);
 
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), 10i128.pow(14));
-    prices.set(asset2.clone(), 10i128.pow(14));
-    prices.set(asset3.clone(), 10i128.pow(14));
+    let mut allocations = Map::new(&env);
+    allocations.set(six.clone(), 5000);
+    allocations.set(seven.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
+    // One whole token of each, both priced at 100.
+    client.deposit(&pid, &six, &1_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &seven, &10_000_000, &String::from_str(&env, ""));
 
-    assert!(!trades.contains_key(asset1));
-    assert!(!trades.contains_key(asset2));
-    assert_eq!(trades.get(asset3).unwrap(), MIN_TRADE_AMOUNT_STROOPS + 1);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.asset_decimals.get(six.clone()), Some(6));
+    assert_eq!(portfolio.asset_decimals.get(seven.clone()), Some(7));
+    assert_eq!(portfolio.total_value, 2 * 100 * 10i128.pow(VALUE_DECIMALS));
+
+    let allocations_now = client.get_current_allocations(&pid);
+    assert_eq!(allocations_now.get(six), Some(5000));
+    assert_eq!(allocations_now.get(seven), Some(5000));
+    assert!(client.preview_rebalance_trades(&pid).is_empty());
 }
 
 #[test]
-fn test_calculate_rebalance_trades_five_asset_rebalance_path() {
+fn test_close_portfolio_returns_all_balances_and_deactivates() {
     let env = Env::default();
-    let a1 = Address::generate(&env);
-    let a2 = Address::generate(&env);
-    let a3 = Address::generate(&env);
-    let a4 = Address::generate(&env);
-    let a5 = Address::generate(&env);
-    let portfolio = build_trade_test_portfolio(
-        &env,
-        &[
-            (a1.clone(), 2000),
-            (a2.clone(), 2000),
-            (a3.clone(), 2000),
-            (a4.clone(), 2000),
-            (a5.clone(), 2000),
-        ],
-        &[
-            (a1.clone(), 300_000_000),
-            (a2.clone(), 50_000_000),
-            (a3.clone(), 50_000_000),
-            (a4.clone(), 50_000_000),
-            (a5.clone(), 50_000_000),
-        ],
-        500_000_000,
-    );
-    let mut prices = Map::new(&env);
-    for asset in vec![
-        &env,
-        a1.clone(),
-        a2.clone(),
-        a3.clone(),
-        a4.clone(),
-        a5.clone(),
-    ]
-    .iter()
-    {
-        prices.set(asset.clone(), 10i128.pow(14));
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    let untracked = create_token(&env);
+    mint(&env, &untracked, &user, 40);
+    client.deposit(&pid, &untracked, &40, &String::from_str(&env, ""));
+    let user_asset2_before = token_balance(&env, &asset2, &user);
+
+    let returned = client.close_portfolio(&pid);
+    assert_eq!(returned.get(asset1.clone()), Some(200_000_000));
+    assert_eq!(returned.get(asset2.clone()), Some(100_000_000));
+    assert_eq!(returned.get(untracked.clone()), Some(40));
+
+    for asset in [&asset1, &asset2, &untracked] {
+        assert_eq!(token_balance(&env, asset, &contract_id), 0);
     }
+    assert_eq!(token_balance(&env, &asset1, &user), 200_000_000);
+    assert_eq!(
+        token_balance(&env, &asset2, &user),
+        user_asset2_before + 100_000_000
+    );
+    assert_eq!(token_balance(&env, &untracked, &user), 40);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
-    assert_eq!(trades.get(a1).unwrap(), -200_000_000);
-    assert_eq!(trades.get(a2).unwrap(), 50_000_000);
-    assert_eq!(trades.get(a3).unwrap(), 50_000_000);
-    assert_eq!(trades.get(a4).unwrap(), 50_000_000);
-    assert_eq!(trades.get(a5).unwrap(), 50_000_000);
+    let portfolio = client.get_portfolio(&pid);
+    assert!(portfolio.current_balances.is_empty());
+    assert_eq!(portfolio.total_value, 0);
+    assert!(!portfolio.is_active);
+    assert!(!client.get_user_portfolios(&user).contains(pid));
+
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("closed"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
 }
 
 #[test]
-fn test_calculate_rebalance_trades_price_precision_14_decimals_edge_case() {
+fn test_self_custody_keeps_tokens_with_owner() {
     let env = Env::default();
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    let precise_price = 123_456_789_012_345i128;
-    let target_balance = 100_000_000i128;
-    let portfolio = build_trade_test_portfolio(
-        &env,
-        &[(asset1.clone(), 5000), (asset2.clone(), 5000)],
-        &[
-            (
-                asset1.clone(),
-                target_balance - (MIN_TRADE_AMOUNT_STROOPS + 5),
-            ),
-            (
-                asset2.clone(),
-                target_balance + (MIN_TRADE_AMOUNT_STROOPS + 5),
-            ),
-        ],
-        246_913_578,
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(
+        client.get_portfolio(&pid).custody_mode,
+        CustodyMode::SelfCustody
     );
-    let mut prices = Map::new(&env);
-    prices.set(asset1.clone(), precise_price);
-    prices.set(asset2.clone(), precise_price);
 
-    let trades = crate::portfolio::calculate_rebalance_trades(&env, &portfolio, &prices);
-    let expected_target_value = (portfolio.total_value * 5000) / 10000;
-    let expected_target_balance = crate::portfolio::value_to_balance(
-        expected_target_value,
-        precise_price,
-        DEFAULT_ASSET_DECIMALS,
+    mint(&env, &asset1, &user, 200_000_000);
+    mint(&env, &asset2, &user, 100_000_000);
+    client.deposit(&pid, &asset1, &200_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
+    assert_eq!(token_balance(&env, &asset1, &contract_id), 0);
+    assert_eq!(token_balance(&env, &asset1, &user), 200_000_000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result.trades.get(asset1.clone()), Some(-50_000_000));
+    assert_eq!(result.trades.get(asset2.clone()), Some(50_000_000));
+    assert_eq!(token_balance(&env, &asset1, &user), 200_000_000);
+    assert_eq!(token_balance(&env, &asset2, &user), 100_000_000);
+    assert_eq!(token_balance(&env, &asset2, &contract_id), 0);
+
+    client.withdraw(&pid, &asset1, &150_000_000);
+    assert_eq!(token_balance(&env, &asset1, &user), 200_000_000);
+}
+
+#[test]
+fn test_contract_custody_moves_tokens_on_deposit_and_withdraw() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_custody_mode(&pid, &CustodyMode::ContractCustody);
+
+    mint(&env, &asset, &user, 1_000);
+    client.deposit(&pid, &asset, &1_000, &String::from_str(&env, ""));
+    assert_eq!(token_balance(&env, &asset, &contract_id), 1_000);
+    assert_eq!(token_balance(&env, &asset, &user), 0);
+    assert_eq!(
+        client.try_set_custody_mode(&pid, &CustodyMode::SelfCustody),
+        Err(Ok(Error::CustodyModeLocked))
     );
-    let expected_buy = expected_target_balance - (target_balance - (MIN_TRADE_AMOUNT_STROOPS + 5));
-    let expected_sell = expected_target_balance - (target_balance + (MIN_TRADE_AMOUNT_STROOPS + 5));
-    assert_eq!(trades.get(asset1).unwrap(), expected_buy);
-    assert_eq!(trades.get(asset2).unwrap(), expected_sell);
+
+    client.withdraw(&pid, &asset, &400);
+    assert_eq!(token_balance(&env, &asset, &contract_id), 600);
+    assert_eq!(token_balance(&env, &asset, &user), 400);
+}
+
+#[test]
+fn test_check_rebalance_needed_no_drift() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+
+    assert!(!client.check_rebalance_needed(&pid));
+}
+
+#[test]
+fn test_check_rebalance_needed_with_drift() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 1000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.deposit(&pid, &asset1, &200, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+
+    assert!(client.check_rebalance_needed(&pid));
+}
+
+#[test]
+fn test_rebalance_status_cooldown_needed_and_paused() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Cooldown);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp += REBALANCE_COOLDOWN_SECONDS;
+    });
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Needed);
+    assert!(client.check_rebalance_needed(&pid));
+
+    client.pause();
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Paused);
+    client.unpause();
+
+    client.set_emergency_stop(&true);
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Paused);
+    client.set_emergency_stop(&false);
+
+    client.pause_portfolio(&pid, &PauseReason::UserPaused);
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Paused);
+}
+
+#[test]
+fn test_last_rebalance_helpers_track_ledger_time() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(client.get_last_rebalance(&pid), 10000);
+    assert_eq!(client.seconds_since_last_rebalance(&pid), 0);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    assert_eq!(
+        client.seconds_since_last_rebalance(&pid),
+        REBALANCE_COOLDOWN_SECONDS
+    );
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp += 7200;
+    });
+    assert_eq!(
+        client.get_last_rebalance(&pid),
+        10000 + REBALANCE_COOLDOWN_SECONDS
+    );
+    assert_eq!(client.seconds_since_last_rebalance(&pid), 7200);
+    assert_eq!(
+        client.try_seconds_since_last_rebalance(&999),
+        Err(Ok(Error::PortfolioNotFound))
+    );
+}
+
+#[test]
+fn test_can_rebalance_now_flips_at_eligibility_timestamp() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    let eligible_at = client.next_rebalance_eligible_at(&pid);
+    assert_eq!(eligible_at, 10000 + REBALANCE_COOLDOWN_SECONDS);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = eligible_at - 1;
+    });
+    assert!(!client.can_rebalance_now(&pid));
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::CooldownActive))
+    );
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = eligible_at;
+    });
+    assert!(client.can_rebalance_now(&pid));
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    // Balanced now, and the cooldown restarts from this rebalance.
+    assert!(!client.can_rebalance_now(&pid));
+    assert_eq!(
+        client.next_rebalance_eligible_at(&pid),
+        eligible_at + REBALANCE_COOLDOWN_SECONDS
+    );
+}
+
+#[test]
+fn test_rebalance_status_within_threshold() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp += REBALANCE_COOLDOWN_SECONDS;
+    });
+    assert_eq!(
+        client.rebalance_status(&pid),
+        RebalanceStatus::WithinThreshold
+    );
+}
+
+#[test]
+fn test_rebalance_status_stale_prices() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_stale_prices::ReflectorWithStalePrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp += REBALANCE_COOLDOWN_SECONDS;
+    });
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Stale);
+}
+
+#[test]
+fn test_get_current_allocations_reports_live_shares() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(client.get_current_allocations(&pid).len(), 0);
+
+    client.deposit(&pid, &asset1, &2550, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &7450, &String::from_str(&env, ""));
+
+    let current = client.get_current_allocations(&pid);
+    assert_eq!(current.get(asset1).unwrap(), 2550);
+    assert_eq!(current.get(asset2).unwrap(), 7450);
+}
+
+#[test]
+fn test_get_asset_drifts_signs_and_magnitudes() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &300, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+
+    // 75% / 25% against 50% / 50% targets.
+    let drifts = client.get_asset_drifts(&pid);
+    assert_eq!(drifts.get(asset1), Some(2500));
+    assert_eq!(drifts.get(asset2), Some(-2500));
+}
+
+#[test]
+fn test_get_current_allocations_missing_price_returns_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let reflector =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+
+    reflector.set_missing_asset(&asset2);
+
+    let result = client.try_get_current_allocations(&pid);
+    assert_eq!(result, Err(Ok(Error::StaleData)));
+}
+
+#[test]
+fn test_check_rebalance_needed_missing_price_returns_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let reflector =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+
+    reflector.set_missing_asset(&asset2);
+
+    let result = client.try_check_rebalance_needed(&pid);
+    assert_eq!(result, Err(Ok(Error::StaleData)));
+}
+
+#[test]
+fn test_check_rebalance_needed_stale_price_returns_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_stale_prices::ReflectorWithStalePrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+
+    let result = client.try_check_rebalance_needed(&pid);
+    assert_eq!(result, Err(Ok(Error::StaleData)));
+}
+
+#[test]
+fn test_portfolio_value_with_fresh_price_succeeds() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+
+    let result = client.try_get_portfolio_value_usd(&pid);
+    assert_eq!(result.unwrap().unwrap().total_usd_value, 10_000);
+}
+
+#[test]
+fn test_get_portfolio_value_known_balances() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &250, &String::from_str(&env, ""));
+
+    // Both assets are priced at 100.
+    let before = client.get_portfolio(&pid);
+    assert_eq!(client.get_portfolio_value(&pid), 35_000);
+    assert_eq!(client.get_portfolio(&pid), before);
+}
+
+#[test]
+fn test_portfolio_value_scales_by_oracle_decimals() {
+    for decimals in [7u32, 14] {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, PortfolioRebalancer);
+        let client = PortfolioRebalancerClient::new(&env, &contract_id);
+        let reflector_id =
+            env.register_contract(None, reflector_with_decimals::ReflectorWithDecimals);
+        reflector_with_decimals::ReflectorWithDecimalsClient::new(&env, &reflector_id)
+            .set_decimals(&decimals);
+        let admin = Address::generate(&env);
+        let user = Address::generate(&env);
+        client.initialize(&admin, &reflector_id);
+
+        let asset1 = create_token(&env);
+        let asset2 = create_token(&env);
+        let mut allocations = Map::new(&env);
+        allocations.set(asset1.clone(), 5000);
+        allocations.set(asset2.clone(), 5000);
+        let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+        client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+        client.deposit(&pid, &asset2, &250, &String::from_str(&env, ""));
+
+        // The same price of 100 must value identically at either precision.
+        assert_eq!(client.get_portfolio_value(&pid), 35_000);
+    }
+}
+
+#[test]
+fn test_quote_asset_converts_valuation_through_cross_rate() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_cross_rates::ReflectorWithCrossRates);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let in_base = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let in_eur = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    for pid in [in_base, in_eur] {
+        client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+        client.deposit(&pid, &asset2, &250, &String::from_str(&env, ""));
+    }
+
+    let usd = Asset::Other(Symbol::new(&env, "USD"));
+    let eur = Asset::Other(Symbol::new(&env, "EUR"));
+    assert_eq!(client.get_portfolio(&in_base).quote_asset, usd);
+    client.set_quote_asset(&in_eur, &eur);
+
+    // 350 units at 100 USD is 35_000 USD, or 17_500 EUR at 2 USD per EUR.
+    assert_eq!(client.get_portfolio_value(&in_base), 35_000);
+    assert_eq!(client.get_portfolio_value(&in_eur), 17_500);
+    assert_eq!(client.get_portfolio(&in_eur).total_value, 17_500);
+    let valuation = client.get_portfolio_value_usd(&in_eur);
+    assert_eq!(valuation.total_usd_value, 17_500);
+    assert_eq!(
+        valuation.assets.get(0).unwrap().oracle_price,
+        50_00000000000000
+    );
+
+    // Choosing the base explicitly falls back to unconverted prices.
+    client.set_quote_asset(&in_eur, &usd);
+    assert_eq!(client.get_portfolio_value(&in_eur), 35_000);
+
+    assert_eq!(
+        client.try_set_quote_asset(&in_eur, &Asset::Other(Symbol::new(&env, "JPY"))),
+        Err(Ok(Error::MissingPrice))
+    );
+}
+
+#[test]
+fn test_base_asset_override_sets_numeraire_of_new_portfolios() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_cross_rates::ReflectorWithCrossRates);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_base_asset_override(), None);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let in_base = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let eur = Asset::Other(Symbol::new(&env, "EUR"));
+    assert_eq!(
+        client.try_set_base_asset_override(&Some(Asset::Other(Symbol::new(&env, "JPY")))),
+        Err(Ok(Error::MissingPrice))
+    );
+    client.set_base_asset_override(&Some(eur.clone()));
+    assert_eq!(client.get_base_asset_override(), Some(eur.clone()));
+    let pinned = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(client.get_portfolio(&pinned).quote_asset, eur);
+
+    // 350 units at 100 USD is 35_000 USD, or 17_500 EUR at 2 USD per EUR.
+    for pid in [in_base, pinned] {
+        client.deposit(&pid, &asset, &350, &String::from_str(&env, ""));
+    }
+    assert_eq!(client.get_portfolio_value(&in_base), 35_000);
+    assert_eq!(client.get_portfolio_value(&pinned), 17_500);
+
+    client.set_base_asset_override(&None);
+    assert_eq!(client.get_base_asset_override(), None);
+    let unpinned = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(
+        client.get_portfolio(&unpinned).quote_asset,
+        Asset::Other(Symbol::new(&env, "USD"))
+    );
+}
+
+#[test]
+fn test_get_portfolio_value_missing_price_returns_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let reflector =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+
+    reflector.set_missing_asset(&asset2);
+
+    assert_eq!(
+        client.try_get_portfolio_value(&pid),
+        Err(Ok(Error::StaleData))
+    );
+}
+
+#[test]
+fn test_prices_fresh_requires_every_target_feed_fresh() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_stale_asset::ReflectorWithStaleAsset);
+    let reflector =
+        reflector_with_stale_asset::ReflectorWithStaleAssetClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let fresh = create_token(&env);
+    let stale = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(fresh.clone(), 5000);
+    allocations.set(stale.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert!(client.prices_fresh(&pid, &3600));
+
+    reflector.set_stale_asset(&stale);
+    assert!(!client.prices_fresh(&pid, &3600));
+    // A looser bound accepts the two-hour-old tick.
+    assert!(client.prices_fresh(&pid, &7200));
+    assert_eq!(
+        client.try_prices_fresh(&99, &3600),
+        Err(Ok(Error::PortfolioNotFound))
+    );
+}
+
+#[test]
+fn test_portfolio_value_falls_back_to_secondary_reflector() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let primary_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let primary =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &primary_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &primary_id);
+    assert_eq!(client.get_secondary_reflector(), None);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+    let full_value = client.get_portfolio_value(&pid);
+
+    primary.set_missing_asset(&asset2);
+
+    // A secondary that lacks the same price cannot help.
+    let blind_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &blind_id)
+        .set_missing_asset(&asset2);
+    client.set_secondary_reflector(&Some(blind_id));
+    assert_eq!(
+        client.try_get_portfolio_value(&pid),
+        Err(Ok(Error::StaleData))
+    );
+
+    let secondary_id = env.register_contract(None, reflector_contract::MockReflector);
+    client.set_secondary_reflector(&Some(secondary_id.clone()));
+    assert_eq!(client.get_secondary_reflector(), Some(secondary_id));
+    assert_eq!(client.get_portfolio_value(&pid), full_value);
+    assert_eq!(client.get_asset_value(&pid, &asset2), full_value / 2);
+
+    client.set_secondary_reflector(&None);
+    assert_eq!(
+        client.try_get_portfolio_value(&pid),
+        Err(Ok(Error::StaleData))
+    );
+}
+
+#[test]
+fn test_rebalance_prices_asset_from_secondary_reflector() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let primary_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let primary =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &primary_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &primary_id);
+    let (pid, _, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15_000;
+    });
+
+    primary.set_missing_asset(&asset2);
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::MissingPrice))
+    );
+
+    let secondary_id = env.register_contract(None, reflector_contract::MockReflector);
+    client.set_secondary_reflector(&Some(secondary_id));
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result.total_value, 30_000_000_000);
+    assert_eq!(result.trades.get(asset2.clone()), Some(50_000_000));
+}
+
+#[test]
+fn test_asset_values_sum_to_portfolio_value() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let reflector =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let unheld = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 4000);
+    allocations.set(asset2.clone(), 3000);
+    allocations.set(unheld.clone(), 3000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &123_456_789, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &98_765, &String::from_str(&env, ""));
+
+    let value1 = client.get_asset_value(&pid, &asset1);
+    let value2 = client.get_asset_value(&pid, &asset2);
+    assert_eq!(value1, 12_345_678_900);
+    assert_eq!(value2, 9_876_500);
+    assert_eq!(client.get_asset_value(&pid, &unheld), 0);
+    assert_eq!(value1 + value2, client.get_portfolio_value(&pid));
+
+    reflector.set_missing_asset(&asset2);
+    assert_eq!(
+        client.try_get_asset_value(&pid, &asset2),
+        Err(Ok(Error::StaleData))
+    );
+}
+
+#[test]
+fn test_portfolio_value_with_aged_price_returns_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_stale_prices::ReflectorWithStalePrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+
+    let result = client.try_get_portfolio_value_usd(&pid);
+    assert_eq!(result, Err(Ok(Error::StaleData)));
+}
+
+#[test]
+fn test_twap_rebalance_with_stale_latest_tick_returns_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    // Latest ticks are two hours old while `twap` still returns an average.
+    let reflector_id =
+        env.register_contract(None, reflector_with_stale_prices::ReflectorWithStalePrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2, 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100_000_000, &String::from_str(&env, ""));
+    client.set_twap_records(&pid, &5);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    assert_eq!(
+        client.try_preview_rebalance_trades(&pid),
+        Err(Ok(Error::StaleData))
+    );
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::StaleData))
+    );
+}
+
+#[test]
+fn test_execute_rebalance_success() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let actual_balances = Map::new(&env);
+    client.execute_rebalance(&pid, &actual_balances);
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.last_rebalance, 15000);
+}
+
+#[test]
+fn test_rebalance_history_keeps_most_recent_entries() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(client.get_rebalance_history(&pid).len(), 0);
+
+    let rebalances = MAX_REBALANCE_HISTORY as u64 + 5;
+    for i in 0..rebalances {
+        if i > 0 {
+            mint(&env, &asset1, &user, 100_000_000);
+            client.deposit(&pid, &asset1, &100_000_000, &String::from_str(&env, ""));
+        }
+        mint(&env, &asset2, &user, 100_000_000);
+        env.ledger().with_mut(|li| {
+            li.timestamp = 10000 + (i + 1) * REBALANCE_COOLDOWN_SECONDS;
+        });
+        client.execute_rebalance(&pid, &Map::new(&env));
+    }
+
+    let history = client.get_rebalance_history(&pid);
+    assert_eq!(history.len(), MAX_REBALANCE_HISTORY);
+    let first = history.first().unwrap();
+    let last = history.last().unwrap();
+    assert_eq!(first.timestamp, 10000 + 6 * REBALANCE_COOLDOWN_SECONDS);
+    assert_eq!(
+        last.timestamp,
+        10000 + rebalances * REBALANCE_COOLDOWN_SECONDS
+    );
+    assert_eq!(last.trade_count, 2);
+    assert_eq!(last.total_value, client.get_portfolio(&pid).total_value);
+    assert_eq!(first.seq, 5);
+    assert_eq!(last.seq, rebalances as u32 - 1);
+
+    // Price snapshots are trimmed with the history they belong to.
+    env.as_contract(&contract_id, || {
+        let storage = env.storage().persistent();
+        assert!(!storage.has(&DataKey::RebalancePrices(pid, 4)));
+        assert!(storage.has(&DataKey::RebalancePrices(pid, 5)));
+    });
+    assert_eq!(
+        client.try_get_rebalance_prices(&pid, &MAX_REBALANCE_HISTORY),
+        Err(Ok(Error::RebalanceRecordNotFound))
+    );
+}
+
+#[test]
+fn test_rebalance_captures_oracle_prices() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let reflector = reflector_contract::MockReflectorClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    mint(&env, &asset2, &user, 100_000_000);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    let captured = client.get_rebalance_prices(&pid, &0);
+    assert_eq!(captured.len(), 2);
+    for asset in [asset1, asset2] {
+        let oracle = reflector
+            .lastprice(&crate::reflector::Asset::Stellar(asset.clone()))
+            .unwrap();
+        assert_eq!(captured.get(asset).unwrap(), oracle);
+    }
+}
+
+#[test]
+fn test_rebalance_continues_past_failed_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let broken = env.register_contract(None, failing_token::FailingToken);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 3333);
+    allocations.set(asset2.clone(), 3333);
+    allocations.set(broken.clone(), 3334);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_custody_mode(&pid, &CustodyMode::ContractCustody);
+
+    mint(&env, &asset1, &user, 200_000_000);
+    mint(&env, &asset2, &user, 200_000_000);
+    client.deposit(&pid, &asset1, &200_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &broken, &100_000_000, &String::from_str(&env, ""));
+    failing_token::FailingTokenClient::new(&env, &broken).disable();
+
+    // The overweight asset is corrected first.
+    let planned = client.preview_rebalance_trades(&pid);
+    let drifts = client.get_asset_drifts(&pid);
+    let ordered = portfolio::order_trades_by_drift(&env, &planned, &drifts);
+    assert_eq!(ordered.first().unwrap().0, asset1);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+    assert!(result.trades.contains_key(asset1.clone()));
+    assert!(result.trades.contains_key(asset2.clone()));
+    assert!(!result.trades.contains_key(broken.clone()));
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(
+        portfolio.current_balances.get(broken.clone()),
+        Some(100_000_000)
+    );
+    let asset1_balance = portfolio.current_balances.get(asset1.clone()).unwrap();
+    assert!(asset1_balance < 200_000_000);
+    assert_eq!(token_balance(&env, &asset1, &contract_id), asset1_balance);
+    assert_eq!(portfolio.last_rebalance, 10000 + REBALANCE_COOLDOWN_SECONDS);
+
+    let record = client.get_rebalance_history(&pid).last().unwrap();
+    assert_eq!(record.trade_count, 2);
+    assert_eq!(record.failed_assets, vec![&env, broken]);
+}
+
+#[test]
+fn test_execute_rebalance_returns_applied_trades() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    let before = client.get_portfolio(&pid).current_balances;
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+
+    assert_eq!(result.trades.len(), 2);
+    assert_eq!(result.trades.get(asset1.clone()).unwrap(), -50_000_000);
+    assert_eq!(result.trades.get(asset2.clone()).unwrap(), 50_000_000);
+
+    let portfolio = client.get_portfolio(&pid);
+    for (asset, delta) in result.trades.iter() {
+        let applied =
+            portfolio.current_balances.get(asset.clone()).unwrap() - before.get(asset).unwrap();
+        assert_eq!(applied, delta);
+    }
+    assert_eq!(result.total_value, 30_000_000_000);
+    assert_eq!(result.total_value, portfolio.total_value);
+}
+
+#[test]
+fn test_execute_rebalance_transfers_tokens_toward_targets() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    // Sell side: 50M of asset1 leaves custody for the owner.
+    assert_eq!(token_balance(&env, &asset1, &contract_id), 150_000_000);
+    assert_eq!(token_balance(&env, &asset1, &user), 50_000_000);
+    // Buy side: 50M of asset2 moves from the owner into custody.
+    assert_eq!(token_balance(&env, &asset2, &contract_id), 150_000_000);
+    assert_eq!(token_balance(&env, &asset2, &user), 50_000_000);
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset1).unwrap(), 150_000_000);
+    assert_eq!(portfolio.current_balances.get(asset2).unwrap(), 150_000_000);
+}
+
+#[test]
+fn test_min_rebalance_value_skips_portfolios_below_floor() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    assert_eq!(
+        client.get_min_rebalance_value(),
+        DEFAULT_MIN_REBALANCE_VALUE
+    );
+    assert_eq!(
+        client.try_set_min_rebalance_value(&-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+
+    let (pid, asset1, _) = create_drifted_token_portfolio(&env, &client, &user);
+    let value = client.get_portfolio_value(&pid);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    // One unit short of the floor: the drifted portfolio is left alone.
+    client.set_min_rebalance_value(&(value + 1));
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::RebalanceNotNeeded))
+    );
+    assert_eq!(token_balance(&env, &asset1, &contract_id), 200_000_000);
+
+    client.set_min_rebalance_value(&value);
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(token_balance(&env, &asset1, &contract_id), 150_000_000);
+}
+
+#[test]
+fn test_execute_rebalance_blocks_reentry_from_token_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    // The overweight asset is sold first, so its transfer is the callback.
+    let hostile = env.register_contract(None, reentrant_token::ReentrantToken);
+    let hostile_client = reentrant_token::ReentrantTokenClient::new(&env, &hostile);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(hostile.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_custody_mode(&pid, &CustodyMode::ContractCustody);
+
+    mint(&env, &asset2, &user, 200_000_000);
+    client.deposit(&pid, &hostile, &200_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
+    hostile_client.set_target(&contract_id, &pid);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    // The host refuses the nested call outright; had it run, the saved
+    // `last_rebalance` would have tripped the cooldown instead.
+    assert_eq!(hostile_client.reentry_blocked(), Some(true));
+    assert_eq!(client.get_rebalance_history(&pid).len(), 1);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.last_rebalance, 15000);
+    assert_eq!(
+        portfolio.current_balances.get(hostile).unwrap(),
+        150_000_000
+    );
+    assert_eq!(portfolio.current_balances.get(asset2).unwrap(), 150_000_000);
+}
+
+#[test]
+fn test_simulate_rebalance_lands_within_rounding_of_targets() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let asset3 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 3334);
+    allocations.set(asset2.clone(), 3333);
+    allocations.set(asset3.clone(), 3333);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &300_000_007, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset3, &50_000_000, &String::from_str(&env, ""));
+
+    let before = client.get_portfolio(&pid);
+    let simulated = client.simulate_rebalance(&pid);
+    assert_eq!(client.get_portfolio(&pid), before);
+
+    for (asset, target) in allocations.iter() {
+        let share = simulated.get(asset).unwrap();
+        assert!(share.abs_diff(target) <= 1, "{share} vs {target}");
+    }
+}
+
+#[test]
+fn test_preview_rebalance_trades_matches_executed_deltas() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let before = client.get_portfolio(&pid);
+    let preview = client.preview_rebalance_trades(&pid);
+    assert_eq!(client.get_portfolio(&pid), before);
+    assert_eq!(preview.get(asset1.clone()), Some(-50_000_000));
+    assert_eq!(preview.get(asset2.clone()), Some(50_000_000));
+
+    client.execute_rebalance(&pid, &Map::new(&env));
+    let after = client.get_portfolio(&pid);
+    for (asset, delta) in preview.iter() {
+        let old = before.current_balances.get(asset.clone()).unwrap_or(0);
+        let new = after.current_balances.get(asset).unwrap_or(0);
+        assert_eq!(new - old, delta);
+    }
+}
+
+#[test]
+fn test_half_ratio_rebalance_trades_half_the_deltas() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(
+        client.get_portfolio(&pid).rebalance_ratio_bps,
+        FULL_REBALANCE_RATIO_BPS
+    );
+    client.set_rebalance_ratio_bps(&pid, &5000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let preview = client.preview_rebalance_trades(&pid);
+    assert_eq!(preview.get(asset1.clone()), Some(-25_000_000));
+    assert_eq!(preview.get(asset2.clone()), Some(25_000_000));
+
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result.trades, preview);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset1).unwrap(), 175_000_000);
+    assert_eq!(portfolio.current_balances.get(asset2).unwrap(), 125_000_000);
+}
+
+#[test]
+fn test_set_rebalance_ratio_bps_rejects_out_of_range() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    assert_eq!(
+        client.try_set_rebalance_ratio_bps(&pid, &0),
+        Err(Ok(Error::InvalidRebalanceRatio))
+    );
+    assert_eq!(
+        client.try_set_rebalance_ratio_bps(&pid, &(FULL_REBALANCE_RATIO_BPS + 1)),
+        Err(Ok(Error::InvalidRebalanceRatio))
+    );
+}
+
+#[test]
+fn test_max_price_age_is_per_portfolio() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_aged_prices::ReflectorWithAgedPrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let strict = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let relaxed = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&strict, &asset, &100, &String::from_str(&env, ""));
+    client.deposit(&relaxed, &asset, &100, &String::from_str(&env, ""));
+
+    assert_eq!(
+        client.get_portfolio(&relaxed).max_price_age,
+        PRICE_MAX_AGE_SECONDS
+    );
+    client.set_max_price_age(&strict, &30);
+
+    // The same 60-second-old tick is stale for one portfolio and fresh for the other.
+    assert_eq!(
+        client.try_get_portfolio_value(&strict),
+        Err(Ok(Error::StaleData))
+    );
+    assert_eq!(
+        client.try_check_rebalance_needed(&strict),
+        Err(Ok(Error::StaleData))
+    );
+    assert_eq!(client.get_portfolio_value(&relaxed), 10_000);
+    assert!(!client.check_rebalance_needed(&relaxed));
+}
+
+#[test]
+fn test_set_max_price_age_rejects_out_of_range() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    assert_eq!(
+        client.try_set_max_price_age(&pid, &0),
+        Err(Ok(Error::InvalidMaxPriceAge))
+    );
+    assert_eq!(
+        client.try_set_max_price_age(&pid, &(PRICE_MAX_AGE_SECONDS + 1)),
+        Err(Ok(Error::InvalidMaxPriceAge))
+    );
+}
+
+#[test]
+fn test_create_portfolio_sets_max_price_age() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id =
+        env.register_contract(None, reflector_with_aged_prices::ReflectorWithAgedPrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let mut asset_decimals = Map::new(&env);
+    asset_decimals.set(asset.clone(), DEFAULT_ASSET_DECIMALS);
+    let strict = client.create_portfolio(
+        &user,
+        &allocations,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &Some(30),
+    );
+    assert_eq!(client.get_portfolio(&strict).max_price_age, 30);
+    client.deposit(&strict, &asset, &100, &String::from_str(&env, ""));
+    assert_eq!(
+        client.try_get_portfolio_value(&strict),
+        Err(Ok(Error::StaleData))
+    );
+
+    for max_price_age in [0, PRICE_MAX_AGE_SECONDS + 1] {
+        assert_eq!(
+            client.try_create_portfolio(
+                &user,
+                &allocations,
+                &asset_decimals,
+                &500,
+                &50,
+                &CURRENT_SLIPPAGE_POLICY_VERSION,
+                &Some(max_price_age),
+            ),
+            Err(Ok(Error::InvalidMaxPriceAge))
+        );
+    }
+}
+
+#[test]
+fn test_excessive_drift_blocks_rebalance_until_forced() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_max_drift_bps(), DEFAULT_MAX_DRIFT_BPS);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 2000);
+    allocations.set(asset2.clone(), 8000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    // Everything sits in asset1, 8000 bps away from its 20% target.
+    mint(&env, &asset1, &client.address, 100_000_000);
+    mint(&env, &asset2, &user, 100_000_000);
+    client.deposit(&pid, &asset1, &100_000_000, &String::from_str(&env, ""));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::ExcessiveDrift))
+    );
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10000);
+
+    let result = client.admin_force_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result.trades.get(asset1), Some(-80_000_000));
+    assert_eq!(result.trades.get(asset2), Some(80_000_000));
+}
+
+#[test]
+fn test_max_drift_bps_is_admin_configurable() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    assert_eq!(
+        client.try_set_max_drift_bps(&0),
+        Err(Ok(Error::InvalidMaxDrift))
+    );
+    assert_eq!(
+        client.try_set_max_drift_bps(&(ALLOCATION_DENOMINATOR + 1)),
+        Err(Ok(Error::InvalidMaxDrift))
+    );
+
+    // The drifted portfolio sits about 1666 bps off target.
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    client.set_max_drift_bps(&1000);
+    assert_eq!(client.get_max_drift_bps(), 1000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::ExcessiveDrift))
+    );
+    client.set_max_drift_bps(&2000);
+    client.execute_rebalance(&pid, &Map::new(&env));
+}
+
+#[test]
+fn test_keeper_can_rebalance() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let keeper = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(
+        client.try_keeper_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::NoKeeper))
+    );
+
+    client.set_keeper(&pid, &keeper);
+    assert_eq!(client.get_keeper(&pid), Some(keeper.clone()));
+    // The owner pre-approves the buy leg so the keeper can rebalance unattended.
+    token::Client::new(&env, &asset2).approve(&user, &contract_id, &50_000_000, &1_000);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let result = client.keeper_rebalance(&pid, &Map::new(&env));
+    assert_eq!(
+        env.auths()[0],
+        (
+            keeper.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    contract_id.clone(),
+                    Symbol::new(&env, "keeper_rebalance"),
+                    (pid, Map::<Address, i128>::new(&env)).into_val(&env),
+                )),
+                sub_invocations: std::vec![],
+            }
+        )
+    );
+    assert_eq!(result.trades.get(asset1), Some(-50_000_000));
+    assert_eq!(result.trades.get(asset2), Some(50_000_000));
+
+    client.revoke_keeper(&pid);
+    assert_eq!(client.get_keeper(&pid), None);
+}
+
+#[test]
+#[should_panic]
+fn test_keeper_cannot_deposit() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let keeper = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_keeper(&pid, &keeper);
+
+    let memo = String::from_str(&env, "");
+    client
+        .mock_auths(&[MockAuth {
+            address: &keeper,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "deposit",
+                args: (pid, asset.clone(), 100i128, memo.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .deposit(&pid, &asset, &100, &memo);
+}
+
+#[test]
+#[should_panic]
+fn test_keeper_cannot_update_allocations() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let keeper = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_keeper(&pid, &keeper);
+
+    let mut new_allocations = Map::new(&env);
+    new_allocations.set(asset1, 5000);
+    new_allocations.set(asset2, 5000);
+    client
+        .mock_auths(&[MockAuth {
+            address: &keeper,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "update_target_allocations",
+                args: (pid, new_allocations.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .update_target_allocations(&pid, &new_allocations);
+}
+
+#[test]
+fn test_execute_rebalance_emits_trade_event_per_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    let mut trade_events = 0;
+    for (emitter, topics, data) in env.events().all().iter() {
+        for (asset, delta) in [(&asset1, -50_000_000i128), (&asset2, 50_000_000)] {
+            if topics == (symbol_short!("trade"), asset.clone(), EVENT_TOPIC_VERSION).into_val(&env)
+            {
+                assert_eq!(emitter, contract_id);
+                let (event_pid, event_delta, event_price): (u64, i128, i128) = data.into_val(&env);
+                assert_eq!(event_pid, pid);
+                assert_eq!(event_delta, delta);
+                assert_eq!(event_price, 100_00000000000000);
+                trade_events += 1;
+            }
+        }
+    }
+    assert_eq!(trade_events, 2);
+
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("portfolio"),
+            Symbol::new(&env, "rebalanced"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+}
+
+#[test]
+fn test_events_carry_topic_version() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    let mut own_events = 0;
+    for (emitter, topics, _) in env.events().all().iter() {
+        // Token transfers are published by the asset contracts themselves.
+        if emitter != contract_id {
+            continue;
+        }
+        assert_eq!(topics.len(), 3);
+        let tag: Symbol = topics.last().unwrap().into_val(&env);
+        assert_eq!(tag, EVENT_TOPIC_VERSION);
+        own_events += 1;
+    }
+    assert!(own_events > 0);
+
+    let config = FeeConfig {
+        platform_name: String::from_str(&env, "Acme Vault"),
+        fee_bps: 0,
+        fee_recipient: Address::generate(&env),
+        enabled: false,
+    };
+    client.set_fee_config(&config);
+    let (_, topics, data) = env
+        .events()
+        .all()
+        .iter()
+        .filter(|(emitter, _, _)| emitter == &contract_id)
+        .last()
+        .unwrap();
+    assert_eq!(
+        topics,
+        (Symbol::new(&env, "FeeConfigUpdated"), EVENT_TOPIC_VERSION).into_val(&env)
+    );
+    let emitted: FeeConfig = data.into_val(&env);
+    assert_eq!(emitted, config);
+}
+
+#[test]
+fn test_portfolio_stats_accumulate_across_rebalances() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    mint(&env, &asset1, &user, 400_000_000);
+    mint(&env, &asset2, &user, 400_000_000);
+    assert_eq!(client.get_portfolio_stats(&pid), PortfolioStats::default());
+
+    let largest_drift = |client: &PortfolioRebalancerClient| -> u64 {
+        client
+            .get_asset_drifts(&pid)
+            .values()
+            .iter()
+            .map(|drift| drift.unsigned_abs() as u64)
+            .max()
+            .unwrap()
+    };
+
+    // 200/100 -> 150/150, then 250/150 -> 200/200, then 200/400 -> 300/300.
+    let mut drift_sum = 0;
+    for (step, top_up) in [
+        None,
+        Some((&asset1, 100_000_000)),
+        Some((&asset2, 200_000_000)),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if let Some((asset, amount)) = top_up {
+            client.deposit(&pid, asset, &amount, &String::from_str(&env, ""));
+        }
+        env.ledger().with_mut(|li| {
+            li.timestamp = 15000 + step as u64 * REBALANCE_COOLDOWN_SECONDS;
+        });
+        drift_sum += largest_drift(&client);
+        client.execute_rebalance(&pid, &Map::new(&env));
+        assert_eq!(
+            client.get_portfolio_stats(&pid).rebalance_count,
+            step as u32 + 1
+        );
+    }
+
+    let stats = client.get_portfolio_stats(&pid);
+    assert_eq!(stats.rebalance_count, 3);
+    // Trades of 50M, 50M and 100M units a side, each unit worth 100.
+    assert_eq!(stats.total_value_traded, 40_000_000_000);
+    assert_eq!(stats.total_drift_corrected_bps, drift_sum);
+    assert_eq!(stats.average_drift_corrected_bps, (drift_sum / 3) as u32);
+}
+
+#[test]
+fn test_execute_rebalance_without_trades_returns_not_needed() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100_000_000, &String::from_str(&env, ""));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::RebalanceNotNeeded)));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10000);
+}
+
+#[test]
+fn test_execute_rebalance_on_empty_portfolio_returns_zero_value() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 5000);
+    allocations.set(create_token(&env), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::ZeroPortfolioValue)));
+    assert_eq!(
+        client.try_preview_rebalance_trades(&pid),
+        Err(Ok(Error::ZeroPortfolioValue))
+    );
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10000);
+}
+
+#[test]
+fn test_fee_config_supports_platform_name_and_zero_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let recipient = Address::generate(&env);
+    let config = FeeConfig {
+        platform_name: String::from_str(&env, "Acme Vault"),
+        fee_bps: 0,
+        fee_recipient: recipient.clone(),
+        enabled: true,
+    };
+
+    client.set_fee_config(&config);
+
+    let persisted = client.get_fee_config();
+    assert_eq!(
+        persisted.platform_name,
+        String::from_str(&env, "Acme Vault")
+    );
+    assert_eq!(persisted.fee_bps, 0);
+    assert_eq!(persisted.fee_recipient, recipient);
+    assert!(persisted.enabled);
+}
+
+#[test]
+fn test_rebalance_applies_non_zero_fee_to_trade_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.set_custody_mode(&pid, &CustodyMode::ContractCustody);
+    mint(&env, &asset1, &user, 10_000_000_000);
+    mint(&env, &asset2, &user, 5_000_000_000);
+    client.deposit(&pid, &asset1, &10_000_000_000, &String::from_str(&env, ""));
+
+    let recipient = Address::generate(&env);
+    let config = FeeConfig {
+        platform_name: String::from_str(&env, "Acme Vault"),
+        fee_bps: 50,
+        fee_recipient: recipient.clone(),
+        enabled: true,
+    };
+    client.set_fee_config(&config);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(
+        portfolio.current_balances.get(asset1.clone()).unwrap(),
+        4_975_000_000
+    );
+    assert_eq!(
+        portfolio.current_balances.get(asset2.clone()).unwrap(),
+        4_975_000_000
+    );
+    assert_eq!(token_balance(&env, &asset1, &recipient), 25_000_000);
+    assert_eq!(token_balance(&env, &asset2, &recipient), 25_000_000);
+    assert_eq!(token_balance(&env, &asset2, &contract_id), 4_975_000_000);
+}
+
+#[test]
+fn test_rebalance_charges_treasury_fee() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_treasury_fee_bps(), 0);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    client.set_treasury(&treasury);
+    client.set_treasury_fee_bps(&100);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    // 1% of a 30_000_000_000 portfolio, paid in the largest holding (asset1).
+    assert_eq!(token_balance(&env, &asset1, &treasury), 3_000_000);
+    assert_eq!(token_balance(&env, &asset2, &treasury), 0);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.total_value, 29_700_000_000);
+    assert_eq!(
+        portfolio.current_balances.get(asset1.clone()).unwrap(),
+        148_500_000
+    );
+    assert_eq!(token_balance(&env, &asset1, &contract_id), 148_500_000);
+}
+
+#[test]
+fn test_rebalance_zero_treasury_fee_skips_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    client.set_treasury(&treasury);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+
+    assert_eq!(token_balance(&env, &asset1, &treasury), 0);
+    assert_eq!(token_balance(&env, &asset2, &treasury), 0);
+    assert_eq!(client.get_portfolio(&pid).total_value, 30_000_000_000);
+}
+
+#[test]
+fn test_set_treasury_fee_bps_rejects_above_cap() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let result = client.try_set_treasury_fee_bps(&(MAX_TREASURY_FEE_BPS + 1));
+    assert_eq!(result, Err(Ok(Error::InvalidFeeBps)));
+    assert_eq!(client.get_treasury_fee_bps(), 0);
+}
+
+#[test]
+fn test_execute_rebalance_cooldown() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10010;
+    });
+
+    let actual_balances = Map::new(&env);
+    let result = client.try_execute_rebalance(&pid, &actual_balances);
+    assert_eq!(result, Err(Ok(Error::CooldownActive)));
+}
+
+#[test]
+fn test_configured_rebalance_cooldown_expires() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_rebalance_cooldown(), REBALANCE_COOLDOWN_SECONDS);
+
+    client.set_rebalance_cooldown(&600);
+    assert_eq!(client.get_rebalance_cooldown(), 600);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10599;
+    });
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::CooldownActive)));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10600;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10600);
+
+    // With the cooldown disabled the next call gets past the cooldown check
+    // and only stops because the portfolio is already balanced.
+    client.set_rebalance_cooldown(&0);
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::RebalanceNotNeeded)));
+}
+
+#[test]
+fn test_min_rebalance_interval_blocks_drifted_portfolio() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(
+        client.try_set_min_rebalance_interval(&pid, &(MAX_REBALANCE_COOLDOWN_SECONDS + 1)),
+        Err(Ok(Error::InvalidCooldown))
+    );
+    client.set_min_rebalance_interval(&pid, &7_200);
+    assert_eq!(client.next_rebalance_eligible_at(&pid), 10000 + 7_200);
+
+    // Cooldown has expired and drift is high, but the two-hour interval has not.
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    assert!(client.check_rebalance_needed(&pid));
+    assert_eq!(client.rebalance_status(&pid), RebalanceStatus::Cooldown);
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::CooldownActive)));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + 7_200;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10000 + 7_200);
+}
+
+#[test]
+fn test_initial_grace_period_delays_only_first_rebalance() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(client.get_portfolio(&pid).initial_grace_seconds, 0);
+    assert_eq!(
+        client.try_set_initial_grace_period(&pid, &(MAX_REBALANCE_COOLDOWN_SECONDS + 1)),
+        Err(Ok(Error::InvalidCooldown))
+    );
+    client.set_initial_grace_period(&pid, &7_200);
+    assert_eq!(client.next_rebalance_eligible_at(&pid), 10000 + 7_200);
+
+    // The cooldown alone would allow it, but the portfolio is still funding.
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + REBALANCE_COOLDOWN_SECONDS;
+    });
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::CooldownActive))
+    );
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000 + 7_200;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(client.get_portfolio(&pid).initial_grace_seconds, 0);
+
+    // Later rebalances wait only for the ordinary cooldown.
+    assert_eq!(
+        client.next_rebalance_eligible_at(&pid),
+        10000 + 7_200 + REBALANCE_COOLDOWN_SECONDS
+    );
+}
+
+#[test]
+fn test_set_rebalance_cooldown_rejects_out_of_range() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let result = client.try_set_rebalance_cooldown(&(MAX_REBALANCE_COOLDOWN_SECONDS + 1));
+    assert_eq!(result, Err(Ok(Error::InvalidCooldown)));
+    assert_eq!(client.get_rebalance_cooldown(), REBALANCE_COOLDOWN_SECONDS);
+}
+
+#[test]
+fn test_emergency_stop() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.set_emergency_stop(&true);
+    let result = client.try_deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    assert_eq!(result, Err(Ok(Error::EmergencyStop)));
+    client.set_emergency_stop(&false);
+
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+}
+
+#[test]
+fn test_emergency_stop_blocks_rebalance_until_resumed() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert!(!client.is_emergency_stopped());
+
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    client.set_emergency_stop(&true);
+    assert!(client.is_emergency_stopped());
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::EmergencyStop)));
+
+    client.set_emergency_stop(&false);
+    assert!(!client.is_emergency_stopped());
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 15000);
+}
+
+#[test]
+fn test_stale_data() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    mod stale_reflector {
+        use crate::reflector::{Asset, PriceData};
+        use soroban_sdk::{contract, contractimpl, Env, Symbol, Vec};
+
+        #[contract]
+        pub struct StaleReflector;
+
+        #[contractimpl]
+        impl StaleReflector {
+            pub fn base(_env: Env) -> Asset {
+                Asset::Other(Symbol::new(&_env, "USD"))
+            }
+            pub fn assets(_env: Env) -> Vec<Asset> {
+                Vec::new(&_env)
+            }
+            pub fn decimals(_env: Env) -> u32 {
+                14
+            }
+            pub fn lastprice(env: Env, _asset: Asset) -> Option<PriceData> {
+                let current = env.ledger().timestamp();
+                Some(PriceData {
+                    price: 100_00000000000000,
+                    timestamp: current - 7200,
+                })
+            }
+            pub fn twap(_env: Env, _asset: Asset, _records: u32) -> Option<i128> {
+                Some(0)
+            }
+        }
+    }
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let stale_reflector_id = env.register_contract(None, stale_reflector::StaleReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &stale_reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let actual_balances = Map::new(&env);
+    let _result = client.try_execute_rebalance(&pid, &actual_balances);
+}
+
+#[test]
+fn test_edge_case_single_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    client.initialize(&Address::generate(&env), &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+
+    let owner = Address::generate(&env);
+    let pid = create_portfolio_with_defaults(&env, &client, &owner, &allocations, 500, 50);
+    assert!(!client.check_rebalance_needed(&pid));
+}
+
+#[test]
+fn test_portfolio_validation() {
+    let env = Env::default();
+    let mut allocations = Map::new(&env);
+
+    allocations.set(create_token(&env), 6000);
+    allocations.set(create_token(&env), 4000);
+    assert!(crate::portfolio::validate_allocations(&allocations));
+
+    let mut invalid_allocations = Map::new(&env);
+    invalid_allocations.set(create_token(&env), 6000);
+    invalid_allocations.set(create_token(&env), 3000);
+    assert!(!crate::portfolio::validate_allocations(
+        &invalid_allocations
+    ));
+}
+
+fn allocation_map_from_percentages(env: &Env, bps_values: &[u32]) -> Map<Address, u32> {
+    let mut allocations = Map::new(env);
+    for bps in bps_values {
+        allocations.set(Address::generate(env), *bps);
+    }
+    allocations
+}
+
+fn random_bps_with_target_sum(seed: &mut u64, count: usize, target_sum: u32) -> [u32; 12] {
+    let mut values = [0u32; 12];
+    let mut remaining = target_sum;
+    let limit = count.min(12);
+    for (i, value) in values.iter_mut().enumerate().take(limit) {
+        if i + 1 == limit {
+            *value = remaining;
+            break;
+        }
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        let next = if remaining == 0 {
+            0
+        } else {
+            ((*seed as u32) % (remaining + 1)).min(remaining)
+        };
+        *value = next;
+        remaining -= next;
+    }
+    values
+}
+
+#[test]
+fn test_validate_allocations_randomized_sum_10000_accepts_500_vectors() {
+    let env = Env::default();
+    let mut seed = 0xC0FFEEu64;
+    for _ in 0..500 {
+        let mut adjusted = [0u32; 10];
+        let mut remaining = 10000u32;
+        for (i, slot) in adjusted.iter_mut().enumerate() {
+            let slots_left = 10 - i;
+            if slots_left == 1 {
+                *slot = remaining;
+                break;
+            }
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let max_for_slot = remaining - (slots_left as u32 - 1);
+            let next = 1 + ((seed as u32) % max_for_slot);
+            *slot = next;
+            remaining -= next;
+        }
+        let allocations = allocation_map_from_percentages(&env, &adjusted);
+        assert!(crate::portfolio::validate_allocations(&allocations));
+    }
+}
+
+#[test]
+fn test_validate_allocations_randomized_sum_9999_rejects_500_vectors() {
+    let env = Env::default();
+    let mut seed = 0xBAD5EEDu64;
+    for _ in 0..500 {
+        let raw = random_bps_with_target_sum(&mut seed, 10, 9999);
+        let allocations = allocation_map_from_percentages(&env, &raw[..10]);
+        assert!(!crate::portfolio::validate_allocations(&allocations));
+    }
+}
+
+#[test]
+fn test_validate_allocations_randomized_sum_10001_rejects_500_vectors() {
+    let env = Env::default();
+    let mut seed = 0xDEADBEEFu64;
+    for _ in 0..500 {
+        let raw = random_bps_with_target_sum(&mut seed, 10, 10001);
+        let allocations = allocation_map_from_percentages(&env, &raw[..10]);
+        assert!(!crate::portfolio::validate_allocations(&allocations));
+    }
+}
+
+#[test]
+fn test_validate_allocations_empty_map_boundary() {
+    let env = Env::default();
+    let allocations = Map::new(&env);
+    assert!(!crate::portfolio::validate_allocations(&allocations));
+}
+
+#[test]
+fn test_validate_allocations_single_asset_full_boundary() {
+    let env = Env::default();
+    let allocations = allocation_map_from_percentages(&env, &[10000]);
+    assert!(crate::portfolio::validate_allocations(&allocations));
+}
+
+#[test]
+fn test_validate_allocations_ten_assets_equal_weight() {
+    let env = Env::default();
+    let allocations = allocation_map_from_percentages(
+        &env,
+        &[1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000, 1000],
+    );
+    assert!(crate::portfolio::validate_allocations(&allocations));
+}
+
+#[test]
+fn test_validate_allocations_fractional_three_way_split() {
+    let env = Env::default();
+    // 33.33% + 33.33% + 33.34% = 100.00%
+    let allocations = allocation_map_from_percentages(&env, &[3333, 3333, 3334]);
+    assert!(crate::portfolio::validate_allocations(&allocations));
+}
+
+#[test]
+fn test_validate_allocations_overflow_rejected() {
+    let env = Env::default();
+    let allocations = allocation_map_from_percentages(&env, &[u32::MAX, 1]);
+    assert!(!crate::portfolio::validate_allocations(&allocations));
+}
+
+#[test]
+fn test_create_portfolio_rejects_allocations_that_would_wrap_to_denominator() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    // u32::MAX + 10_001 wraps to exactly ALLOCATION_DENOMINATOR.
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), u32::MAX);
+    allocations.set(create_token(&env), ALLOCATION_DENOMINATOR + 1);
+    assert!(!crate::portfolio::validate_allocations(&allocations));
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::InvalidAllocation)));
+}
+
+fn build_trade_test_portfolio(
+    env: &Env,
+    allocations: &[(Address, u32)],
+    balances: &[(Address, i128)],
+    total_value: i128,
+) -> Portfolio {
+    let mut target_allocations = Map::new(env);
+    for (asset, bps) in allocations {
+        target_allocations.set(asset.clone(), *bps);
+    }
+    let mut current_balances = Map::new(env);
+    for (asset, balance) in balances {
+        current_balances.set(asset.clone(), *balance);
+    }
+    let asset_decimals = allocation_decimals(env, &target_allocations, DEFAULT_ASSET_DECIMALS);
+    Portfolio {
+        user: Address::generate(env),
+        target_allocations,
+        current_balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    }
+}
+
+#[test]
+fn test_calculate_rebalance_trades_excludes_below_minimum_stroops() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let asset3 = create_token(&env);
+    // Sits exactly on target so the weights can sum to 100%.
+    let on_target = create_token(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 2500);
+    allocations.set(asset2.clone(), 2500);
+    allocations.set(asset3.clone(), 2500);
+    allocations.set(on_target.clone(), 2500);
+
+    let target_balance = 50_000_000i128;
+    let mut balances = Map::new(&env);
+    balances.set(on_target.clone(), target_balance);
+    balances.set(
+        asset1.clone(),
+        target_balance - (MIN_TRADE_AMOUNT_STROOPS / 2),
+    );
+    balances.set(asset2.clone(), target_balance - MIN_TRADE_AMOUNT_STROOPS);
+    balances.set(
+        asset3.clone(),
+        target_balance - (MIN_TRADE_AMOUNT_STROOPS + 1),
+    );
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 4 * target_balance,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+    prices.set(asset3.clone(), 10i128.pow(14));
+    prices.set(on_target.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+    assert!(!trades.contains_key(asset1));
+    assert!(!trades.contains_key(asset2));
+    assert_eq!(trades.get(asset3).unwrap(), MIN_TRADE_AMOUNT_STROOPS + 1);
+}
+
+#[test]
+fn test_rounding_mode_controls_target_balance() {
+    let env = Env::default();
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+
+    let target_for = |rounding_mode: RoundingMode, total_value: i128, price: i128| {
+        let portfolio = Portfolio {
+            user: Address::generate(&env),
+            target_allocations: allocations.clone(),
+            current_balances: Map::new(&env),
+            asset_decimals: allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS),
+            rebalance_threshold: 500,
+            slippage_tolerance: 50,
+            slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+            last_rebalance: 0,
+            total_value,
+            is_active: true,
+            pause_reason: PauseReason::None,
+            twap_records: 0,
+            min_trade_amounts: Map::new(&env),
+            rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+            max_price_age: PRICE_MAX_AGE_SECONDS,
+            quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+            custody_mode: CustodyMode::SelfCustody,
+            no_trade_band_bps: 0,
+            max_twap_divergence_bps: 0,
+            rounding_mode,
+            max_trade_notional: 0,
+            min_interval_seconds: 0,
+            initial_grace_seconds: 0,
+            drift_scaling_cap_bps: 0,
+            min_balances: Map::new(&env),
+            fee_bps: 0,
+            fee_collector: None,
+            fee_asset: None,
+        };
+        let mut prices = Map::new(&env);
+        prices.set(asset.clone(), price * 10i128.pow(14));
+        crate::portfolio::calculate_rebalance_trades(
+            &env,
+            &portfolio,
+            &prices,
+            REFLECTOR_PRICE_DECIMALS,
+        )
+        .unwrap()
+        .get(asset.clone())
+        .unwrap()
+    };
+
+    // 200_000_000 / 3 = 66_666_666.67
+    assert_eq!(
+        target_for(RoundingMode::Truncate, 200_000_000, 3),
+        66_666_666
+    );
+    assert_eq!(target_for(RoundingMode::HalfUp, 200_000_000, 3), 66_666_667);
+    assert_eq!(
+        target_for(RoundingMode::Nearest, 200_000_000, 3),
+        66_666_667
+    );
+
+    // 100_000_001 / 2 = 50_000_000.5, a tie below an even quotient.
+    assert_eq!(
+        target_for(RoundingMode::Truncate, 100_000_001, 2),
+        50_000_000
+    );
+    assert_eq!(target_for(RoundingMode::HalfUp, 100_000_001, 2), 50_000_001);
+    assert_eq!(
+        target_for(RoundingMode::Nearest, 100_000_001, 2),
+        50_000_000
+    );
+
+    // 100_000_003 / 2 = 50_000_001.5, a tie below an odd quotient.
+    assert_eq!(
+        target_for(RoundingMode::Truncate, 100_000_003, 2),
+        50_000_001
+    );
+    assert_eq!(target_for(RoundingMode::HalfUp, 100_000_003, 2), 50_000_002);
+    assert_eq!(
+        target_for(RoundingMode::Nearest, 100_000_003, 2),
+        50_000_002
+    );
+}
+
+#[test]
+fn test_calculate_rebalance_trades_honors_custom_min_trade_amount() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let asset3 = create_token(&env);
+    // Sits exactly on target so the weights can sum to 100%.
+    let on_target = create_token(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 2500);
+    allocations.set(asset2.clone(), 2500);
+    allocations.set(asset3.clone(), 2500);
+    allocations.set(on_target.clone(), 2500);
+
+    let custom_min = 5_000i128;
+    let target_balance = 50_000_000i128;
+    let mut balances = Map::new(&env);
+    balances.set(on_target.clone(), target_balance);
+    balances.set(asset1.clone(), target_balance - custom_min);
+    balances.set(asset2.clone(), target_balance - (custom_min + 1));
+    balances.set(asset3.clone(), target_balance - (custom_min + 1));
+
+    let mut min_trade_amounts = Map::new(&env);
+    min_trade_amounts.set(asset1.clone(), custom_min);
+    min_trade_amounts.set(asset2.clone(), custom_min);
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 4 * target_balance,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts,
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+    prices.set(asset3.clone(), 10i128.pow(14));
+    prices.set(on_target.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+    assert!(!trades.contains_key(asset1));
+    assert_eq!(trades.get(asset2).unwrap(), custom_min + 1);
+    // asset3 has no override and keeps the MIN_TRADE_AMOUNT_STROOPS default.
+    assert!(!trades.contains_key(asset3));
+}
+
+#[test]
+fn test_calculate_rebalance_trades_rejects_infeasible_targets() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+
+    // Weights that sum past 100% size targets worth more than the portfolio.
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[(asset1.clone(), 10000), (asset2.clone(), 10000)],
+        &[(asset1.clone(), 50_000_000), (asset2.clone(), 50_000_000)],
+        100_000_000,
+    );
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+    assert_eq!(
+        crate::portfolio::calculate_rebalance_trades(
+            &env,
+            &portfolio,
+            &prices,
+            REFLECTOR_PRICE_DECIMALS,
+        ),
+        Err(Error::InfeasibleTarget)
+    );
+
+    // A negative price turns a positive target value into a short position.
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[(asset1.clone(), 5000), (asset2.clone(), 5000)],
+        &[(asset1.clone(), 50_000_000), (asset2.clone(), 50_000_000)],
+        100_000_000,
+    );
+    prices.set(asset2.clone(), -(10i128.pow(14)));
+    assert_eq!(
+        crate::portfolio::calculate_rebalance_trades(
+            &env,
+            &portfolio,
+            &prices,
+            REFLECTOR_PRICE_DECIMALS,
+        ),
+        Err(Error::InfeasibleTarget)
+    );
+}
+
+#[test]
+fn test_drift_scaling_sizes_trades_in_proportion_to_drift() {
+    let env = Env::default();
+    let heavy = create_token(&env);
+    let light = create_token(&env);
+    let mut prices = Map::new(&env);
+    prices.set(heavy.clone(), 10i128.pow(14));
+    prices.set(light.clone(), 10i128.pow(14));
+
+    // Threshold 500 bps, full trades from 2500 bps of drift.
+    let heavy_trade = |heavy_balance: i128| {
+        let mut portfolio = build_trade_test_portfolio(
+            &env,
+            &[(heavy.clone(), 5000), (light.clone(), 5000)],
+            &[
+                (heavy.clone(), heavy_balance),
+                (light.clone(), 100_000_000 - heavy_balance),
+            ],
+            100_000_000,
+        );
+        portfolio.drift_scaling_cap_bps = 2500;
+        crate::portfolio::calculate_rebalance_trades(
+            &env,
+            &portfolio,
+            &prices,
+            REFLECTOR_PRICE_DECIMALS,
+        )
+        .unwrap()
+        .get(heavy.clone())
+    };
+
+    // 400 bps is under the threshold: nothing trades.
+    assert_eq!(heavy_trade(54_000_000), None);
+    // 1000 bps: a quarter of the 10M drift.
+    assert_eq!(heavy_trade(60_000_000), Some(-2_500_000));
+    // 1500 bps: half of the 15M drift.
+    assert_eq!(heavy_trade(65_000_000), Some(-7_500_000));
+    // 4000 bps is past the cap: the full 40M.
+    assert_eq!(heavy_trade(90_000_000), Some(-40_000_000));
+}
+
+#[test]
+fn test_set_drift_scaling_cap_validates_against_threshold() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 5000);
+    allocations.set(create_token(&env), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(client.get_portfolio(&pid).drift_scaling_cap_bps, 0);
+
+    assert_eq!(
+        client.try_set_drift_scaling_cap(&pid, &500),
+        Err(Ok(Error::InvalidThreshold))
+    );
+    assert_eq!(
+        client.try_set_drift_scaling_cap(&pid, &(ALLOCATION_DENOMINATOR + 1)),
+        Err(Ok(Error::InvalidThreshold))
+    );
+    client.set_drift_scaling_cap(&pid, &2500);
+    assert_eq!(client.get_portfolio(&pid).drift_scaling_cap_bps, 2500);
+    client.set_drift_scaling_cap(&pid, &0);
+    assert_eq!(client.get_portfolio(&pid).drift_scaling_cap_bps, 0);
+}
+
+#[test]
+fn test_min_balance_floor_clamps_sell_trades() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    // 200M / 100M against 50/50 targets: a full rebalance sells 50M of asset1.
+    let (pid, asset1, asset2) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(
+        client.try_set_min_balance(&pid, &asset1, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_set_min_balance(&pid, &create_token(&env), &1),
+        Err(Ok(Error::AssetNotSupported))
+    );
+    client.set_min_balance(&pid, &asset1, &180_000_000);
+    assert_eq!(
+        client.get_portfolio(&pid).min_balances.get(asset1.clone()),
+        Some(180_000_000)
+    );
+
+    let trades = client.preview_rebalance_trades(&pid);
+    assert_eq!(trades.get(asset1.clone()), Some(-20_000_000));
+    assert_eq!(trades.get(asset2.clone()), Some(50_000_000));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    let result = client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result.trades.get(asset1.clone()), Some(-20_000_000));
+    assert_eq!(client.get_balances(&pid).get(asset1), Some(180_000_000));
+}
+
+#[test]
+fn test_set_min_trade_amount_validates_input() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.set_min_trade_amount(&pid, &asset, &5_000);
+    assert_eq!(
+        client
+            .get_portfolio(&pid)
+            .min_trade_amounts
+            .get(asset.clone())
+            .unwrap(),
+        5_000
+    );
+    assert_eq!(
+        client.try_set_min_trade_amount(&pid, &asset, &-1),
+        Err(Ok(Error::InvalidAmount))
+    );
+    assert_eq!(
+        client.try_set_min_trade_amount(&pid, &Address::generate(&env), &5_000),
+        Err(Ok(Error::AssetNotSupported))
+    );
+}
+
+#[test]
+fn test_calculate_rebalance_trades_2_asset() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+
+    let mut balances = Map::new(&env);
+    balances.set(asset1.clone(), 150 * 10i128.pow(14));
+    balances.set(asset2.clone(), 50 * 10i128.pow(14));
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 200 * 10i128.pow(14),
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+
+    assert_eq!(trades.get(asset1.clone()).unwrap(), -50 * 10i128.pow(14));
+    assert_eq!(trades.get(asset2.clone()).unwrap(), 50 * 10i128.pow(14));
+}
+
+#[test]
+fn test_calculate_rebalance_trades_two_asset_direction_correctness() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[(asset1.clone(), 5000), (asset2.clone(), 5000)],
+        &[(asset1.clone(), 70_000_000), (asset2.clone(), 30_000_000)],
+        100_000_000,
+    );
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+
+    assert_eq!(trades.get(asset1).unwrap(), -20_000_000);
+    assert_eq!(trades.get(asset2).unwrap(), 20_000_000);
+}
+
+#[test]
+fn test_calculate_rebalance_trades_5_asset() {
+    let env = Env::default();
+    let a1 = Address::generate(&env);
+    let a2 = Address::generate(&env);
+    let a3 = Address::generate(&env);
+    let a4 = Address::generate(&env);
+    let a5 = Address::generate(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(a1.clone(), 2000);
+    allocations.set(a2.clone(), 2000);
+    allocations.set(a3.clone(), 2000);
+    allocations.set(a4.clone(), 2000);
+    allocations.set(a5.clone(), 2000);
+
+    let mut balances = Map::new(&env);
+    balances.set(a1.clone(), 50 * 10i128.pow(14));
+    balances.set(a2.clone(), 150 * 10i128.pow(14));
+    balances.set(a3.clone(), 100 * 10i128.pow(14));
+    balances.set(a4.clone(), 20 * 10i128.pow(14));
+    balances.set(a5.clone(), 180 * 10i128.pow(14));
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 500 * 10i128.pow(14),
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(a1.clone(), 10i128.pow(14));
+    prices.set(a2.clone(), 10i128.pow(14));
+    prices.set(a3.clone(), 10i128.pow(14));
+    prices.set(a4.clone(), 10i128.pow(14));
+    prices.set(a5.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+
+    assert_eq!(trades.get(a1).unwrap(), 50 * 10i128.pow(14));
+    assert_eq!(trades.get(a2).unwrap(), -50 * 10i128.pow(14));
+    assert!(!trades.contains_key(a3));
+    assert_eq!(trades.get(a4).unwrap(), 80 * 10i128.pow(14));
+    assert_eq!(trades.get(a5).unwrap(), -80 * 10i128.pow(14));
+}
+
+#[test]
+fn test_calculate_rebalance_trades_direction_buy_sell() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+
+    let mut balances = Map::new(&env);
+    balances.set(asset1.clone(), 120 * 10i128.pow(14));
+    balances.set(asset2.clone(), 80 * 10i128.pow(14));
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 200 * 10i128.pow(14),
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+
+    let trade_a1 = trades.get(asset1).unwrap();
+    let trade_a2 = trades.get(asset2).unwrap();
+
+    assert!(
+        trade_a1 < 0,
+        "Overweight asset should result in a sell (negative) trade"
+    );
+    assert!(
+        trade_a2 > 0,
+        "Underweight asset should result in a buy (positive) trade"
+    );
+    assert_eq!(trade_a1, -20 * 10i128.pow(14));
+    assert_eq!(trade_a2, 20 * 10i128.pow(14));
+}
+
+#[test]
+fn test_calculate_rebalance_trades_price_precision() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 6000);
+    allocations.set(asset2.clone(), 4000);
+
+    let mut balances = Map::new(&env);
+    balances.set(asset1.clone(), 150 * 10i128.pow(14));
+    balances.set(asset2.clone(), 125 * 10i128.pow(13));
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 100 * 10i128.pow(14),
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 50_000_000_000_000);
+    prices.set(asset2.clone(), 200_000_000_000_000);
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+
+    assert_eq!(trades.get(asset1).unwrap(), -30 * 10i128.pow(14));
+    assert_eq!(trades.get(asset2).unwrap(), 75 * 10i128.pow(13));
+}
+
+#[test]
+fn test_calculate_rebalance_trades_three_asset_rebalance_path() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let asset3 = create_token(&env);
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[
+            (asset1.clone(), 5000),
+            (asset2.clone(), 3000),
+            (asset3.clone(), 2000),
+        ],
+        &[
+            (asset1.clone(), 40_000_000),
+            (asset2.clone(), 40_000_000),
+            (asset3.clone(), 20_000_000),
+        ],
+        100_000_000,
+    );
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+    prices.set(asset3.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+    assert_eq!(trades.get(asset1).unwrap(), 10_000_000);
+    assert_eq!(trades.get(asset2).unwrap(), -10_000_000);
+    assert!(!trades.contains_key(asset3));
+}
+
+#[test]
+fn test_calculate_rebalance_trades_exact_boundary() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let asset3 = create_token(&env);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 4000);
+    allocations.set(asset2.clone(), 3000);
+    allocations.set(asset3.clone(), 3000);
+
+    let mut balances = Map::new(&env);
+    let target1 = 40_000_000i128;
+    let target2 = 30_000_000i128;
+    let target3 = 30_000_000i128;
+
+    balances.set(asset1.clone(), target1 - MIN_TRADE_AMOUNT_STROOPS);
+    balances.set(asset2.clone(), target2 - (MIN_TRADE_AMOUNT_STROOPS - 1));
+    balances.set(asset3.clone(), target3 - (MIN_TRADE_AMOUNT_STROOPS + 1));
+
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations,
+        current_balances: balances,
+        asset_decimals,
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 100_000_000i128,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), 10i128.pow(14));
+    prices.set(asset2.clone(), 10i128.pow(14));
+    prices.set(asset3.clone(), 10i128.pow(14));
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+
+    assert!(!trades.contains_key(asset1));
+    assert!(!trades.contains_key(asset2));
+    assert_eq!(trades.get(asset3).unwrap(), MIN_TRADE_AMOUNT_STROOPS + 1);
+}
+
+#[test]
+fn test_calculate_rebalance_trades_five_asset_rebalance_path() {
+    let env = Env::default();
+    let a1 = Address::generate(&env);
+    let a2 = Address::generate(&env);
+    let a3 = Address::generate(&env);
+    let a4 = Address::generate(&env);
+    let a5 = Address::generate(&env);
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[
+            (a1.clone(), 2000),
+            (a2.clone(), 2000),
+            (a3.clone(), 2000),
+            (a4.clone(), 2000),
+            (a5.clone(), 2000),
+        ],
+        &[
+            (a1.clone(), 300_000_000),
+            (a2.clone(), 50_000_000),
+            (a3.clone(), 50_000_000),
+            (a4.clone(), 50_000_000),
+            (a5.clone(), 50_000_000),
+        ],
+        500_000_000,
+    );
+    let mut prices = Map::new(&env);
+    for asset in vec![
+        &env,
+        a1.clone(),
+        a2.clone(),
+        a3.clone(),
+        a4.clone(),
+        a5.clone(),
+    ]
+    .iter()
+    {
+        prices.set(asset.clone(), 10i128.pow(14));
+    }
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+    assert_eq!(trades.get(a1).unwrap(), -200_000_000);
+    assert_eq!(trades.get(a2).unwrap(), 50_000_000);
+    assert_eq!(trades.get(a3).unwrap(), 50_000_000);
+    assert_eq!(trades.get(a4).unwrap(), 50_000_000);
+    assert_eq!(trades.get(a5).unwrap(), 50_000_000);
+}
+
+#[test]
+fn test_calculate_rebalance_trades_price_precision_14_decimals_edge_case() {
+    let env = Env::default();
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let precise_price = 123_456_789_012_345i128;
+    let target_balance = 100_000_000i128;
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[(asset1.clone(), 5000), (asset2.clone(), 5000)],
+        &[
+            (
+                asset1.clone(),
+                target_balance - (MIN_TRADE_AMOUNT_STROOPS + 5),
+            ),
+            (
+                asset2.clone(),
+                target_balance + (MIN_TRADE_AMOUNT_STROOPS + 5),
+            ),
+        ],
+        246_913_578,
+    );
+    let mut prices = Map::new(&env);
+    prices.set(asset1.clone(), precise_price);
+    prices.set(asset2.clone(), precise_price);
+
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+    let expected_target_value = (portfolio.total_value * 5000) / 10000;
+    let expected_target_balance = crate::portfolio::value_to_balance(
+        expected_target_value,
+        precise_price,
+        REFLECTOR_PRICE_DECIMALS,
+        DEFAULT_ASSET_DECIMALS,
+    );
+    let expected_buy = expected_target_balance - (target_balance - (MIN_TRADE_AMOUNT_STROOPS + 5));
+    let expected_sell = expected_target_balance - (target_balance + (MIN_TRADE_AMOUNT_STROOPS + 5));
+    assert_eq!(trades.get(asset1).unwrap(), expected_buy);
+    assert_eq!(trades.get(asset2).unwrap(), expected_sell);
+}
+
+#[test]
+#[should_panic]
+fn test_initialize_guard() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+
+    client.initialize(&admin, &reflector_id);
+    client.initialize(&admin, &reflector_id);
+}
+
+#[test]
+fn test_second_initialize_cannot_replace_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let attacker = Address::generate(&env);
+    let attacker_reflector = env.register_contract(None, reflector_contract::MockReflector);
+
+    assert_eq!(client.try_initialize(&admin, &reflector_id), Ok(Ok(())));
+    assert_eq!(
+        client.try_initialize(&attacker, &attacker_reflector),
+        Err(Ok(Error::AlreadyInitialized))
+    );
+    assert_eq!(client.get_admin(), admin);
+    assert_eq!(client.get_config().reflector_address, reflector_id);
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_invalid_allocation() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 6000);
+    allocations.set(create_token(&env), 3000); // sums to 9000, not 10000
+
+    create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+}
+
+#[test]
+fn test_create_portfolio_rejects_allocation_sum_too_low() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let allocations = allocation_map_from_percentages(&env, &[4000, 3000]);
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::InvalidAllocation)));
+}
+
+#[test]
+fn test_create_portfolio_rejects_allocation_sum_too_high() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let allocations = allocation_map_from_percentages(&env, &[9000, 6000]);
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::InvalidAllocation)));
+}
+
+#[test]
+fn test_create_portfolio_rejects_empty_allocations() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let allocations: Map<Address, u32> = Map::new(&env);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &Map::new(&env),
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::InvalidAllocation)));
+}
+
+#[test]
+fn test_validate_allocations_rejects_single_weight_above_denominator() {
+    let env = Env::default();
+    let allocations = allocation_map_from_percentages(&env, &[ALLOCATION_DENOMINATOR + 1]);
+    assert!(!crate::portfolio::validate_allocations(&allocations));
+}
+
+#[test]
+fn test_create_portfolio_fractional_bps_allocations() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 2550);
+    allocations.set(asset2.clone(), 7450);
+    // 0.5% drift threshold.
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 50, 50);
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.target_allocations.get(asset1).unwrap(), 2550);
+    assert_eq!(portfolio.target_allocations.get(asset2).unwrap(), 7450);
+    assert_eq!(portfolio.rebalance_threshold, 50);
+}
+
+#[test]
+fn test_create_portfolio_rejects_percentage_scale_allocations() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let mut asset_decimals = Map::new(&env);
+    for weight in [60u32, 40] {
+        let asset = create_token(&env);
+        allocations.set(asset.clone(), weight);
+        asset_decimals.set(asset, DEFAULT_ASSET_DECIMALS);
+    }
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::AllocationsNotBasisPoints)));
+}
+
+#[test]
+fn test_check_rebalance_needed_fractional_threshold() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 2550);
+    allocations.set(asset2.clone(), 7450);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 50, 50);
+
+    // 25.80% / 74.20%: 30 bps of drift stays within a 50 bps threshold.
+    client.deposit(&pid, &asset1, &2580, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &7420, &String::from_str(&env, ""));
+    assert!(!client.check_rebalance_needed(&pid));
+
+    // 26.10% / 73.90%: 60 bps of drift exceeds it.
+    client.deposit(&pid, &asset1, &30, &String::from_str(&env, ""));
+    client.withdraw(&pid, &asset2, &30);
+    assert!(client.check_rebalance_needed(&pid));
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_threshold_too_low() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    create_portfolio_with_defaults(&env, &client, &user, &allocations, 0, 50);
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_threshold_too_high() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    create_portfolio_with_defaults(&env, &client, &user, &allocations, 5001, 50);
+}
+
+#[test]
+fn test_rebalance_threshold_validation_on_create_and_update() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+
+    for threshold in [0u32, 4_000_000_000] {
+        let result = client.try_create_portfolio(
+            &user,
+            &allocations,
+            &asset_decimals,
+            &threshold,
+            &50,
+            &CURRENT_SLIPPAGE_POLICY_VERSION,
+            &None,
+        );
+        assert_eq!(result, Err(Ok(Error::InvalidThreshold)));
+    }
+
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 2500, 50);
+    assert_eq!(client.get_portfolio(&pid).rebalance_threshold, 2500);
+
+    assert_eq!(
+        client.try_set_rebalance_threshold(&pid, &0),
+        Err(Ok(Error::InvalidThreshold))
+    );
+    assert_eq!(
+        client.try_set_rebalance_threshold(&pid, &4_000_000_000),
+        Err(Ok(Error::InvalidThreshold))
+    );
+    client.set_rebalance_threshold(&pid, &750);
+    assert_eq!(client.get_portfolio(&pid).rebalance_threshold, 750);
+}
+
+#[test]
+fn test_create_portfolio_multiple_same_ledger() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.sequence_number = 1;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    let pid1 = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let pid2 = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    assert_eq!(pid1, 1);
+    assert_eq!(pid2, 2);
+    assert_ne!(pid1, pid2);
+}
+
+#[test]
+fn test_deposits_and_withdraw_refresh_total_value() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(client.get_portfolio(&pid).total_value, 0);
+
+    // MockReflector prices every asset at 100.
+    client.deposit(&pid, &asset1, &1000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &250, &String::from_str(&env, ""));
+    assert_eq!(
+        client.get_portfolio(&pid).total_value,
+        1000 * 100 + 250 * 100
+    );
+
+    client.withdraw(&pid, &asset1, &400);
+    assert_eq!(
+        client.get_portfolio(&pid).total_value,
+        600 * 100 + 250 * 100
+    );
+}
+
+fn portfolio_ttl(env: &Env, contract_id: &Address, portfolio_id: u64) -> u32 {
+    env.as_contract(contract_id, || {
+        env.storage()
+            .persistent()
+            .get_ttl(&DataKey::Portfolio(portfolio_id))
+    })
+}
+
+#[test]
+fn test_portfolio_ttl_extended_on_access() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(
+        portfolio_ttl(&env, &contract_id, pid),
+        PORTFOLIO_TTL_EXTEND_LEDGERS
+    );
+
+    // The mock oracle and token instances are outside this contract's TTL management.
+    for external in [&reflector_id, &asset] {
+        env.as_contract(external, || {
+            env.storage()
+                .instance()
+                .extend_ttl(PORTFOLIO_TTL_EXTEND_LEDGERS, PORTFOLIO_TTL_EXTEND_LEDGERS)
+        });
+    }
+    let elapsed = PORTFOLIO_TTL_EXTEND_LEDGERS - PORTFOLIO_TTL_THRESHOLD_LEDGERS + 1;
+    env.ledger().with_mut(|li| {
+        li.sequence_number += elapsed;
+    });
+    assert_eq!(
+        portfolio_ttl(&env, &contract_id, pid),
+        PORTFOLIO_TTL_THRESHOLD_LEDGERS - 1
+    );
+
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    assert_eq!(
+        portfolio_ttl(&env, &contract_id, pid),
+        PORTFOLIO_TTL_EXTEND_LEDGERS
+    );
+}
+
+#[test]
+fn test_bump_portfolio_ttl_extends_idle_portfolio() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.bump_portfolio_ttl(&pid, &3_000_000);
+    assert_eq!(portfolio_ttl(&env, &contract_id, pid), 3_000_000);
+
+    assert_eq!(
+        client.try_bump_portfolio_ttl(&(pid + 1), &3_000_000),
+        Err(Ok(Error::PortfolioNotFound))
+    );
+}
+
+#[test]
+fn test_asset_whitelist_enforced_on_deposit_and_allocation() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let allowed = create_token(&env);
+    let unlisted = create_token(&env);
+    client.add_allowed_asset(&allowed);
+    assert!(client.is_asset_allowed(&allowed));
+    assert!(!client.is_asset_allowed(&unlisted));
+    assert_eq!(client.get_allowed_assets(), vec![&env, allowed.clone()]);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(allowed.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &allowed, &100, &String::from_str(&env, ""));
+    assert_eq!(
+        client
+            .get_portfolio(&pid)
+            .current_balances
+            .get(allowed.clone())
+            .unwrap(),
+        100
+    );
+
+    let result = client.try_deposit(&pid, &unlisted, &100, &String::from_str(&env, ""));
+    assert_eq!(result, Err(Ok(Error::AssetNotAllowed)));
+
+    let mut unlisted_allocations = Map::new(&env);
+    unlisted_allocations.set(unlisted.clone(), 10000);
+    let result = client.try_create_portfolio(
+        &user,
+        &unlisted_allocations,
+        &allocation_decimals(&env, &unlisted_allocations, DEFAULT_ASSET_DECIMALS),
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::AssetNotAllowed)));
+
+    client.remove_allowed_asset(&allowed);
+    assert_eq!(client.get_allowed_assets().len(), 0);
+    // An empty whitelist leaves assets unrestricted again.
+    client.deposit(&pid, &unlisted, &100, &String::from_str(&env, ""));
+}
+
+#[test]
+fn test_deposit_many_applies_all_assets() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let asset3 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 4000);
+    allocations.set(asset2.clone(), 3000);
+    allocations.set(asset3.clone(), 3000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &50, &String::from_str(&env, ""));
+
+    let mut deposits = Map::new(&env);
+    deposits.set(asset1.clone(), 350);
+    deposits.set(asset2.clone(), 300);
+    deposits.set(asset3.clone(), 300);
+    client.deposit_many(&pid, &deposits);
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset1).unwrap(), 400);
+    assert_eq!(portfolio.current_balances.get(asset2).unwrap(), 300);
+    assert_eq!(portfolio.current_balances.get(asset3).unwrap(), 300);
+    assert_eq!(portfolio.total_value, 1000 * 100);
+}
+
+#[test]
+fn test_deposit_many_rejects_negative_amount() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let mut deposits = Map::new(&env);
+    deposits.set(asset1.clone(), 100);
+    deposits.set(asset2.clone(), -1);
+    let result = client.try_deposit_many(&pid, &deposits);
+    assert_eq!(result, Err(Ok(Error::InvalidAmount)));
+    assert!(client.get_portfolio(&pid).current_balances.is_empty());
+}
+
+#[test]
+fn test_get_user_portfolios_lists_owned_ids() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let other_user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    assert_eq!(client.get_user_portfolios(&user).len(), 0);
+
+    let pid1 = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let other_pid =
+        create_portfolio_with_defaults(&env, &client, &other_user, &allocations, 500, 50);
+    let pid2 = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    assert_eq!(client.get_user_portfolios(&user), vec![&env, pid1, pid2]);
+    assert_eq!(
+        client.get_user_portfolios(&other_user),
+        vec![&env, other_pid]
+    );
+}
+
+#[test]
+fn test_create_portfolios_from_template() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut template = Map::new(&env);
+    template.set(create_token(&env), 6000);
+    template.set(create_token(&env), 4000);
+
+    let ids = client.create_portfolios(&user, &template, &5, &500);
+    assert_eq!(ids.len(), 5);
+    for (i, pid) in ids.iter().enumerate() {
+        assert!(!ids.slice(i as u32 + 1..).contains(pid));
+        let portfolio = client.get_portfolio(&pid);
+        assert_eq!(portfolio.user, user);
+        assert_eq!(portfolio.target_allocations, template);
+        assert_eq!(portfolio.rebalance_threshold, 500);
+        assert_eq!(portfolio.slippage_tolerance, DEFAULT_SLIPPAGE_TOLERANCE_BPS);
+    }
+    assert_eq!(client.get_user_portfolios(&user), ids);
+
+    assert_eq!(
+        client.try_create_portfolios(&user, &template, &0, &500),
+        Err(Ok(Error::InvalidBatchSize))
+    );
+    assert_eq!(
+        client.try_create_portfolios(&user, &template, &(MAX_BATCH_PORTFOLIOS + 1), &500),
+        Err(Ok(Error::InvalidBatchSize))
+    );
+    template.set(create_token(&env), 1);
+    assert_eq!(
+        client.try_create_portfolios(&user, &template, &2, &500),
+        Err(Ok(Error::InvalidAllocation))
+    );
+}
+
+#[test]
+fn test_create_portfolio_from_pairs() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let xlm = create_token(&env);
+    let usdc = create_token(&env);
+    let pairs = vec![&env, (xlm.clone(), 6000u32), (usdc.clone(), 4000u32)];
+
+    let pid = client.create_portfolio_from_pairs(&user, &pairs, &500);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.user, user);
+    assert_eq!(portfolio.target_allocations.get(xlm.clone()), Some(6000));
+    assert_eq!(portfolio.target_allocations.get(usdc.clone()), Some(4000));
+    assert_eq!(portfolio.rebalance_threshold, 500);
+    assert_eq!(portfolio.slippage_tolerance, DEFAULT_SLIPPAGE_TOLERANCE_BPS);
+}
+
+#[test]
+fn test_create_portfolio_from_pairs_rejects_duplicate_asset() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let xlm = create_token(&env);
+    let usdc = create_token(&env);
+    // Weights still sum to 100% once the map collapses them, so only the
+    // duplicate check can catch this.
+    let pairs = vec![
+        &env,
+        (xlm.clone(), 3000u32),
+        (usdc.clone(), 4000u32),
+        (xlm.clone(), 6000u32),
+    ];
+
+    assert_eq!(
+        client.try_create_portfolio_from_pairs(&user, &pairs, &500),
+        Err(Ok(Error::DuplicateAsset))
+    );
+    assert_eq!(client.get_user_portfolios(&user).len(), 0);
+}
+
+#[test]
+fn test_three_portfolios_same_ledger_independently_retrievable() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.sequence_number = 42;
+        li.timestamp = 1000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let users = [
+        Address::generate(&env),
+        Address::generate(&env),
+        Address::generate(&env),
+    ];
+    let thresholds = [500u32, 1000, 1500];
+    let mut ids = [0u64; 3];
+    for i in 0..3 {
+        let mut allocations = Map::new(&env);
+        allocations.set(create_token(&env), 10000);
+        ids[i] = create_portfolio_with_defaults(
+            &env,
+            &client,
+            &users[i],
+            &allocations,
+            thresholds[i],
+            50,
+        );
+    }
+
+    assert_eq!(ids, [1, 2, 3]);
+    for i in 0..3 {
+        let portfolio = client.get_portfolio(&ids[i]);
+        assert_eq!(portfolio.user, users[i]);
+        assert_eq!(portfolio.rebalance_threshold, thresholds[i]);
+    }
+}
+
+#[test]
+fn test_portfolio_id_starts_at_one() {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.sequence_number = 1;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(pid, 1);
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_slippage_too_low() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    client.initialize(&Address::generate(&env), &reflector_id);
+    let user = Address::generate(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 5);
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_slippage_too_high() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    client.initialize(&Address::generate(&env), &reflector_id);
+    let user = Address::generate(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 501);
+}
+
+#[test]
+fn test_emergency_stop_admin_pause_and_reactivate() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.set_emergency_stop(&true);
+    client.set_emergency_stop(&false);
+
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 100);
+}
+
+#[test]
+fn test_pause_blocks_mutations_but_not_reads() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, _) = create_drifted_token_portfolio(&env, &client, &user);
+    client.pause();
+    assert!(client.is_paused());
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let memo = String::from_str(&env, "");
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    assert_eq!(
+        client.try_create_portfolio(
+            &user,
+            &allocations,
+            &asset_decimals,
+            &500,
+            &50,
+            &CURRENT_SLIPPAGE_POLICY_VERSION,
+            &None
+        ),
+        Err(Ok(Error::ContractPaused))
+    );
+    assert_eq!(
+        client.try_deposit(&pid, &asset1, &100, &memo),
+        Err(Ok(Error::ContractPaused))
+    );
+    assert_eq!(
+        client.try_withdraw(&pid, &asset1, &100),
+        Err(Ok(Error::ContractPaused))
+    );
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::ContractPaused))
+    );
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(
+        portfolio.current_balances.get(asset1.clone()),
+        Some(200_000_000)
+    );
+    assert_eq!(client.get_portfolio_value(&pid), portfolio.total_value);
+    assert!(client.check_rebalance_needed(&pid));
+
+    client.unpause();
+    assert!(!client.is_paused());
+    client.execute_rebalance(&pid, &Map::new(&env));
+}
+
+#[test]
+fn test_get_admin_returns_configured_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let configured_admin = client.get_admin();
+    assert_eq!(configured_admin, admin);
+}
+
+#[test]
+#[should_panic]
+fn test_emergency_stop_non_admin_rejected() {
+    let env = Env::default();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let non_admin = Address::generate(&env);
+    client
+        .mock_auths(&[MockAuth {
+            address: &admin,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "initialize",
+                args: (&admin, &reflector_id).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .initialize(&admin, &reflector_id);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &non_admin,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "set_emergency_stop",
+                args: vec![&env, true.into_val(&env)],
+                sub_invokes: &[],
+            },
+        }])
+        .set_emergency_stop(&true);
+}
+
+#[test]
+fn test_admin_deactivate_portfolio_blocks_owner_reactivation() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &1_000, &String::from_str(&env, ""));
+
+    client.admin_deactivate_portfolio(&pid);
+    assert_eq!(
+        env.auths()[0],
+        (
+            admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    contract_id.clone(),
+                    Symbol::new(&env, "admin_deactivate_portfolio"),
+                    (pid,).into_val(&env),
+                )),
+                sub_invocations: std::vec::Vec::new(),
+            }
+        )
+    );
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("admin"),
+            Symbol::new(&env, "deactivated"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+    let (event_pid, event_admin): (u64, Address) = data.into_val(&env);
+    assert_eq!((event_pid, event_admin), (pid, admin));
+
+    let portfolio = client.get_portfolio(&pid);
+    assert!(!portfolio.is_active);
+    assert_eq!(portfolio.pause_reason, PauseReason::AdminEmergency);
+    assert_eq!(
+        client.try_reactivate_portfolio(&pid),
+        Err(Ok(Error::PortfolioPaused))
+    );
+    assert_eq!(
+        client.try_deposit(&pid, &asset, &1, &String::from_str(&env, "")),
+        Err(Ok(Error::PortfolioPaused))
+    );
+    client.withdraw(&pid, &asset, &1_000);
+}
+
+#[test]
+#[should_panic]
+fn test_admin_deactivate_portfolio_rejects_non_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &user,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "admin_deactivate_portfolio",
+                args: (pid,).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .admin_deactivate_portfolio(&pid);
+}
+
+#[test]
+fn test_set_reflector_address_by_admin() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let new_reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    client.set_reflector_address(&new_reflector_id);
+
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("reflector"),
+            symbol_short!("rotated"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+    let (old_address, new_address): (Address, Address) = data.into_val(&env);
+    assert_eq!(old_address, reflector_id);
+    assert_eq!(new_address, new_reflector_id);
+
+    assert_eq!(
+        client.get_config_view(&0).reflector_address,
+        new_reflector_id
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_set_reflector_address_non_admin_rejected() {
+    let env = Env::default();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let new_reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let non_admin = Address::generate(&env);
+    client
+        .mock_auths(&[MockAuth {
+            address: &admin,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "initialize",
+                args: (&admin, &reflector_id).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .initialize(&admin, &reflector_id);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &non_admin,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "set_reflector_address",
+                args: (&new_reflector_id,).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .set_reflector_address(&new_reflector_id);
+}
+
+#[test]
+fn test_two_step_admin_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let new_admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    assert_eq!(client.try_accept_admin(), Err(Ok(Error::NoPendingAdmin)));
+
+    client.propose_admin(&new_admin);
+    assert_eq!(client.get_admin(), admin);
+    assert_eq!(client.get_pending_admin(), Some(new_admin.clone()));
+    let (_, topics, _) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("admin"),
+            symbol_short!("proposed"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+
+    client.accept_admin();
+    assert_eq!(client.get_admin(), new_admin);
+    assert_eq!(client.get_pending_admin(), None);
+    let (_, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("admin"),
+            symbol_short!("accepted"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+    let (previous_admin, accepted_admin): (Address, Address) = data.into_val(&env);
+    assert_eq!(previous_admin, admin);
+    assert_eq!(accepted_admin, new_admin);
+}
+
+#[test]
+#[should_panic]
+fn test_accept_admin_rejects_non_proposed_address() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let new_admin = Address::generate(&env);
+    let intruder = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    client.propose_admin(&new_admin);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &intruder,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "accept_admin",
+                args: ().into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .accept_admin();
+}
+
+#[test]
+fn test_create_portfolio_max_assets_limit() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut max_allocations = Map::new(&env);
+    for _ in 0..MAX_PORTFOLIO_ASSETS {
+        max_allocations.set(
+            create_token(&env),
+            ALLOCATION_DENOMINATOR / MAX_PORTFOLIO_ASSETS,
+        );
+    }
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &max_allocations, 500, 50);
+    assert!(pid > 0);
+
+    let over = MAX_PORTFOLIO_ASSETS + 1;
+    let over_share = ALLOCATION_DENOMINATOR / over;
+    let mut too_many_allocations = Map::new(&env);
+    for i in 0..over {
+        let weight = if i == 0 {
+            ALLOCATION_DENOMINATOR - over_share * (over - 1)
+        } else {
+            over_share
+        };
+        too_many_allocations.set(create_token(&env), weight);
+    }
+    let too_many_decimals =
+        allocation_decimals(&env, &too_many_allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &too_many_allocations,
+        &too_many_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::TooManyAssets)));
+}
+
+#[test]
+fn test_min_assets_rejects_single_asset_portfolio() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_min_assets(), DEFAULT_MIN_PORTFOLIO_ASSETS);
+
+    let asset1 = create_token(&env);
+    let mut single = Map::new(&env);
+    single.set(asset1.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &single, 500, 50);
+
+    assert_eq!(
+        client.try_set_min_assets(&0),
+        Err(Ok(Error::InvalidMinAssets))
+    );
+    client.set_min_assets(&2);
+
+    let asset_decimals = allocation_decimals(&env, &single, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &single,
+        &asset_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::TooFewAssets)));
+
+    let mut pair = Map::new(&env);
+    pair.set(asset1, 5000);
+    pair.set(create_token(&env), 5000);
+    create_portfolio_with_defaults(&env, &client, &user, &pair, 500, 50);
+
+    let mut other_single = Map::new(&env);
+    other_single.set(create_token(&env), 10000);
+    assert_eq!(
+        client.try_update_target_allocations(&pid, &other_single),
+        Err(Ok(Error::TooFewAssets))
+    );
+}
+
+#[test]
+fn test_portfolio_storage_footprint_estimate_is_deterministic() {
+    let env = Env::default();
+
+    let portfolio = build_trade_test_portfolio(
+        &env,
+        &[
+            (Address::generate(&env), 7000),
+            (Address::generate(&env), 3000),
+        ],
+        &[],
+        0,
+    );
+
+    let portfolio_id = 7;
+    let estimate =
+        crate::portfolio::estimate_portfolio_storage_footprint(&env, portfolio_id, &portfolio);
+    let estimate_again =
+        crate::portfolio::estimate_portfolio_storage_footprint(&env, portfolio_id, &portfolio);
+
+    assert_eq!(estimate, estimate_again);
+    assert!(estimate > 0);
+    assert_eq!(
+        crate::portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio),
+        Ok(estimate)
+    );
+
+    crate::portfolio::set_portfolio_storage_limit_for_tests(Some(estimate - 1));
+    let limited =
+        crate::portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio);
+    crate::portfolio::set_portfolio_storage_limit_for_tests(None);
+    assert_eq!(limited, Err(Error::PortfolioStorageFootprintTooLarge));
+}
+
+#[test]
+fn test_transfer_stewardship() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let new_steward = Address::generate(&env);
+    client.transfer_stewardship(&pid, &new_steward);
+
+    let steward = client.get_steward(&pid);
+    assert_eq!(steward, new_steward);
+}
+
+#[test]
+fn test_transfer_stewardship_steward_can_deposit() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let new_steward = Address::generate(&env);
+    client.transfer_stewardship(&pid, &new_steward);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &new_steward,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "deposit",
+                args: (pid, asset.clone(), 500i128, String::from_str(&env, "")).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .deposit(&pid, &asset, &500, &String::from_str(&env, ""));
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 500);
+}
+
+#[test]
+fn test_preview_rebalance_reports_trades_and_thresholds() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.deposit(&pid, &asset1, &20_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &10_000_000, &String::from_str(&env, ""));
+
+    let preview = client.preview_rebalance(&pid);
+    assert!(preview.rebalance_needed);
+    assert!(preview.candidate_trades.contains_key(asset1.clone()));
+    assert!(preview.threshold_decisions.contains_key(asset1.clone()));
+    let decision = preview.threshold_decisions.get(asset1).unwrap();
+    assert!(decision.exceeds_threshold);
+}
+
+#[test]
+fn test_preview_rebalance_does_not_mutate_portfolio() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
+
+    let before = client.get_portfolio(&pid);
+    let _ = client.preview_rebalance(&pid);
+    let after = client.get_portfolio(&pid);
+    assert_eq!(before.last_rebalance, after.last_rebalance);
+    assert_eq!(
+        before.current_balances.get(asset.clone()).unwrap(),
+        after.current_balances.get(asset).unwrap()
+    );
+}
+
+#[test]
+fn test_create_portfolio_stores_slippage_policy_version() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(
+        portfolio.slippage_policy_version,
+        SLIPPAGE_POLICY_VERSION_V1
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_transfer_stewardship_unauthorized() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let unauthorized = Address::generate(&env);
+    let attacker = Address::generate(&env);
+    client
+        .mock_auths(&[MockAuth {
+            address: &unauthorized,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "transfer_stewardship",
+                args: (pid, attacker.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .transfer_stewardship(&pid, &attacker);
+}
+
+#[test]
+fn test_transfer_portfolio_ownership_moves_control_to_new_owner() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    let new_owner = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.transfer_stewardship(&pid, &Address::generate(&env));
+
+    client.transfer_portfolio_ownership(&pid, &new_owner);
+
+    let events = env.events().all();
+    let (_, topics, data) = events.last().unwrap();
+    assert_eq!(
+        topics,
+        vec![
+            &env,
+            symbol_short!("portfolio").into_val(&env),
+            Symbol::new(&env, "owner_transferred").into_val(&env),
+            EVENT_TOPIC_VERSION.into_val(&env),
+        ]
+    );
+    let data: (u64, Address, Address) = data.into_val(&env);
+    assert_eq!(data, (pid, user.clone(), new_owner.clone()));
+
+    assert_eq!(client.get_portfolio(&pid).user, new_owner);
+    assert_eq!(client.get_steward(&pid), new_owner);
+    assert_eq!(client.get_user_portfolios(&user).len(), 0);
+    assert_eq!(client.get_user_portfolios(&new_owner), vec![&env, pid]);
+
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    assert_eq!(env.auths()[0].0, new_owner);
+}
+
+#[test]
+#[should_panic]
+fn test_previous_owner_cannot_deposit_after_ownership_transfer() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.transfer_portfolio_ownership(&pid, &Address::generate(&env));
+
+    let memo = String::from_str(&env, "");
+    client
+        .mock_auths(&[MockAuth {
+            address: &user,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "deposit",
+                args: (pid, asset.clone(), 100i128, memo.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .deposit(&pid, &asset, &100, &memo);
+}
+
+#[test]
+fn test_get_steward_defaults_to_user() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let steward = client.get_steward(&pid);
+    assert_eq!(steward, user);
+}
+
+#[test]
+fn test_capabilities() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let caps = client.capabilities();
+    assert!(caps & CapabilityFlag::PerPortfolioSteward as u32 != 0);
+    assert!(caps & CapabilityFlag::DifferentiatedPricing as u32 != 0);
+    assert!(caps & CapabilityFlag::EmergencyStop as u32 != 0);
+}
+
+#[test]
+fn test_version_matches_contract_version_constant() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+
+    assert_eq!(client.version(), CONTRACT_VERSION);
+}
+
+#[test]
+fn test_capability_summary() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+
+    assert_eq!(client.version(), 1);
+    assert_eq!(client.schema_version(), 1);
+
+    let summary = client.capability_summary();
+    assert_eq!(summary.version, 1);
+    assert_eq!(summary.schema_version, 1);
+    assert!(summary.capability_flags & CapabilityFlag::PerPortfolioSteward as u32 != 0);
+    assert!(summary.capability_flags & CapabilityFlag::DifferentiatedPricing as u32 != 0);
+    assert!(summary.capability_flags & CapabilityFlag::EmergencyStop as u32 != 0);
+    assert_eq!(summary.min_rebalance_threshold, 10);
+    assert_eq!(summary.max_rebalance_threshold, 5000);
+    assert_eq!(summary.min_slippage_tolerance_bps, 10);
+    assert_eq!(summary.max_slippage_tolerance_bps, 500);
+    assert_eq!(summary.max_portfolio_assets, 10);
+}
+
+#[test]
+fn test_missing_price_error() {
+    let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let missing_reflector_id =
+        env.register_contract(None, reflector_without_prices::ReflectorWithoutPrices);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &missing_reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::MissingPrice)));
+}
+
+#[test]
+fn test_create_portfolio_rejects_asset_without_price_feed() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_missing_price::ReflectorWithMissingPrice,
+    );
+    let reflector =
+        reflector_with_missing_price::ReflectorWithMissingPriceClient::new(&env, &reflector_id);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let tracked = create_token(&env);
+    let untracked = create_token(&env);
+    reflector.set_missing_asset(&untracked);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(tracked.clone(), 5000);
+    allocations.set(untracked.clone(), 5000);
+    let decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    let result = client.try_create_portfolio(
+        &user,
+        &allocations,
+        &decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+    assert_eq!(result, Err(Ok(Error::MissingPrice)));
+    assert_eq!(client.get_user_portfolios(&user).len(), 0);
+
+    let mut tracked_only = Map::new(&env);
+    tracked_only.set(tracked, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &tracked_only, 500, 50);
+    assert_eq!(
+        client.try_update_target_allocations(&pid, &allocations),
+        Err(Ok(Error::MissingPrice))
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_unsupported_slippage_policy_version() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
+    client.create_portfolio(&user, &allocations, &asset_decimals, &500, &50, &99, &None);
+}
+
+#[test]
+#[should_panic]
+fn test_create_portfolio_invalid_asset_decimals() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 10000);
+
+    let empty_decimals = Map::new(&env);
+    client.create_portfolio(
+        &user,
+        &allocations,
+        &empty_decimals,
+        &500,
+        &50,
+        &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
+    );
+}
+
+#[test]
+fn test_pause_portfolio_persists_reason() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    client.pause_portfolio(&pid, &PauseReason::UserPaused);
+    let portfolio = client.get_portfolio(&pid);
+    assert!(!portfolio.is_active);
+    assert_eq!(portfolio.pause_reason, PauseReason::UserPaused);
 }
 
 #[test]
-#[should_panic]
-fn test_initialize_guard() {
+#[should_panic(expected = "Error(Contract, #18)")]
+fn test_deposit_rejects_paused_portfolio() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-
-    client.initialize(&admin, &reflector_id);
+    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
+
+    let mut allocations = Map::new(&env);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.pause_portfolio(&pid, &PauseReason::VolatilityCircuitBreaker);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
 }
 
 #[test]
-#[should_panic]
-fn test_create_portfolio_invalid_allocation() {
+fn test_execute_rebalance_rejects_paused_portfolio() {
     let env = Env::default();
     env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 20_000;
+    });
+
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -1158,15 +6781,37 @@ fn test_create_portfolio_invalid_allocation() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 6000);
-    allocations.set(Address::generate(&env), 3000); // sums to 9000, not 10000
+    let asset = create_token(&env);
+    allocations.set(asset, 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.pause_portfolio(&pid, &PauseReason::UserPaused);
 
-    create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::PortfolioPaused)));
 }
 
 #[test]
-#[should_panic]
-fn test_create_portfolio_threshold_too_low() {
+fn test_contract_pause_reason_on_emergency_stop() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let admin = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    client.set_emergency_stop(&true);
+    assert_eq!(
+        client.get_contract_pause_reason(),
+        PauseReason::AdminEmergency
+    );
+
+    client.set_emergency_stop(&false);
+    assert_eq!(client.get_contract_pause_reason(), PauseReason::None);
+}
+
+#[test]
+fn test_check_invariants_inactive_portfolio() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
@@ -1177,14 +6822,18 @@ fn test_create_portfolio_threshold_too_low() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    client.withdraw(&pid, &asset, &100);
 
-    create_portfolio_with_defaults(&env, &client, &user, &allocations, 0, 50);
+    let result = client.try_check_invariants(&pid);
+    assert_eq!(result, Ok(Ok(())));
 }
 
 #[test]
-#[should_panic]
-fn test_create_portfolio_threshold_too_high() {
+fn test_withdraw_success() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
@@ -1195,19 +6844,21 @@ fn test_create_portfolio_threshold_too_high() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
+    client.withdraw(&pid, &asset, &400);
 
-    create_portfolio_with_defaults(&env, &client, &user, &allocations, 51, 50);
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 600);
+    assert!(portfolio.is_active);
 }
 
 #[test]
-fn test_create_portfolio_multiple_same_ledger() {
+fn test_withdraw_insufficient_balance() {
     let env = Env::default();
     env.mock_all_auths();
-    env.ledger().with_mut(|li| {
-        li.sequence_number = 1;
-    });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -1216,24 +6867,19 @@ fn test_create_portfolio_multiple_same_ledger() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
-
-    let pid1 = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    let pid2 = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
 
-    assert_eq!(pid1, 1);
-    assert_eq!(pid2, 2);
-    assert_ne!(pid1, pid2);
+    let result = client.try_withdraw(&pid, &asset, &200);
+    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
 }
 
 #[test]
-fn test_portfolio_id_starts_at_one() {
+fn test_withdraw_full_exit_deactivates_portfolio() {
     let env = Env::default();
     env.mock_all_auths();
-    env.ledger().with_mut(|li| {
-        li.sequence_number = 1;
-    });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -1242,50 +6888,94 @@ fn test_portfolio_id_starts_at_one() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    client.withdraw(&pid, &asset, &100);
 
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    assert_eq!(pid, 1);
+    let portfolio = client.get_portfolio(&pid);
+    assert!(!portfolio.is_active);
+    assert!(!portfolio.current_balances.contains_key(asset));
 }
 
 #[test]
-#[should_panic]
-fn test_create_portfolio_slippage_too_low() {
+fn test_deactivated_portfolio_rejects_deposit_and_rebalance_until_reactivated() {
     let env = Env::default();
     env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    client.initialize(&Address::generate(&env), &reflector_id);
+    let admin = Address::generate(&env);
     let user = Address::generate(&env);
-    let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
+    client.initialize(&admin, &reflector_id);
+
+    let (pid, asset1, _) = create_drifted_token_portfolio(&env, &client, &user);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    client.deactivate_portfolio(&pid);
+    assert!(!client.get_portfolio(&pid).is_active);
+    assert_eq!(client.get_user_portfolios(&user).len(), 0);
+
+    let deposit = client.try_deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    assert_eq!(deposit, Err(Ok(Error::PortfolioInactive)));
+    let rebalance = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(rebalance, Err(Ok(Error::PortfolioInactive)));
+
+    client.reactivate_portfolio(&pid);
+    assert!(client.get_portfolio(&pid).is_active);
+    assert_eq!(client.get_user_portfolios(&user), vec![&env, pid]);
 
-    create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 5);
+    mint(&env, &asset1, &user, 100);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 15000);
 }
 
 #[test]
-#[should_panic]
-fn test_create_portfolio_slippage_too_high() {
+fn test_withdraw_emits_event_with_amount() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    client.initialize(&Address::generate(&env), &reflector_id);
+    let admin = Address::generate(&env);
     let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
+    client.withdraw(&pid, &asset, &250);
 
-    create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 501);
+    let (emitter, topics, data) = env.events().all().last().unwrap();
+    assert_eq!(emitter, contract_id);
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("portfolio"),
+            symbol_short!("withdraw"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+    let payload: (u64, Address, i128) = data.into_val(&env);
+    assert_eq!(payload, (pid, asset, 250));
 }
 
 #[test]
-fn test_emergency_stop_admin_pause_and_reactivate() {
+fn test_deposit_and_withdraw_events_carry_resulting_balance() {
     let env = Env::default();
     env.mock_all_auths();
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -1294,163 +6984,293 @@ fn test_emergency_stop_admin_pause_and_reactivate() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
+    let asset = create_token(&env);
     allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let asset_event = |action: Symbol| {
+        let topics: Vec<soroban_sdk::Val> =
+            (action, asset.clone(), EVENT_TOPIC_VERSION).into_val(&env);
+        let (_, _, data) = env
+            .events()
+            .all()
+            .iter()
+            .filter(|(emitter, event_topics, _)| *emitter == contract_id && *event_topics == topics)
+            .last()
+            .expect("asset balance event");
+        let payload: (u64, i128, i128) = data.into_val(&env);
+        payload
+    };
 
-    client.set_emergency_stop(&true);
-    client.set_emergency_stop(&false);
+    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset, &500, &String::from_str(&env, ""));
+    assert_eq!(asset_event(symbol_short!("deposit")), (pid, 500, 1500));
 
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 100);
+    client.withdraw(&pid, &asset, &250);
+    assert_eq!(asset_event(symbol_short!("withdraw")), (pid, 250, 1250));
+    assert_eq!(client.get_balances(&pid).get(asset.clone()), Some(1250));
 }
 
 #[test]
-fn test_get_admin_returns_configured_admin() {
+fn test_admin_force_rebalance_bypasses_cooldown() {
     let env = Env::default();
     env.mock_all_auths();
 
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
+
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
+    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let configured_admin = client.get_admin();
-    assert_eq!(configured_admin, admin);
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10010;
+    });
+
+    let actual_balances = Map::new(&env);
+    client.admin_force_rebalance(&pid, &actual_balances);
+
+    let portfolio = client.get_portfolio(&pid);
+    assert_eq!(portfolio.last_rebalance, 10010);
 }
 
 #[test]
-#[should_panic]
-fn test_emergency_stop_non_admin_rejected() {
+fn test_admin_force_rebalance_overrides_owner_cooldown() {
     let env = Env::default();
+    env.mock_all_auths();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
 
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let non_admin = Address::generate(&env);
-    client
+    let user = Address::generate(&env);
+    client.initialize(&admin, &reflector_id);
+
+    // Drifted, but well inside the owner's two-hour minimum interval.
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    client.set_min_rebalance_interval(&pid, &7200);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10600;
+    });
+
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::CooldownActive))
+    );
+
+    // The owner's signature alone cannot force through the cooldown.
+    let owner_forced = client
         .mock_auths(&[MockAuth {
-            address: &admin,
+            address: &user,
             invoke: &MockAuthInvoke {
                 contract: &contract_id,
-                fn_name: "initialize",
-                args: (&admin, &reflector_id).into_val(&env),
+                fn_name: "admin_force_rebalance",
+                args: (pid, Map::<Address, i128>::new(&env)).into_val(&env),
                 sub_invokes: &[],
             },
         }])
-        .initialize(&admin, &reflector_id);
+        .try_admin_force_rebalance(&pid, &Map::new(&env));
+    assert_eq!(owner_forced, Err(Err(soroban_sdk::InvokeError::Abort)));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10000);
 
+    // The admin's signature alone is enough; the owner is not asked.
     client
         .mock_auths(&[MockAuth {
-            address: &non_admin,
+            address: &admin,
             invoke: &MockAuthInvoke {
                 contract: &contract_id,
-                fn_name: "set_emergency_stop",
-                args: vec![&env, true.into_val(&env)],
+                fn_name: "admin_force_rebalance",
+                args: (pid, Map::<Address, i128>::new(&env)).into_val(&env),
                 sub_invokes: &[],
             },
         }])
-        .set_emergency_stop(&true);
+        .admin_force_rebalance(&pid, &Map::new(&env));
+    assert_eq!(client.get_portfolio(&pid).last_rebalance, 10600);
+
+    let forced_topics: Vec<soroban_sdk::Val> = (
+        symbol_short!("rebalance"),
+        symbol_short!("forced"),
+        EVENT_TOPIC_VERSION,
+    )
+        .into_val(&env);
+    let (_, _, data) = env
+        .events()
+        .all()
+        .iter()
+        .find(|(emitter, topics, _)| *emitter == contract_id && *topics == forced_topics)
+        .expect("forced rebalance event");
+    let payload: (u64, Address, u64) = data.into_val(&env);
+    assert_eq!(payload, (pid, admin, 10600));
+    let legacy_topics: Vec<soroban_sdk::Val> = (
+        symbol_short!("portfolio"),
+        Symbol::new(&env, "cooldown_override"),
+        EVENT_TOPIC_VERSION,
+    )
+        .into_val(&env);
+    assert!(!env
+        .events()
+        .all()
+        .iter()
+        .any(|(emitter, topics, _)| emitter == contract_id && topics == legacy_topics));
 }
 
 #[test]
-fn test_create_portfolio_max_assets_limit() {
+fn test_portfolio_invariants_helper_rejects_invalid_allocations() {
     let env = Env::default();
-    env.mock_all_auths();
-
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
-
-    let mut max_allocations = Map::new(&env);
-    for _ in 0..MAX_PORTFOLIO_ASSETS {
-        max_allocations.set(
-            Address::generate(&env),
-            ALLOCATION_DENOMINATOR / MAX_PORTFOLIO_ASSETS,
-        );
-    }
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &max_allocations, 5, 50);
-    assert!(pid > 0);
-
-    let mut too_many_allocations = Map::new(&env);
-    for _ in 0..20u32 {
-        too_many_allocations.set(Address::generate(&env), 500);
-    }
-    let too_many_decimals =
-        allocation_decimals(&env, &too_many_allocations, DEFAULT_ASSET_DECIMALS);
-    let result = client.try_create_portfolio(
-        &user,
-        &too_many_allocations,
-        &too_many_decimals,
-        &5,
-        &50,
-        &CURRENT_SLIPPAGE_POLICY_VERSION,
+    let mut allocations = Map::new(&env);
+    allocations.set(create_token(&env), 4000);
+    allocations.set(create_token(&env), 4000);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations.clone(),
+        current_balances: Map::new(&env),
+        asset_decimals: allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS),
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 0,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+    assert_eq!(
+        crate::portfolio::check_portfolio_invariants(&portfolio),
+        Err(Error::InvariantViolation)
     );
-    assert_eq!(result, Err(Ok(Error::TooManyAssets)));
 }
 
 #[test]
-fn test_portfolio_storage_footprint_estimate_is_deterministic() {
+fn test_calculate_rebalance_trades_zero_total_value_produces_no_trades() {
     let env = Env::default();
+    let asset = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset.clone(), 10000);
+    let mut balances = Map::new(&env);
+    balances.set(asset.clone(), 50_000_000);
+    let portfolio = Portfolio {
+        user: Address::generate(&env),
+        target_allocations: allocations.clone(),
+        current_balances: balances,
+        asset_decimals: allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS),
+        rebalance_threshold: 500,
+        slippage_tolerance: 50,
+        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
+        last_rebalance: 0,
+        total_value: 0,
+        is_active: true,
+        pause_reason: PauseReason::None,
+        twap_records: 0,
+        min_trade_amounts: Map::new(&env),
+        rebalance_ratio_bps: FULL_REBALANCE_RATIO_BPS,
+        max_price_age: PRICE_MAX_AGE_SECONDS,
+        quote_asset: crate::reflector::Asset::Other(Symbol::new(&env, "USD")),
+        custody_mode: CustodyMode::SelfCustody,
+        no_trade_band_bps: 0,
+        max_twap_divergence_bps: 0,
+        rounding_mode: RoundingMode::Truncate,
+        max_trade_notional: 0,
+        min_interval_seconds: 0,
+        initial_grace_seconds: 0,
+        drift_scaling_cap_bps: 0,
+        min_balances: Map::new(&env),
+        fee_bps: 0,
+        fee_collector: None,
+        fee_asset: None,
+    };
+    let mut prices = Map::new(&env);
+    prices.set(asset, 100_00000000000000i128);
 
-    let portfolio = build_trade_test_portfolio(
-        &env,
-        &[
-            (Address::generate(&env), 7000),
-            (Address::generate(&env), 3000),
-        ],
-        &[],
-        0,
-    );
-
-    let portfolio_id = 7;
-    let estimate =
-        crate::portfolio::estimate_portfolio_storage_footprint(&env, portfolio_id, &portfolio);
-    let estimate_again =
-        crate::portfolio::estimate_portfolio_storage_footprint(&env, portfolio_id, &portfolio);
+    let trades = crate::portfolio::calculate_rebalance_trades(
+        &env,
+        &portfolio,
+        &prices,
+        REFLECTOR_PRICE_DECIMALS,
+    )
+    .unwrap();
+    assert!(trades.is_empty());
+}
 
-    assert_eq!(estimate, estimate_again);
-    assert!(estimate > 0);
-    assert_eq!(
-        crate::portfolio::validate_portfolio_storage_footprint(&env, portfolio_id, &portfolio),
-        Ok(estimate)
+fn assert_cost_within_tolerance(
+    name: &str,
+    cpu: u64,
+    mem: u64,
+    baseline_cpu: u64,
+    baseline_mem: u64,
+) {
+    let cpu_limit = baseline_cpu + (baseline_cpu * BENCHMARK_TOLERANCE_PERCENT / 100);
+    let mem_limit = baseline_mem + (baseline_mem * BENCHMARK_TOLERANCE_PERCENT / 100);
+
+    assert!(
+        cpu <= cpu_limit,
+        "{} CPU instruction usage exceeded threshold: actual={}, baseline={}, max_allowed={}",
+        name,
+        cpu,
+        baseline_cpu,
+        cpu_limit
+    );
+    assert!(
+        mem <= mem_limit,
+        "{} memory usage exceeded threshold: actual={}, baseline={}, max_allowed={}",
+        name,
+        mem,
+        baseline_mem,
+        mem_limit
     );
 }
 
 #[test]
-fn test_transfer_stewardship() {
+fn benchmark_initialize_gas() {
     let env = Env::default();
     env.mock_all_auths();
+    env.budget().reset_unlimited();
+    env.budget().reset_tracker();
 
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
-
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-
-    let new_steward = Address::generate(&env);
-    client.transfer_stewardship(&pid, &new_steward);
-
-    let steward = client.get_steward(&pid);
-    assert_eq!(steward, new_steward);
+    assert_cost_within_tolerance(
+        "initialize",
+        env.budget().cpu_instruction_cost(),
+        env.budget().memory_bytes_cost(),
+        BASELINE_INITIALIZE_CPU,
+        BASELINE_INITIALIZE_MEM,
+    );
 }
 
 #[test]
-fn test_transfer_stewardship_steward_can_deposit() {
+fn benchmark_create_portfolio_gas() {
     let env = Env::default();
     env.mock_all_auths();
+    env.budget().reset_unlimited();
 
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
@@ -1460,33 +7280,28 @@ fn test_transfer_stewardship_steward_can_deposit() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-
-    let new_steward = Address::generate(&env);
-    client.transfer_stewardship(&pid, &new_steward);
-
-    client
-        .mock_auths(&[MockAuth {
-            address: &new_steward,
-            invoke: &MockAuthInvoke {
-                contract: &contract_id,
-                fn_name: "deposit",
-                args: (pid, asset.clone(), 500i128, String::from_str(&env, "")).into_val(&env),
-                sub_invokes: &[],
-            },
-        }])
-        .deposit(&pid, &asset, &500, &String::from_str(&env, ""));
+    allocations.set(create_token(&env), 10000);
 
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 500);
+    env.budget().reset_tracker();
+    let _ = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_cost_within_tolerance(
+        "create_portfolio",
+        env.budget().cpu_instruction_cost(),
+        env.budget().memory_bytes_cost(),
+        BASELINE_CREATE_PORTFOLIO_CPU,
+        BASELINE_CREATE_PORTFOLIO_MEM,
+    );
 }
 
 #[test]
-fn test_preview_rebalance_reports_trades_and_thresholds() {
+fn benchmark_execute_rebalance_gas() {
     let env = Env::default();
     env.mock_all_auths();
+    env.budget().reset_unlimited();
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
 
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
@@ -1495,28 +7310,28 @@ fn test_preview_rebalance_reports_trades_and_thresholds() {
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    allocations.set(asset1.clone(), 5000);
-    allocations.set(asset2.clone(), 5000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
 
-    client.deposit(&pid, &asset1, &20_000_000, &String::from_str(&env, ""));
-    client.deposit(&pid, &asset2, &10_000_000, &String::from_str(&env, ""));
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15_000;
+    });
 
-    let preview = client.preview_rebalance(&pid);
-    assert!(preview.rebalance_needed);
-    assert!(preview.candidate_trades.contains_key(asset1.clone()));
-    assert!(preview.threshold_decisions.contains_key(asset1.clone()));
-    let decision = preview.threshold_decisions.get(asset1).unwrap();
-    assert!(decision.exceeds_threshold);
+    env.budget().reset_tracker();
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_cost_within_tolerance(
+        "execute_rebalance",
+        env.budget().cpu_instruction_cost(),
+        env.budget().memory_bytes_cost(),
+        BASELINE_EXECUTE_REBALANCE_CPU,
+        BASELINE_EXECUTE_REBALANCE_MEM,
+    );
 }
 
 #[test]
-fn test_preview_rebalance_does_not_mutate_portfolio() {
+fn benchmark_deposit_gas() {
     let env = Env::default();
     env.mock_all_auths();
+    env.budget().reset_unlimited();
 
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
@@ -1526,48 +7341,32 @@ fn test_preview_rebalance_does_not_mutate_portfolio() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
+    let asset = create_token(&env);
     allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-
-    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let before = client.get_portfolio(&pid);
-    let _ = client.preview_rebalance(&pid);
-    let after = client.get_portfolio(&pid);
-    assert_eq!(before.last_rebalance, after.last_rebalance);
-    assert_eq!(
-        before.current_balances.get(asset.clone()).unwrap(),
-        after.current_balances.get(asset).unwrap()
+    env.budget().reset_tracker();
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    assert_cost_within_tolerance(
+        "deposit",
+        env.budget().cpu_instruction_cost(),
+        env.budget().memory_bytes_cost(),
+        BASELINE_DEPOSIT_CPU,
+        BASELINE_DEPOSIT_MEM,
     );
 }
 
+// ── Issue #861: rebalance validates allocation sum ──────────────────────
+
 #[test]
-fn test_create_portfolio_stores_slippage_policy_version() {
+fn test_rebalance_rejects_invalid_allocation_sum() {
     let env = Env::default();
     env.mock_all_auths();
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(
-        portfolio.slippage_policy_version,
-        SLIPPAGE_POLICY_VERSION_V1
-    );
-}
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
 
-#[test]
-#[should_panic]
-fn test_transfer_stewardship_unauthorized() {
-    let env = Env::default();
-    env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -1576,29 +7375,38 @@ fn test_transfer_stewardship_unauthorized() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let asset = create_token(&env);
+    allocations.set(asset.clone(), 10000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let unauthorized = Address::generate(&env);
-    let attacker = Address::generate(&env);
-    client
-        .mock_auths(&[MockAuth {
-            address: &unauthorized,
-            invoke: &MockAuthInvoke {
-                contract: &contract_id,
-                fn_name: "transfer_stewardship",
-                args: (pid, attacker.clone()).into_val(&env),
-                sub_invokes: &[],
-            },
-        }])
-        .transfer_stewardship(&pid, &attacker);
+    // Corrupt stored allocations via as_contract so they don't sum to 10000
+    env.as_contract(&contract_id, || {
+        let mut portfolio: Portfolio = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Portfolio(pid))
+            .unwrap();
+        portfolio.target_allocations.set(asset.clone(), 9900);
+        env.storage()
+            .persistent()
+            .set(&DataKey::Portfolio(pid), &portfolio);
+    });
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+
+    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(result, Err(Ok(Error::InvalidAllocationSum)));
 }
 
+// ── Issue #862: portfolio value in USD view function ────────────────────
+
 #[test]
-fn test_get_steward_defaults_to_user() {
+fn test_get_portfolio_value_usd_basic() {
     let env = Env::default();
     env.mock_all_auths();
+
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -1607,100 +7415,76 @@ fn test_get_steward_defaults_to_user() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    let steward = client.get_steward(&pid);
-    assert_eq!(steward, user);
-}
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
 
-#[test]
-fn test_capabilities() {
-    let env = Env::default();
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let caps = client.capabilities();
-    assert!(caps & CapabilityFlag::PerPortfolioSteward as u32 != 0);
-    assert!(caps & CapabilityFlag::DifferentiatedPricing as u32 != 0);
-    assert!(caps & CapabilityFlag::EmergencyStop as u32 != 0);
+    let valuation = client.get_portfolio_value_usd(&pid);
+    assert!(valuation.total_usd_value > 0);
+    assert_eq!(valuation.assets.len(), 2);
 }
 
 #[test]
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
-fn test_capability_summary() {
-    let env = Env::default();
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    
-    assert_eq!(client.version(), 1);
-    assert_eq!(client.schema_version(), 1);
-    
-    let summary = client.capability_summary();
-    assert_eq!(summary.version, 1);
-    assert_eq!(summary.schema_version, 1);
-    assert!(summary.capability_flags & CapabilityFlag::PerPortfolioSteward as u32 != 0);
-    assert!(summary.capability_flags & CapabilityFlag::DifferentiatedPricing as u32 != 0);
-    assert!(summary.capability_flags & CapabilityFlag::EmergencyStop as u32 != 0);
-    assert_eq!(summary.min_rebalance_threshold, 1);
-    assert_eq!(summary.max_rebalance_threshold, 50);
-    assert_eq!(summary.min_slippage_tolerance_bps, 10);
-    assert_eq!(summary.max_slippage_tolerance_bps, 500);
-    assert_eq!(summary.max_portfolio_assets, 10);
-}
-
-// // #[test]
-
-fn test_missing_price_error() {
+fn test_get_portfolio_value_usd_drift() {
     let env = Env::default();
     env.mock_all_auths();
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let missing_reflector_id =
-        env.register_contract(None, reflector_without_prices::ReflectorWithoutPrices);
+    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
-    client.initialize(&admin, &missing_reflector_id);
+    client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    env.ledger().with_mut(|li| {
-        li.timestamp = 20000;
-    });
+    // Deposit unequal amounts to create drift
+    client.deposit(&pid, &asset1, &200, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
 
-    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
-    assert!(result.is_err());
+    let valuation = client.get_portfolio_value_usd(&pid);
+    assert!(valuation.total_usd_value > 0);
+
+    // At least one asset should have non-zero drift
+    let mut has_drift = false;
+    for i in 0..valuation.assets.len() {
+        let av = valuation.assets.get(i).unwrap();
+        if av.drift != 0 {
+            has_drift = true;
+        }
+    }
+    assert!(has_drift);
 }
 
 #[test]
-#[should_panic]
-fn test_create_portfolio_unsupported_slippage_policy_version() {
+fn test_get_portfolio_value_usd_not_found() {
     let env = Env::default();
     env.mock_all_auths();
+
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
-    let asset_decimals = allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS);
-    client.create_portfolio(&user, &allocations, &asset_decimals, &5, &50, &99);
+    let result = client.try_get_portfolio_value_usd(&999);
+    assert_eq!(result, Err(Ok(Error::PortfolioNotFound)));
 }
 
+// ── Issue #859: fractional allocation (basis points) ────────────────────
+
 #[test]
-#[should_panic]
-fn test_create_portfolio_invalid_asset_decimals() {
+fn test_fractional_allocation_three_way_equal() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
@@ -1711,44 +7495,51 @@ fn test_create_portfolio_invalid_asset_decimals() {
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
+    let a1 = create_token(&env);
+    let a2 = create_token(&env);
+    let a3 = create_token(&env);
+    // 33.33% + 33.33% + 33.34% = 100.00%
+    allocations.set(a1, 3333);
+    allocations.set(a2, 3333);
+    allocations.set(a3, 3334);
 
-    let empty_decimals = Map::new(&env);
-    client.create_portfolio(
-        &user,
-        &allocations,
-        &empty_decimals,
-        &5,
-        &50,
-        &CURRENT_SLIPPAGE_POLICY_VERSION,
-    );
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert!(pid > 0);
 }
 
 #[test]
-fn test_pause_portfolio_persists_reason() {
+fn test_get_config_reflects_initialize_and_flags() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    assert_eq!(
+        client.get_config(),
+        ContractConfig {
+            admin: admin.clone(),
+            reflector_address: reflector_id.clone(),
+            paused: false,
+            emergency_stop: false,
+            fee_bps: 0,
+        }
+    );
 
-    client.pause_portfolio(&pid, &PauseReason::UserPaused);
-    let portfolio = client.get_portfolio(&pid);
-    assert!(!portfolio.is_active);
-    assert_eq!(portfolio.pause_reason, PauseReason::UserPaused);
+    client.pause();
+    client.set_emergency_stop(&true);
+    client.set_treasury_fee_bps(&25);
+    let config = client.get_config();
+    assert!(config.paused);
+    assert!(config.emergency_stop);
+    assert_eq!(config.fee_bps, 25);
+    assert_eq!(config.admin, admin);
 }
 
 #[test]
-#[should_panic(expected = "Error(Contract, #18)")]
-fn test_deposit_rejects_paused_portfolio() {
+fn test_get_config_view_success() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
@@ -1756,108 +7547,198 @@ fn test_deposit_rejects_paused_portfolio() {
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
+
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
+    let asset = create_token(&env);
     allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.pause_portfolio(&pid, &PauseReason::VolatilityCircuitBreaker);
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+
+    let config_view = client.get_config_view(&pid);
+
+    assert_eq!(config_view.admin, admin);
+    assert_eq!(config_view.reflector_address, reflector_id);
+    assert!(!config_view.emergency_stop);
+
+    let portfolio = match config_view.portfolio {
+        PortfolioOption::Some(p) => p,
+        PortfolioOption::None => panic!("Expected PortfolioOption::Some"),
+    };
+    assert_eq!(portfolio.user, user);
+    assert_eq!(portfolio.rebalance_threshold, 500);
+    assert_eq!(portfolio.slippage_tolerance, 50);
 }
 
 #[test]
-fn test_execute_rebalance_rejects_paused_portfolio() {
+fn test_get_config_view_no_portfolio() {
     let env = Env::default();
     env.mock_all_auths();
-    env.ledger().with_mut(|li| {
-        li.timestamp = 20_000;
-    });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let user = Address::generate(&env);
+
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset, 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.pause_portfolio(&pid, &PauseReason::UserPaused);
+    let config_view = client.get_config_view(&999);
 
-    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
-    assert_eq!(result, Err(Ok(Error::PortfolioPaused)));
+    assert_eq!(config_view.admin, admin);
+    assert_eq!(config_view.reflector_address, reflector_id);
+    assert!(!config_view.emergency_stop);
+    assert_eq!(config_view.portfolio, PortfolioOption::None);
 }
 
 #[test]
-fn test_contract_pause_reason_on_emergency_stop() {
+fn test_get_config_view_emergency_stop() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
 
+    client.initialize(&admin, &reflector_id);
     client.set_emergency_stop(&true);
-    assert_eq!(
-        client.get_contract_pause_reason(),
-        PauseReason::AdminEmergency
-    );
 
-    client.set_emergency_stop(&false);
-    assert_eq!(client.get_contract_pause_reason(), PauseReason::None);
+    let config_view = client.get_config_view(&1);
+
+    assert_eq!(config_view.admin, admin);
+    assert_eq!(config_view.reflector_address, reflector_id);
+    assert!(config_view.emergency_stop);
+    assert_eq!(config_view.portfolio, PortfolioOption::None);
+}
+
+/// Smallest module the host accepts as contract code: the wasm header plus
+/// a `contractenvmetav0` section declaring interface version 21.
+fn empty_upgrade_wasm() -> [u8; 40] {
+    [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // wasm magic and version
+        0x00, 0x1e, 0x11, // custom section, 30 bytes, 17-byte name
+        b'c', b'o', b'n', b't', b'r', b'a', b'c', b't', b'e', b'n', b'v', b'm', b'e', b't', b'a',
+        b'v', b'0', // "contractenvmetav0"
+        0x00, 0x00, 0x00, 0x00, // SC_ENV_META_KIND_INTERFACE_VERSION
+        0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, 0x00, // protocol 21, pre-release 0
+    ]
 }
 
 #[test]
-fn test_check_invariants_inactive_portfolio() {
+fn test_upgrade_by_admin_swaps_wasm_and_records_hash() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
-    client.withdraw(&pid, &asset, &100);
+    let new_wasm_hash = env
+        .deployer()
+        .upload_contract_wasm(soroban_sdk::Bytes::from_slice(&env, &empty_upgrade_wasm()));
+    client.upgrade(&new_wasm_hash);
+    assert_eq!(
+        env.auths(),
+        std::vec![(
+            admin.clone(),
+            AuthorizedInvocation {
+                function: AuthorizedFunction::Contract((
+                    contract_id.clone(),
+                    Symbol::new(&env, "upgrade"),
+                    vec![&env, new_wasm_hash.into_val(&env)],
+                )),
+                sub_invocations: std::vec![],
+            }
+        )]
+    );
 
-    let result = client.try_check_invariants(&pid);
-    assert_eq!(result, Ok(Ok(())));
+    let (_, topics, data) = env
+        .events()
+        .all()
+        .iter()
+        .filter(|(emitter, _, _)| emitter == &contract_id)
+        .last()
+        .unwrap();
+    assert_eq!(
+        topics,
+        (
+            symbol_short!("contract"),
+            symbol_short!("upgraded"),
+            EVENT_TOPIC_VERSION
+        )
+            .into_val(&env)
+    );
+    let event: UpgradeEvent = data.into_val(&env);
+    assert_eq!(event.from_hash, BytesN::from_array(&env, &[0u8; 32]));
+    assert_eq!(event.to_hash, new_wasm_hash);
+
+    // The contract now runs the uploaded code, so storage is read directly.
+    let stored_hash: BytesN<32> = env.as_contract(&contract_id, || {
+        env.storage().instance().get(&DataKey::WasmHash).unwrap()
+    });
+    assert_eq!(stored_hash, new_wasm_hash);
 }
 
 #[test]
-fn test_withdraw_success() {
+fn test_upgrade_non_admin_rejected() {
     let env = Env::default();
-    env.mock_all_auths();
+
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    client.initialize(&admin, &reflector_id);
-
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.deposit(&pid, &asset, &1000, &String::from_str(&env, ""));
-    client.withdraw(&pid, &asset, &400);
+    let non_admin = Address::generate(&env);
+    client
+        .mock_auths(&[MockAuth {
+            address: &admin,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "initialize",
+                args: (&admin, &reflector_id).into_val(&env),
+                sub_invokes: &[],
+            },
+        }])
+        .initialize(&admin, &reflector_id);
 
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.current_balances.get(asset).unwrap(), 600);
-    assert!(portfolio.is_active);
+    let new_wasm_hash = env
+        .deployer()
+        .upload_contract_wasm(soroban_sdk::Bytes::from_slice(&env, &empty_upgrade_wasm()));
+    let result = client
+        .mock_auths(&[MockAuth {
+            address: &non_admin,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "upgrade",
+                args: vec![&env, new_wasm_hash.into_val(&env)],
+                sub_invokes: &[],
+            },
+        }])
+        .try_upgrade(&new_wasm_hash);
+    // The host's auth error is escalated to a panic inside the natively
+    // registered contract, so the caller sees it as a context error; the
+    // diagnostic log keeps the underlying auth failure.
+    assert_eq!(
+        result,
+        Err(Ok(soroban_sdk::Error::from_type_and_code(
+            soroban_sdk::xdr::ScErrorType::Context,
+            soroban_sdk::xdr::ScErrorCode::InvalidAction,
+        )))
+    );
+    let auth_error = soroban_sdk::xdr::ScVal::Error(soroban_sdk::xdr::ScError::Auth(
+        soroban_sdk::xdr::ScErrorCode::InvalidAction,
+    ));
+    let diagnostics = env.host().get_diagnostic_events().unwrap();
+    assert!(diagnostics.0.iter().any(|event| {
+        let soroban_sdk::xdr::ContractEventBody::V0(body) = &event.event.body;
+        event.failed_call && body.topics.iter().any(|topic| topic == &auth_error)
+    }));
+    let stored_hash: Option<BytesN<32>> = env.as_contract(&contract_id, || {
+        env.storage().instance().get(&DataKey::WasmHash)
+    });
+    assert_eq!(stored_hash, None);
 }
 
 #[test]
-fn test_withdraw_insufficient_balance() {
+fn test_global_stats_track_portfolio_count_and_value() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
@@ -1866,250 +7747,274 @@ fn test_withdraw_insufficient_balance() {
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
+    assert_eq!(client.get_global_stats(), GlobalStats::default());
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
+    let asset = create_token(&env);
     allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    let first = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let second = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(client.get_global_stats().portfolio_count, 2);
+
+    client.deposit(&first, &asset, &1000, &String::from_str(&env, ""));
+    client.deposit(&second, &asset, &500, &String::from_str(&env, ""));
+    let stats = client.get_global_stats();
+    let first_value = client.get_portfolio(&first).total_value;
+    let second_value = client.get_portfolio(&second).total_value;
+    assert!(first_value > 0 && second_value > 0);
+    assert_eq!(
+        stats.total_value_under_management,
+        first_value + second_value
+    );
 
-    let result = client.try_withdraw(&pid, &asset, &200);
-    assert_eq!(result, Err(Ok(Error::InsufficientBalance)));
+    client.withdraw(&first, &asset, &250);
+    let first_value = client.get_portfolio(&first).total_value;
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        first_value + second_value
+    );
+
+    client.close_portfolio(&second);
+    let stats = client.get_global_stats();
+    assert_eq!(stats.portfolio_count, 1);
+    assert_eq!(stats.total_value_under_management, first_value);
+
+    // Closing twice must not count the portfolio out again.
+    client.close_portfolio(&second);
+    assert_eq!(client.get_global_stats().portfolio_count, 1);
+
+    client.deactivate_portfolio(&first);
+    assert_eq!(client.get_global_stats().portfolio_count, 0);
+    client.reactivate_portfolio(&first);
+    assert_eq!(client.get_global_stats().portfolio_count, 1);
 }
 
 #[test]
-fn test_withdraw_full_exit_deactivates_portfolio() {
+fn test_global_stats_value_returns_to_zero_after_fee_rebalance_and_close() {
     let env = Env::default();
     env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10000;
+    });
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
+    let treasury = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
-    client.withdraw(&pid, &asset, &100);
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    client.set_treasury(&treasury);
+    client.set_treasury_fee_bps(&100);
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        30_000_000_000
+    );
 
-    let portfolio = client.get_portfolio(&pid);
-    assert!(!portfolio.is_active);
-    assert!(!portfolio.current_balances.contains_key(asset));
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15000;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        29_700_000_000
+    );
+
+    client.close_portfolio(&pid);
+    assert_eq!(client.get_global_stats().total_value_under_management, 0);
 }
 
 #[test]
-fn test_admin_force_rebalance_bypasses_cooldown() {
+fn test_global_stats_value_excludes_portfolios_in_other_quotes() {
     let env = Env::default();
     env.mock_all_auths();
-
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
-    });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let reflector_id =
+        env.register_contract(None, reflector_with_cross_rates::ReflectorWithCrossRates);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
+    let asset = create_token(&env);
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
     allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
-
-    env.ledger().with_mut(|li| {
-        li.timestamp = 10010;
-    });
-
-    let actual_balances = Map::new(&env);
-    client.admin_force_rebalance(&pid, &actual_balances);
+    let in_base = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    let switched = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    for pid in [in_base, switched] {
+        client.deposit(&pid, &asset, &350, &String::from_str(&env, ""));
+    }
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        70_000
+    );
 
-    let portfolio = client.get_portfolio(&pid);
-    assert_eq!(portfolio.last_rebalance, 10010);
-}
+    // Values in EUR stay out of the USD total, however they change.
+    let usd = Asset::Other(Symbol::new(&env, "USD"));
+    let eur = Asset::Other(Symbol::new(&env, "EUR"));
+    client.set_quote_asset(&switched, &eur);
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        35_000
+    );
+    client.deposit(&switched, &asset, &100, &String::from_str(&env, ""));
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        35_000
+    );
+    client.set_quote_asset(&switched, &usd);
+    assert_eq!(
+        client.get_global_stats().total_value_under_management,
+        80_000
+    );
 
-#[test]
-fn test_portfolio_invariants_helper_rejects_invalid_allocations() {
-    let env = Env::default();
-    let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 4000);
-    allocations.set(Address::generate(&env), 4000);
-    let portfolio = Portfolio {
-        user: Address::generate(&env),
-        target_allocations: allocations.clone(),
-        current_balances: Map::new(&env),
-        asset_decimals: allocation_decimals(&env, &allocations, DEFAULT_ASSET_DECIMALS),
-        rebalance_threshold: 5,
-        slippage_tolerance: 50,
-        slippage_policy_version: CURRENT_SLIPPAGE_POLICY_VERSION,
-        last_rebalance: 0,
-        total_value: 0,
-        is_active: true,
-        pause_reason: PauseReason::None,
-    };
+    client.set_base_asset_override(&Some(eur.clone()));
+    let pinned = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pinned, &asset, &350, &String::from_str(&env, ""));
+    client.close_portfolio(&pinned);
+    client.close_portfolio(&switched);
     assert_eq!(
-        crate::portfolio::check_portfolio_invariants(&portfolio),
-        Err(Error::InvariantViolation)
+        client.get_global_stats().total_value_under_management,
+        35_000
     );
 }
 
-fn assert_cost_within_tolerance(
-    name: &str,
-    cpu: u64,
-    mem: u64,
-    baseline_cpu: u64,
-    baseline_mem: u64,
-) {
-    let cpu_limit = baseline_cpu + (baseline_cpu * BENCHMARK_TOLERANCE_PERCENT / 100);
-    let mem_limit = baseline_mem + (baseline_mem * BENCHMARK_TOLERANCE_PERCENT / 100);
+#[test]
+fn test_normalize_allocations_rescales_rounded_percentages() {
+    let env = Env::default();
+    let contract_id = env.register_contract(None, PortfolioRebalancer);
+    let client = PortfolioRebalancerClient::new(&env, &contract_id);
 
-    assert!(
-        cpu <= cpu_limit,
-        "CPU instruction usage exceeded threshold: actual={}, baseline={}, max_allowed={}",
-        cpu,
-        baseline_cpu,
-        cpu_limit
-    );
-    assert!(
-        mem <= mem_limit,
-        "Memory usage exceeded threshold: actual={}, baseline={}, max_allowed={}",
-        mem,
-        baseline_mem,
-        mem_limit
+    let (a, b, c) = (
+        Address::generate(&env),
+        Address::generate(&env),
+        Address::generate(&env),
     );
+    let mut raw = Map::new(&env);
+    raw.set(a.clone(), 33);
+    raw.set(b.clone(), 33);
+    raw.set(c.clone(), 33);
+
+    let normalized = client.normalize_allocations(&raw);
+    assert!(crate::portfolio::validate_allocations(&normalized));
+    let mut weights: std::vec::Vec<u32> = [a, b, c]
+        .iter()
+        .map(|asset| normalized.get(asset.clone()).unwrap())
+        .collect();
+    weights.sort();
+    assert_eq!(weights, [3333, 3333, 3334]);
 }
 
 #[test]
-fn benchmark_initialize_gas() {
+fn test_normalize_allocations_rejects_wildly_off_sums() {
     let env = Env::default();
-    env.mock_all_auths();
-    env.budget().reset_unlimited();
-    env.budget().reset_tracker();
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let _ = client.initialize(&admin, &reflector_id);
-    assert_cost_within_tolerance(
-        "initialize",
-        env.budget().cpu_instruction_cost(),
-        env.budget().memory_bytes_cost(),
-        BASELINE_INITIALIZE_CPU,
-        BASELINE_INITIALIZE_MEM,
+
+    let mut raw = Map::new(&env);
+    raw.set(Address::generate(&env), 5);
+    raw.set(Address::generate(&env), 5);
+    assert_eq!(
+        client.try_normalize_allocations(&raw),
+        Err(Ok(Error::InvalidAllocation))
     );
 }
 
 #[test]
-fn benchmark_create_portfolio_gas() {
+fn test_cached_reflector_decimals_refresh_on_rotation() {
     let env = Env::default();
     env.mock_all_auths();
-    env.budget().reset_unlimited();
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let reflector_id = env.register_contract(None, reflector_with_decimals::ReflectorWithDecimals);
+    let reflector = reflector_with_decimals::ReflectorWithDecimalsClient::new(&env, &reflector_id);
+    reflector.set_decimals(&7);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
-    let _ = client.initialize(&admin, &reflector_id);
+    client.initialize(&admin, &reflector_id);
 
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
     let mut allocations = Map::new(&env);
-    allocations.set(Address::generate(&env), 10000);
+    allocations.set(asset1.clone(), 5000);
+    allocations.set(asset2.clone(), 5000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &250, &String::from_str(&env, ""));
+    assert_eq!(client.get_portfolio_value(&pid), 35_000);
 
-    env.budget().reset_tracker();
-    let _ = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    assert_cost_within_tolerance(
-        "create_portfolio",
-        env.budget().cpu_instruction_cost(),
-        env.budget().memory_bytes_cost(),
-        BASELINE_CREATE_PORTFOLIO_CPU,
-        BASELINE_CREATE_PORTFOLIO_MEM,
-    );
+    // The cached precision stands until the oracle is configured again, so
+    // prices now quoted at 14 decimals read as ten million times larger.
+    reflector.set_decimals(&14);
+    assert_eq!(client.get_portfolio_value(&pid), 35_000 * 10i128.pow(7));
+
+    let rotated_id = env.register_contract(None, reflector_with_decimals::ReflectorWithDecimals);
+    client.set_reflector_address(&rotated_id);
+    assert_eq!(client.get_portfolio_value(&pid), 35_000);
+
+    client.set_reflector_address(&reflector_id);
+    assert_eq!(client.get_portfolio_value(&pid), 35_000);
 }
 
 #[test]
-fn benchmark_execute_rebalance_gas() {
+fn test_route_hint_splits_swap_into_two_hops() {
     let env = Env::default();
     env.mock_all_auths();
-    env.budget().reset_unlimited();
-
     env.ledger().with_mut(|li| {
         li.timestamp = 10_000;
     });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
-    let _ = client.initialize(&admin, &reflector_id);
-
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
+    client.initialize(&admin, &reflector_id);
 
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let (direct, direct_sell, direct_buy) = create_drifted_token_portfolio(&env, &client, &user);
+    let (routed, routed_sell, routed_buy) = create_drifted_token_portfolio(&env, &client, &user);
+    let via = Address::generate(&env);
+    client.set_route_hint(&routed_sell, &routed_buy, &Some(via.clone()));
+    assert_eq!(
+        client.get_route_hint(&routed_sell, &routed_buy),
+        Some(via.clone())
+    );
+    assert_eq!(client.get_route_hint(&direct_sell, &direct_buy), None);
+    assert_eq!(
+        client.try_set_route_hint(&routed_sell, &routed_buy, &Some(routed_buy.clone())),
+        Err(Ok(Error::DuplicateAsset))
+    );
 
     env.ledger().with_mut(|li| {
         li.timestamp = 15_000;
     });
-
-    env.budget().reset_tracker();
-    let _ = client.execute_rebalance(&pid, &Map::new(&env));
-    assert_cost_within_tolerance(
-        "execute_rebalance",
-        env.budget().cpu_instruction_cost(),
-        env.budget().memory_bytes_cost(),
-        BASELINE_EXECUTE_REBALANCE_CPU,
-        BASELINE_EXECUTE_REBALANCE_MEM,
+    let result = client.execute_rebalance(&direct, &Map::new(&env));
+    assert_eq!(result.hops.len(), 1);
+    let hop = result.hops.get(0).unwrap();
+    assert_eq!((hop.from, hop.to), (direct_sell, direct_buy));
+
+    let result = client.execute_rebalance(&routed, &Map::new(&env));
+    assert_eq!(result.hops.len(), 2);
+    let (first, second) = (result.hops.get(0).unwrap(), result.hops.get(1).unwrap());
+    assert_eq!(
+        (first.from, first.to.clone()),
+        (routed_sell.clone(), via.clone())
     );
-}
-
-#[test]
-fn benchmark_deposit_gas() {
-    let env = Env::default();
-    env.mock_all_auths();
-    env.budget().reset_unlimited();
-
-    let contract_id = env.register_contract(None, PortfolioRebalancer);
-    let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
-    let admin = Address::generate(&env);
-    let user = Address::generate(&env);
-    let _ = client.initialize(&admin, &reflector_id);
-
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    assert_eq!((second.from, second.to), (via, routed_buy.clone()));
+    assert!(first.value > 0);
+    assert_eq!(first.value, second.value);
 
-    env.budget().reset_tracker();
-    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
-    assert_cost_within_tolerance(
-        "deposit",
-        env.budget().cpu_instruction_cost(),
-        env.budget().memory_bytes_cost(),
-        BASELINE_DEPOSIT_CPU,
-        BASELINE_DEPOSIT_MEM,
-    );
+    client.set_route_hint(&routed_sell, &routed_buy, &None);
+    assert_eq!(client.get_route_hint(&routed_sell, &routed_buy), None);
 }
 
-// ── Issue #861: rebalance validates allocation sum ──────────────────────
-
 #[test]
-fn test_rebalance_rejects_invalid_allocation_sum() {
+fn test_notify_prefs_gate_tagged_notification_events() {
     let env = Env::default();
     env.mock_all_auths();
-
     env.ledger().with_mut(|li| {
-        li.timestamp = 10000;
+        li.timestamp = 10_000;
     });
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -2117,39 +8022,54 @@ fn test_rebalance_rejects_invalid_allocation_sum() {
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
-    allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(client.get_notify_prefs(&pid), 0);
+    assert_eq!(
+        client.try_set_notify_prefs(&pid, &(NOTIFY_ALL + 1)),
+        Err(Ok(Error::InvalidNotifyPrefs))
+    );
 
-    // Corrupt stored allocations via as_contract so they don't sum to 10000
-    env.as_contract(&contract_id, || {
-        let mut portfolio: Portfolio = env
-            .storage()
-            .persistent()
-            .get(&DataKey::Portfolio(pid))
-            .unwrap();
-        portfolio.target_allocations.set(asset.clone(), 9900);
-        env.storage()
-            .persistent()
-            .set(&DataKey::Portfolio(pid), &portfolio);
-    });
+    let notifications = |kind: Symbol| {
+        let topics: Vec<soroban_sdk::Val> =
+            (symbol_short!("notify"), kind, EVENT_TOPIC_VERSION).into_val(&env);
+        env.events()
+            .all()
+            .iter()
+            .filter(|(emitter, event_topics, _)| *emitter == contract_id && *event_topics == topics)
+            .map(|(_, _, data)| {
+                let payload: (u64, Address) = data.into_val(&env);
+                payload
+            })
+            .collect::<std::vec::Vec<_>>()
+    };
+
+    // Without preferences the drift goes untagged.
+    assert!(client.check_rebalance_needed(&pid));
+    assert!(notifications(symbol_short!("drift")).is_empty());
+
+    client.set_notify_prefs(&pid, &(NOTIFY_DRIFT_EXCEEDED | NOTIFY_REBALANCE_EXECUTED));
+    assert_eq!(
+        client.get_notify_prefs(&pid),
+        NOTIFY_DRIFT_EXCEEDED | NOTIFY_REBALANCE_EXECUTED
+    );
+    assert!(client.check_rebalance_needed(&pid));
+    assert_eq!(notifications(symbol_short!("drift")), [(pid, user.clone())]);
 
     env.ledger().with_mut(|li| {
-        li.timestamp = 15000;
+        li.timestamp = 15_000;
     });
-
-    let result = client.try_execute_rebalance(&pid, &Map::new(&env));
-    assert_eq!(result, Err(Ok(Error::InvalidAllocationSum)));
+    client.execute_rebalance(&pid, &Map::new(&env));
+    assert_eq!(
+        notifications(symbol_short!("rebalance")),
+        [(pid, user.clone())]
+    );
+    assert!(notifications(symbol_short!("stale")).is_empty());
 }
 
-// ── Issue #862: portfolio value in USD view function ────────────────────
-
 #[test]
-fn test_get_portfolio_value_usd_basic() {
+fn test_deposit_with_target_adds_asset_and_rescales_weights() {
     let env = Env::default();
     env.mock_all_auths();
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -2157,26 +8077,34 @@ fn test_get_portfolio_value_usd_basic() {
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
     let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
-    allocations.set(asset1.clone(), 5000);
-    allocations.set(asset2.clone(), 5000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    allocations.set(asset1.clone(), 6000);
+    allocations.set(asset2.clone(), 4000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
 
-    client.deposit(&pid, &asset1, &100, &String::from_str(&env, ""));
-    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
+    let asset3 = create_token(&env);
+    client.deposit_with_target(&pid, &asset3, &1000, &2000);
+    let targets = client.get_target_allocations(&pid);
+    assert_eq!(targets.get(asset1.clone()), Some(4800));
+    assert_eq!(targets.get(asset2.clone()), Some(3200));
+    assert_eq!(targets.get(asset3.clone()), Some(2000));
+    assert_eq!(client.get_balances(&pid).get(asset3.clone()), Some(1000));
 
-    let valuation = client.get_portfolio_value_usd(&pid);
-    assert!(valuation.total_usd_value > 0);
-    assert_eq!(valuation.assets.len(), 2);
+    // Every other asset would be scaled to nothing.
+    assert_eq!(
+        client.try_deposit_with_target(&pid, &asset3, &1000, &ALLOCATION_DENOMINATOR),
+        Err(Ok(Error::InvalidAllocation))
+    );
+    assert_eq!(client.get_target_allocations(&pid), targets);
+    assert_eq!(client.get_balances(&pid).get(asset3), Some(1000));
 }
 
 #[test]
-fn test_get_portfolio_value_usd_drift() {
+fn test_preview_liquidation_sells_every_other_asset_in_full() {
     let env = Env::default();
     env.mock_all_auths();
-
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
@@ -2184,139 +8112,258 @@ fn test_get_portfolio_value_usd_drift() {
     let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let usdc = create_token(&env);
     let mut allocations = Map::new(&env);
-    let asset1 = Address::generate(&env);
-    let asset2 = Address::generate(&env);
     allocations.set(asset1.clone(), 5000);
     allocations.set(asset2.clone(), 5000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-
-    // Deposit unequal amounts to create drift
-    client.deposit(&pid, &asset1, &200, &String::from_str(&env, ""));
-    client.deposit(&pid, &asset2, &100, &String::from_str(&env, ""));
-
-    let valuation = client.get_portfolio_value_usd(&pid);
-    assert!(valuation.total_usd_value > 0);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &50_000_000, &String::from_str(&env, ""));
+    // A liquidation sells through the owner's rebalance floor.
+    client.set_min_balance(&pid, &asset1, &10_000_000);
 
-    // At least one asset should have non-zero drift
-    let mut has_drift = false;
-    for i in 0..valuation.assets.len() {
-        let av = valuation.assets.get(i).unwrap();
-        if av.drift != 0 {
-            has_drift = true;
-        }
-    }
-    assert!(has_drift);
+    let before = client.get_portfolio(&pid);
+    let trades = client.preview_liquidation(&pid, &usdc);
+    assert_eq!(trades.len(), 3);
+    assert_eq!(trades.get(asset1), Some(-100_000_000));
+    assert_eq!(trades.get(asset2), Some(-50_000_000));
+    // Every asset shares the mock's price, so the proceeds buy one for one.
+    assert_eq!(trades.get(usdc), Some(150_000_000));
+    assert_eq!(client.get_portfolio(&pid), before);
 }
 
 #[test]
-fn test_get_portfolio_value_usd_not_found() {
+fn test_unfunded_target_sized_from_zero_and_flagged() {
     let env = Env::default();
     env.mock_all_auths();
-
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
+    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let result = client.try_get_portfolio_value_usd(&999);
-    assert_eq!(result, Err(Ok(Error::PortfolioNotFound)));
-}
+    let asset1 = create_token(&env);
+    let asset2 = create_token(&env);
+    let never_funded = create_token(&env);
+    let mut allocations = Map::new(&env);
+    allocations.set(asset1.clone(), 4000);
+    allocations.set(asset2.clone(), 4000);
+    allocations.set(never_funded.clone(), 2000);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    client.deposit(&pid, &asset1, &100_000_000, &String::from_str(&env, ""));
+    client.deposit(&pid, &asset2, &100_000_000, &String::from_str(&env, ""));
 
-// ── Issue #859: fractional allocation (basis points) ────────────────────
+    let unfunded = client.get_unfunded_targets(&pid);
+    assert_eq!(unfunded, Vec::from_array(&env, [never_funded.clone()]));
+
+    // The missing entry counts as a zero balance, so all of its target is bought.
+    let trades = client.preview_rebalance_trades(&pid);
+    assert_eq!(trades.get(never_funded.clone()), Some(40_000_000));
+    assert_eq!(trades.get(asset1), Some(-20_000_000));
+    assert_eq!(trades.get(asset2), Some(-20_000_000));
+
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15_000;
+    });
+    client.execute_rebalance(&pid, &Map::new(&env));
+    let topics: Vec<soroban_sdk::Val> = (
+        symbol_short!("portfolio"),
+        Symbol::new(&env, "unfunded_targets"),
+        EVENT_TOPIC_VERSION,
+    )
+        .into_val(&env);
+    let (_, _, data) = env
+        .events()
+        .all()
+        .iter()
+        .filter(|(emitter, event_topics, _)| *emitter == contract_id && *event_topics == topics)
+        .last()
+        .expect("unfunded targets event");
+    let payload: (u64, Vec<Address>) = data.into_val(&env);
+    assert_eq!(payload, (pid, unfunded));
+    assert!(client.get_unfunded_targets(&pid).is_empty());
+}
 
 #[test]
-fn test_fractional_allocation_three_way_equal() {
+fn test_portfolio_fee_paid_to_collector_in_chosen_asset() {
     let env = Env::default();
     env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
     let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
+    let collector = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
 
-    let mut allocations = Map::new(&env);
-    let a1 = Address::generate(&env);
-    let a2 = Address::generate(&env);
-    let a3 = Address::generate(&env);
-    // 33.33% + 33.33% + 33.34% = 100.00%
-    allocations.set(a1, 3333);
-    allocations.set(a2, 3333);
-    allocations.set(a3, 3334);
+    let (charged, charged1, charged2) = create_drifted_token_portfolio(&env, &client, &user);
+    let (free, free1, free2) = create_drifted_token_portfolio(&env, &client, &user);
+    assert_eq!(
+        client.try_set_portfolio_fee(
+            &charged,
+            &(MAX_PORTFOLIO_FEE_BPS + 1),
+            &Some(collector.clone()),
+            &None
+        ),
+        Err(Ok(Error::InvalidFeeBps))
+    );
+    assert_eq!(
+        client.try_set_portfolio_fee(&charged, &100, &None, &None),
+        Err(Ok(Error::InvalidFeeBps))
+    );
+    // The smaller holding, so the largest-holding default would pick otherwise.
+    client.set_portfolio_fee(
+        &charged,
+        &100,
+        &Some(collector.clone()),
+        &Some(charged2.clone()),
+    );
+    client.set_portfolio_fee(&free, &0, &Some(collector.clone()), &None);
 
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
-    assert!(pid > 0);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15_000;
+    });
+    client.execute_rebalance(&charged, &Map::new(&env));
+    client.execute_rebalance(&free, &Map::new(&env));
+
+    // 1% of a 30_000_000_000 portfolio, paid in asset2.
+    assert_eq!(token_balance(&env, &charged2, &collector), 3_000_000);
+    assert_eq!(token_balance(&env, &charged1, &collector), 0);
+    assert_eq!(client.get_portfolio(&charged).total_value, 29_700_000_000);
+
+    assert_eq!(token_balance(&env, &free1, &collector), 0);
+    assert_eq!(token_balance(&env, &free2, &collector), 0);
+    assert_eq!(client.get_portfolio(&free).total_value, 30_000_000_000);
 }
 
 #[test]
-fn test_get_config_view_success() {
+fn test_cost_basis_is_deposit_weighted_average_price() {
     let env = Env::default();
     env.mock_all_auths();
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_settable_price::ReflectorWithSettablePrice,
+    );
+    let reflector =
+        reflector_with_settable_price::ReflectorWithSettablePriceClient::new(&env, &reflector_id);
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
-
     client.initialize(&admin, &reflector_id);
 
     let mut allocations = Map::new(&env);
-    let asset = Address::generate(&env);
+    let asset = create_token(&env);
     allocations.set(asset.clone(), 10000);
-    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 5, 50);
+    let pid = create_portfolio_with_defaults(&env, &client, &user, &allocations, 500, 50);
+    assert_eq!(client.get_cost_basis(&pid, &asset), None);
 
-    let config_view = client.get_config_view(&pid);
+    reflector.set_price(&100_00000000000000i128);
+    client.deposit(&pid, &asset, &100, &String::from_str(&env, ""));
+    reflector.set_price(&200_00000000000000i128);
+    client.deposit(&pid, &asset, &300, &String::from_str(&env, ""));
 
-    assert_eq!(config_view.admin, admin);
-    assert_eq!(config_view.reflector_address, reflector_id);
-    assert_eq!(config_view.emergency_stop, false);
+    let basis = client.get_cost_basis(&pid, &asset).unwrap();
+    assert_eq!(basis.quantity, 400);
+    assert_eq!(basis.average_price, 175_00000000000000i128);
 
-    let portfolio = match config_view.portfolio {
-        PortfolioOption::Some(p) => p,
-        PortfolioOption::None => panic!("Expected PortfolioOption::Some"),
-    };
-    assert_eq!(portfolio.user, user);
-    assert_eq!(portfolio.rebalance_threshold, 5);
-    assert_eq!(portfolio.slippage_tolerance, 50);
+    client.withdraw(&pid, &asset, &50);
+    assert_eq!(client.get_cost_basis(&pid, &asset), Some(basis));
 }
 
 #[test]
-fn test_get_config_view_no_portfolio() {
+fn test_execute_rebalance_rejects_price_outside_sane_bounds() {
     let env = Env::default();
     env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_settable_price::ReflectorWithSettablePrice,
+    );
+    let reflector =
+        reflector_with_settable_price::ReflectorWithSettablePriceClient::new(&env, &reflector_id);
     let admin = Address::generate(&env);
-
+    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15_000;
+    });
 
-    let config_view = client.get_config_view(&999);
+    assert_eq!(
+        client.get_price_bounds(),
+        (DEFAULT_MIN_SANE_PRICE, DEFAULT_MAX_SANE_PRICE)
+    );
+    reflector.set_price(&0);
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::StaleData))
+    );
 
-    assert_eq!(config_view.admin, admin);
-    assert_eq!(config_view.reflector_address, reflector_id);
-    assert_eq!(config_view.emergency_stop, false);
-    assert_eq!(config_view.portfolio, PortfolioOption::None);
+    assert_eq!(
+        client.try_set_price_bounds(&0, &1),
+        Err(Ok(Error::InvalidPriceBounds))
+    );
+    assert_eq!(
+        client.try_set_price_bounds(&2, &1),
+        Err(Ok(Error::InvalidPriceBounds))
+    );
+    client.set_price_bounds(&1, &(1_000 * 10i128.pow(14)));
+    reflector.set_price(&(1_000_000 * 10i128.pow(14)));
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::StaleData))
+    );
+
+    reflector.set_price(&100_00000000000000i128);
+    assert!(client.try_execute_rebalance(&pid, &Map::new(&env)).is_ok());
 }
 
 #[test]
-fn test_get_config_view_emergency_stop() {
+fn test_price_bounds_apply_to_twap_and_valuation() {
     let env = Env::default();
     env.mock_all_auths();
+    env.ledger().with_mut(|li| {
+        li.timestamp = 10_000;
+    });
     let contract_id = env.register_contract(None, PortfolioRebalancer);
     let client = PortfolioRebalancerClient::new(&env, &contract_id);
-    let reflector_id = env.register_contract(None, reflector_contract::MockReflector);
+    let reflector_id = env.register_contract(
+        None,
+        reflector_with_divergent_twap::ReflectorWithDivergentTwap,
+    );
     let admin = Address::generate(&env);
-
+    let user = Address::generate(&env);
     client.initialize(&admin, &reflector_id);
-    client.set_emergency_stop(&true);
+    let (pid, _, _) = create_drifted_token_portfolio(&env, &client, &user);
+    env.ledger().with_mut(|li| {
+        li.timestamp = 15_000;
+    });
 
-    let config_view = client.get_config_view(&1);
+    // Spot is 100 and the TWAP 50; only the spot is within the bounds.
+    client.set_price_bounds(&(60 * 10i128.pow(14)), &(1_000 * 10i128.pow(14)));
+    assert_eq!(client.get_portfolio_value(&pid), 30_000_000_000);
 
-    assert_eq!(config_view.admin, admin);
-    assert_eq!(config_view.reflector_address, reflector_id);
-    assert_eq!(config_view.emergency_stop, true);
-    assert_eq!(config_view.portfolio, PortfolioOption::None);
+    client.set_twap_records(&pid, &5);
+    assert_eq!(
+        client.try_get_portfolio_value(&pid),
+        Err(Ok(Error::StaleData))
+    );
+    assert_eq!(
+        client.try_execute_rebalance(&pid, &Map::new(&env)),
+        Err(Ok(Error::StaleData))
+    );
 }
@@ -1,17 +1,26 @@
-use soroban_sdk::{contracterror, contracttype, Address, BytesN, Map, String, Vec};
+use soroban_sdk::{
+    contracterror, contracttype, symbol_short, Address, BytesN, Map, String, Symbol, Vec,
+};
 
 pub const MIN_TRADE_AMOUNT_STROOPS: i128 = 1_000_000;
 pub const ALLOCATION_DENOMINATOR: u32 = 10_000;
 pub const REFLECTOR_PRICE_DECIMALS: u32 = 14;
 pub const DEFAULT_ASSET_DECIMALS: u32 = 7;
+/// Common precision portfolio values are expressed in; balances of tokens
+/// with other decimals are rescaled to it before they are summed.
+pub const VALUE_DECIMALS: u32 = 7;
 pub const MAX_ASSET_DECIMALS: u32 = 18;
 pub const SLIPPAGE_POLICY_VERSION_V1: u32 = 1;
 pub const CURRENT_SLIPPAGE_POLICY_VERSION: u32 = SLIPPAGE_POLICY_VERSION_V1;
- #391-Introduce-contract-version-read-method-for-safer-client-compatibility-checks-FIX
 /// Contract version representing the overall deployed logic version.
+/// Bump by hand in every release that ships new WASM, so clients can confirm
+/// an `upgrade` landed the logic they expect.
 pub const CONTRACT_VERSION: u32 = 1;
 /// Contract event schema version matching backend expected schema version.
 pub const CONTRACT_EVENT_SCHEMA_VERSION: u32 = 1;
+/// Last topic of every event, so indexers can branch on the payload shape.
+/// Moves in step with `CONTRACT_EVENT_SCHEMA_VERSION`.
+pub const EVENT_TOPIC_VERSION: Symbol = symbol_short!("v1");
 /// Maximum number of assets allowed in a single portfolio (#296).
 ///
 /// Soroban persistent storage entries are bounded by ledger entry size limits.
@@ -20,19 +29,59 @@ pub const CONTRACT_EVENT_SCHEMA_VERSION: u32 = 1;
 /// 10 assets is the tested practical maximum that keeps all operations within
 /// Soroban CPU and memory budgets.
 ///
-/// Attempting to create a portfolio with more assets returns [`Error::TooManyAssets`].
-
+/// Creating a portfolio or updating its target allocations with more assets
+/// returns [`Error::TooManyAssets`].
 pub const MAX_PORTFOLIO_ASSETS: u32 = 10;
+/// Default floor on allocation entries; `1` leaves single-asset portfolios allowed.
+pub const DEFAULT_MIN_PORTFOLIO_ASSETS: u32 = 1;
+/// Most portfolios a single `create_portfolios` call may create.
+pub const MAX_BATCH_PORTFOLIOS: u32 = 10;
+/// Most ids a single `get_all_portfolio_ids` page returns.
+pub const MAX_PORTFOLIO_PAGE_SIZE: u32 = 50;
 pub const MAX_PORTFOLIO_STORAGE_BYTES: u32 = 3_072;
 pub const REBALANCE_COOLDOWN_SECONDS: u64 = 3600;
+pub const MAX_REBALANCE_COOLDOWN_SECONDS: u64 = 604_800;
+/// Largest single-asset drift, in basis points, that `execute_rebalance` will
+/// trade through before refusing with `ExcessiveDrift`.
+pub const DEFAULT_MAX_DRIFT_BPS: u32 = 5_000;
+/// Portfolio value floor for `execute_rebalance`; `0` rebalances any size.
+pub const DEFAULT_MIN_REBALANCE_VALUE: i128 = 0;
+/// Default sane range for a raw Reflector price: any positive price passes.
+pub const DEFAULT_MIN_SANE_PRICE: i128 = 1;
+pub const DEFAULT_MAX_SANE_PRICE: i128 = i128::MAX;
 pub const PRICE_MAX_AGE_SECONDS: u64 = 3600;
 pub const MAX_TIMESTAMP_DRIFT_SECONDS: u64 = 7200;
+/// Ledgers close roughly every 5 seconds, so 17_280 ledgers is about a day.
+/// Portfolio entries touched with less than ~30 days of TTL left are
+/// extended to ~90 days.
+pub const PORTFOLIO_TTL_THRESHOLD_LEDGERS: u32 = 17_280 * 30;
+pub const PORTFOLIO_TTL_EXTEND_LEDGERS: u32 = 17_280 * 90;
 
-pub const MIN_REBALANCE_THRESHOLD: u32 = 1;
-pub const MAX_REBALANCE_THRESHOLD: u32 = 50;
+/// Rebalance drift thresholds are expressed in basis points (0.1%..=50%).
+pub const MIN_REBALANCE_THRESHOLD: u32 = 10;
+pub const MAX_REBALANCE_THRESHOLD: u32 = 5_000;
 pub const MIN_SLIPPAGE_TOLERANCE_BPS: u32 = 10;
 pub const MAX_SLIPPAGE_TOLERANCE_BPS: u32 = 500;
+/// Slippage applied to portfolios made by `create_portfolios`.
+pub const DEFAULT_SLIPPAGE_TOLERANCE_BPS: u32 = 50;
 pub const MAX_FEE_BPS: u32 = 50;
+/// Upper bound for the contract-wide treasury fee charged on each rebalance.
+pub const MAX_TREASURY_FEE_BPS: u32 = 100;
+/// Upper bound for a portfolio's own rebalance fee, set by its owner.
+pub const MAX_PORTFOLIO_FEE_BPS: u32 = 100;
+pub const MAX_TWAP_RECORDS: u32 = 20;
+/// Share of each drift closed per rebalance; the full ratio trades all the way to target.
+pub const FULL_REBALANCE_RATIO_BPS: u32 = 10_000;
+/// Number of most recent rebalances kept per portfolio; older records are evicted first.
+pub const MAX_REBALANCE_HISTORY: u32 = 20;
+/// TWAP window used for the divergence check when a portfolio values at spot.
+pub const DIVERGENCE_TWAP_RECORDS: u32 = 5;
+/// `DataKey::NotifyPrefs` bits selecting which `("notify", ..)` events a
+/// portfolio emits for off-chain alerting.
+pub const NOTIFY_DRIFT_EXCEEDED: u32 = 1 << 0;
+pub const NOTIFY_REBALANCE_EXECUTED: u32 = 1 << 1;
+pub const NOTIFY_STALE_DATA: u32 = 1 << 2;
+pub const NOTIFY_ALL: u32 = NOTIFY_DRIFT_EXCEEDED | NOTIFY_REBALANCE_EXECUTED | NOTIFY_STALE_DATA;
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -61,6 +110,50 @@ pub struct Portfolio {
     pub total_value: i128,
     pub is_active: bool,
     pub pause_reason: PauseReason,
+    /// Number of Reflector TWAP records used for valuation; `0` uses spot prices.
+    pub twap_records: u32,
+    /// Per-asset dust threshold for rebalance trades, in the asset's smallest
+    /// unit. Assets without an entry use `MIN_TRADE_AMOUNT_STROOPS`.
+    pub min_trade_amounts: Map<Address, i128>,
+    /// Fraction of each asset's drift traded per rebalance, in basis points.
+    pub rebalance_ratio_bps: u32,
+    /// Oldest Reflector price, in seconds, this portfolio's valuations accept.
+    pub max_price_age: u64,
+    /// Numeraire for valuation; prices are converted from the oracle's base
+    /// through this asset's own price unless it is the base.
+    pub quote_asset: crate::reflector::Asset,
+    /// Whether the tokens sit in the owner's wallet or in this contract.
+    pub custody_mode: CustodyMode,
+    /// Assets whose own drift is at or below this many basis points are not
+    /// traded, even when another asset triggers a rebalance. `0` disables it.
+    pub no_trade_band_bps: u32,
+    /// Largest `|spot - twap| / twap`, in basis points, at which an asset's
+    /// trade is sized in full; wider gaps scale it down. `0` disables it.
+    pub max_twap_divergence_bps: u32,
+    /// How target balances are rounded when trades are sized.
+    pub rounding_mode: RoundingMode,
+    /// Most value, in quote units at `VALUE_DECIMALS`, traded in any one
+    /// asset per rebalance. `0` leaves trades uncapped.
+    pub max_trade_notional: i128,
+    /// Least time, in seconds, between two rebalances of this portfolio,
+    /// enforced alongside the contract-wide cooldown. `0` adds no limit.
+    pub min_interval_seconds: u64,
+    /// Seconds after creation before the first rebalance may run, so a
+    /// portfolio can finish funding. Cleared by the first rebalance.
+    pub initial_grace_seconds: u64,
+    /// Drift, in bps, at which trades are sized in full. Between
+    /// `rebalance_threshold` and this cap they scale linearly; `0` always
+    /// trades in full.
+    pub drift_scaling_cap_bps: u32,
+    /// Per-asset balance, in the asset's smallest unit, that rebalancing sells
+    /// never go below. Assets without an entry may be sold to zero.
+    pub min_balances: Map<Address, i128>,
+    /// Owner-set fee, in bps of portfolio value, charged on each rebalance
+    /// and paid to `fee_collector`. `0` charges nothing.
+    pub fee_bps: u32,
+    pub fee_collector: Option<Address>,
+    /// Asset the fee is paid in; `None` takes it from the largest holding.
+    pub fee_asset: Option<Address>,
 }
 
 #[contracttype]
@@ -74,6 +167,43 @@ pub enum PauseReason {
     CooldownActive = 4,
 }
 
+/// Why `execute_rebalance` would or would not trade right now, as reported by
+/// `rebalance_status`. Checks run in declaration order from `Paused` upwards.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum RebalanceStatus {
+    Needed = 0,
+    WithinThreshold = 1,
+    Cooldown = 2,
+    Stale = 3,
+    Paused = 4,
+}
+
+/// Who holds a portfolio's tokens. Under `SelfCustody` the balances are
+/// bookkeeping over the owner's wallet and the owner signs any transfer;
+/// under `ContractCustody` deposits move into this contract and
+/// withdrawals and trades move out of it.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum CustodyMode {
+    SelfCustody = 0,
+    ContractCustody = 1,
+}
+
+/// Rounding applied when a target value is converted to a token balance.
+/// `Truncate` drops the remainder, `HalfUp` rounds halves away from zero and
+/// `Nearest` rounds halves to the even neighbour, so ties carry no bias.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum RoundingMode {
+    Truncate = 0,
+    HalfUp = 1,
+    Nearest = 2,
+}
+
 #[contracttype]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u32)]
@@ -104,6 +234,83 @@ pub struct RebalancePreview {
     pub total_value: i128,
 }
 
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RebalanceResult {
+    /// Balance deltas applied per asset, net of any `FeeConfig` fee.
+    pub trades: Map<Address, i128>,
+    /// Portfolio value after the trades settled.
+    pub total_value: i128,
+    /// Assets whose trade was scaled down, or dropped, because spot diverged
+    /// from the TWAP by more than `max_twap_divergence_bps`.
+    pub downsized_assets: Vec<Address>,
+    /// Swap legs that settle the trades, sells matched to buys by value and
+    /// routed through any `DataKey::RouteHint` intermediate.
+    pub hops: Vec<TradeHop>,
+}
+
+/// One leg of a rebalance: `value` (in the portfolio's quote units) moved
+/// out of `from` and into `to`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TradeHop {
+    pub from: Address,
+    pub to: Address,
+    pub value: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RebalanceRecord {
+    /// Per-portfolio rebalance counter; keys the matching price snapshot.
+    pub seq: u32,
+    pub timestamp: u64,
+    pub total_value: i128,
+    /// Trades that settled.
+    pub trade_count: u32,
+    /// Assets whose token transfer failed; their balances were left as-is.
+    pub failed_assets: Vec<Address>,
+}
+
+/// Lifetime totals across every successful rebalance of a portfolio.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PortfolioStats {
+    pub rebalance_count: u32,
+    /// Sum of each settled trade's value, in the same units as `total_value`.
+    pub total_value_traded: i128,
+    /// Sum of the largest asset drift, in basis points, each rebalance corrected.
+    pub total_drift_corrected_bps: u64,
+    /// `total_drift_corrected_bps / rebalance_count`.
+    pub average_drift_corrected_bps: u32,
+}
+
+/// Rough entry price of one asset's deposits into a portfolio, for
+/// reporting. Rebalance trades and withdrawals do not move it.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CostBasis {
+    /// Total deposited, in the asset's smallest unit, that the average covers.
+    pub quantity: i128,
+    /// Deposit-weighted average Reflector price, in the portfolio's
+    /// `quote_asset` at the oracle's decimals.
+    pub average_price: i128,
+}
+
+/// Contract-wide totals for dashboards, so they need not walk every
+/// portfolio. Stored as-is under `DataKey::GlobalStats`.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GlobalStats {
+    /// Open portfolios: created or reactivated, and not since closed or
+    /// deactivated.
+    pub portfolio_count: u64,
+    /// Sum of portfolio valuations in the oracle's base asset as of each
+    /// one's last create, deposit, withdraw, rebalance or close, so it lags
+    /// live prices. Portfolios with another `quote_asset` are left out.
+    pub total_value_under_management: i128,
+}
+
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct FeeConfig {
@@ -136,6 +343,31 @@ pub enum DataKey {
     UpgradeAuthority,
     WasmHash,
     LastTimestamp,
+    RebalanceCooldown,
+    PendingAdmin,
+    RebalanceHistory(u64),
+    RebalancePrices(u64, u32),
+    UserPortfolios(Address),
+    FeeBps,
+    Treasury,
+    AssetWhitelist,
+    MaxDriftBps,
+    Keeper(u64),
+    Paused,
+    MinAssets,
+    MinRebalanceValue,
+    PortfolioStats(u64),
+    BaseAssetOverride,
+    SecondaryReflector,
+    GlobalStats,
+    ReflectorDecimals,
+    RouteHint,
+    NotifyPrefs(u64),
+    CostBasis(u64),
+    PriceBounds,
+    /// Present while the portfolio is valued in a quote other than the
+    /// oracle's base, which keeps it out of the global value total.
+    ForeignQuote(u64),
 }
 
 #[contracterror]
@@ -153,8 +385,11 @@ pub enum Error {
     InvalidSlippageTolerance = 9,
     SlippageExceeded = 10,
     TooManyAssets = 11,
-    StaleOraclePrice = 12,
-    InvalidAssetThreshold = 13,
+    // Code 12 was the never-raised `StaleOraclePrice`.
+    InfeasibleTarget = 12,
+    // Code 13 was the never-raised `InvalidAssetThreshold`; contract error
+    // enums are capped at 50 variants, so the slot is reused.
+    DuplicateAsset = 13,
     InvariantViolation = 14,
     InvalidAssetDecimals = 15,
     UnsupportedSlippagePolicyVersion = 16,
@@ -168,12 +403,37 @@ pub enum Error {
     InvalidCooldown = 24,
     AssetNotSupported = 25,
     InvalidAmount = 26,
-    WithdrawFailed = 27,
+    // Code 27 was the never-raised `WithdrawFailed`.
+    InvalidNotifyPrefs = 27,
     InvalidAllocationSum = 28,
+    InvalidTwapRecords = 29,
+    NoPendingAdmin = 30,
+    PortfolioInactive = 31,
+    InvalidFeeBps = 32,
+    AllocationsNotBasisPoints = 33,
+    AssetNotAllowed = 34,
+    InvalidRebalanceRatio = 35,
+    InvalidMaxDrift = 36,
+    NoKeeper = 37,
+    ContractPaused = 38,
+    TooFewAssets = 39,
+    InvalidMinAssets = 40,
+    NotAToken = 41,
+    InvalidMaxPriceAge = 42,
+    InvalidBatchSize = 43,
+    RebalanceRecordNotFound = 44,
+    TradeExecutionFailed = 45,
+    CustodyModeLocked = 46,
+    // Shared by the no-trade band, TWAP divergence and trade notional setters.
+    InvalidTradeLimit = 47,
+    AssetHasBalance = 48,
+    InvalidPriceBounds = 49,
+    ZeroPortfolioValue = 50,
 }
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum PortfolioOption {
     None,
     Some(Portfolio),
@@ -188,6 +448,18 @@ pub struct ConfigView {
     pub portfolio: PortfolioOption,
 }
 
+/// Contract-wide settings returned by `get_config`.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractConfig {
+    pub admin: Address,
+    pub reflector_address: Address,
+    pub paused: bool,
+    pub emergency_stop: bool,
+    /// Treasury fee charged on each rebalance, in basis points.
+    pub fee_bps: u32,
+}
+
 #[repr(u32)]
 pub enum CapabilityFlag {
     PerPortfolioSteward = 1 << 0,
@@ -6,6 +6,7 @@ use portfolio_rebalancer::{
 };
 use soroban_sdk::{
     testutils::{Address as _, Ledger},
+    token::StellarAssetClient,
     Address, Env, Map, String, Vec,
 };
 
@@ -45,6 +46,15 @@ mod mock_reflector {
     }
 }
 
+fn create_token(env: &Env) -> Address {
+    env.register_stellar_asset_contract_v2(Address::generate(env))
+        .address()
+}
+
+fn mint(env: &Env, token: &Address, to: &Address, amount: i128) {
+    StellarAssetClient::new(env, token).mint(to, &amount);
+}
+
 // ── Full rebalance flow ────────────────────────────────────────────────
 
 #[test]
@@ -64,8 +74,8 @@ fn integration_full_rebalance_flow() {
 
     client.initialize(&admin, &reflector_id);
 
-    let asset_a = Address::generate(&env);
-    let asset_b = Address::generate(&env);
+    let asset_a = create_token(&env);
+    let asset_b = create_token(&env);
 
     let mut allocations = Map::new(&env);
     allocations.set(asset_a.clone(), 5000);
@@ -79,12 +89,16 @@ fn integration_full_rebalance_flow() {
         &user,
         &allocations,
         &asset_decimals,
-        &5,
+        &500,
         &50,
         &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
     );
     assert_eq!(pid, 1);
 
+    mint(&env, &asset_a, &contract_id, 200_000_000);
+    mint(&env, &asset_b, &contract_id, 100_000_000);
+    mint(&env, &asset_b, &user, 100_000_000);
     client.deposit(
         &pid,
         &asset_a,
@@ -134,9 +148,9 @@ fn integration_basis_points_fractional_allocations() {
 
     client.initialize(&admin, &reflector_id);
 
-    let a1 = Address::generate(&env);
-    let a2 = Address::generate(&env);
-    let a3 = Address::generate(&env);
+    let a1 = create_token(&env);
+    let a2 = create_token(&env);
+    let a3 = create_token(&env);
 
     let mut allocations = Map::new(&env);
     allocations.set(a1.clone(), 3333);
@@ -152,9 +166,10 @@ fn integration_basis_points_fractional_allocations() {
         &user,
         &allocations,
         &asset_decimals,
-        &5,
+        &500,
         &50,
         &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
     );
     assert!(pid > 0);
 
@@ -182,7 +197,7 @@ fn integration_rebalance_rejects_corrupted_allocations() {
 
     client.initialize(&admin, &reflector_id);
 
-    let asset = Address::generate(&env);
+    let asset = create_token(&env);
     let mut allocations = Map::new(&env);
     allocations.set(asset.clone(), 10000);
 
@@ -193,9 +208,10 @@ fn integration_rebalance_rejects_corrupted_allocations() {
         &user,
         &allocations,
         &asset_decimals,
-        &5,
+        &500,
         &50,
         &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
     );
 
     use portfolio_rebalancer::{DataKey, Portfolio};
@@ -234,8 +250,8 @@ fn integration_portfolio_value_usd_returns_correct_structure() {
 
     client.initialize(&admin, &reflector_id);
 
-    let asset_a = Address::generate(&env);
-    let asset_b = Address::generate(&env);
+    let asset_a = create_token(&env);
+    let asset_b = create_token(&env);
 
     let mut allocations = Map::new(&env);
     allocations.set(asset_a.clone(), 7000);
@@ -249,9 +265,10 @@ fn integration_portfolio_value_usd_returns_correct_structure() {
         &user,
         &allocations,
         &asset_decimals,
-        &5,
+        &500,
         &50,
         &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
     );
 
     client.deposit(&pid, &asset_a, &1_000_000, &String::from_str(&env, ""));
@@ -289,8 +306,8 @@ fn integration_emergency_stop_blocks_rebalance() {
 
     client.initialize(&admin, &reflector_id);
 
-    let asset_a = Address::generate(&env);
-    let asset_b = Address::generate(&env);
+    let asset_a = create_token(&env);
+    let asset_b = create_token(&env);
 
     let mut allocations = Map::new(&env);
     allocations.set(asset_a.clone(), 5000);
@@ -304,11 +321,15 @@ fn integration_emergency_stop_blocks_rebalance() {
         &user,
         &allocations,
         &asset_decimals,
-        &5,
+        &500,
         &50,
         &CURRENT_SLIPPAGE_POLICY_VERSION,
+        &None,
     );
 
+    mint(&env, &asset_a, &contract_id, 200_000_000);
+    mint(&env, &asset_b, &contract_id, 100_000_000);
+    mint(&env, &asset_b, &user, 100_000_000);
     client.deposit(&pid, &asset_a, &200_000_000, &String::from_str(&env, ""));
     client.deposit(&pid, &asset_b, &100_000_000, &String::from_str(&env, ""));
 